@@ -1,4 +1,8 @@
-use clap::{Parser, Subcommand};
+use crate::config::Transport;
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand, ValueEnum};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -11,42 +15,151 @@ pub struct Args {
 pub enum Commands {
     /// Record video from the configured device
     Record {
-        /// Path to the configuration file
-        #[arg(short, long, default_value = "config.toml")]
-        config: String,
+        #[command(flatten)]
+        config: ConfigPath,
     },
-    /// Play back an encrypted video file
+    /// Play back an encrypted video file or a sequence of rotated segments
     Play {
-        /// Path to the configuration file (for decryption keys)
-        #[arg(short, long, default_value = "config.toml")]
-        config: String,
-        /// Path to the encrypted input file
+        #[command(flatten)]
+        config: ConfigPath,
+        /// Encrypted input file, directory of segments or glob (e.g. "rec/cam0_*.ts.enc")
+        #[arg(short, long)]
+        input: String,
+        /// Only play segments from this UTC time on (RFC 3339), starting at it
+        #[arg(long)]
+        from: Option<DateTime<Utc>>,
+        /// Only play segments up to this UTC time (RFC 3339)
+        #[arg(long)]
+        to: Option<DateTime<Utc>>,
+        /// Seconds to seek with the Left/Right arrow keys
+        #[arg(long, default_value_t = 5)]
+        seek_step: u64,
+    },
+    /// Decrypt a recording or segment set into a standard MP4 or Matroska file
+    Export {
+        #[command(flatten)]
+        config: ConfigPath,
+        /// Encrypted input file, directory of segments or glob
         #[arg(short, long)]
         input: String,
+        /// Output file; the format follows its extension unless --format is given
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Container to write
+        #[arg(short, long, value_enum)]
+        format: Option<ExportFormat>,
+        /// Only export segments from this UTC time on (RFC 3339), starting at it
+        #[arg(long, conflicts_with = "start")]
+        from: Option<DateTime<Utc>>,
+        /// Only export up to this UTC time (RFC 3339)
+        #[arg(long, conflicts_with = "end")]
+        to: Option<DateTime<Utc>>,
+        /// Start at this position in the input ([[hh:]mm:]ss)
+        #[arg(long, value_parser = parse_position)]
+        start: Option<Duration>,
+        /// Stop at this position in the input ([[hh:]mm:]ss)
+        #[arg(long, value_parser = parse_position)]
+        end: Option<Duration>,
+    },
+    /// Check recordings for a wrong key, damage, truncation and stream errors
+    Verify {
+        #[command(flatten)]
+        config: ConfigPath,
+        /// Encrypted file, directory or glob to check
+        #[arg(short, long)]
+        input: String,
+    },
+    /// Salvage a recording cut off by a power loss into a new, playable file
+    Recover {
+        #[command(flatten)]
+        config: ConfigPath,
+        /// Damaged recording
+        #[arg(short, long)]
+        input: PathBuf,
+        /// Output file [default: <input>.recovered.ts.enc]
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Re-encrypt recordings under a new key
+    Rekey {
+        /// Encrypted file, directory or glob to re-encrypt in place
+        #[arg(short, long)]
+        input: String,
+        /// Current key (hex, or an env:/file:/... source like `key` in the config)
+        #[arg(long)]
+        old_key: String,
+        /// New key, in the same form
+        #[arg(long)]
+        new_key: String,
+    },
+    /// Create a private key for reviewing recordings and print its recipient
+    Keygen {
+        /// File to write the private key to (must not exist)
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Wrap a key with a key held in a PKCS#11 token and print its pkcs11: source
+    WrapKey {
+        /// Token parameters of the pkcs11: source, without wrapped=
+        /// (e.g. "module=/usr/lib/softhsm/libsofthsm2.so,token=cams,key=kek,pin=env:HSM_PIN")
+        #[arg(long)]
+        pkcs11: String,
+        /// Key to wrap (hex, or an env:/file:/... source like `key` in the config)
+        #[arg(long)]
+        key: String,
     },
     /// Stream encrypted video over the network
     Stream {
-        /// Path to the configuration file
-        #[arg(short, long, default_value = "config.toml")]
-        config: String,
-        /// Destination IP address
-        #[arg(short, long, default_value = "127.0.0.1")]
-        dest: String,
-        /// Destination port
-        #[arg(short, long, default_value = "8088")]
-        port: u16,
+        #[command(flatten)]
+        config: ConfigPath,
+        /// Destination IP address [config: stream_dest, default: 127.0.0.1]
+        #[arg(short, long)]
+        dest: Option<String>,
+        /// Destination port [config: stream_port, default: 8088]
+        #[arg(short, long)]
+        port: Option<u16>,
+        /// Packet encryption used on the wire [config: stream_transport, default: aead]
+        #[arg(short, long, value_enum)]
+        transport: Option<Transport>,
+        /// Write an SDP file for standard SRTP receivers (srtp transport only)
+        #[arg(long)]
+        sdp: Option<String>,
     },
     /// Receive and decrypt video from the network
     Receive {
-        /// Path to the configuration file (for decryption keys)
-        #[arg(short, long, default_value = "config.toml")]
-        config: String,
-        /// Listen IP address
-        #[arg(short, long, default_value = "0.0.0.0")]
-        listen: String,
-        /// Listen port
-        #[arg(short, long, default_value = "8088")]
-        port: u16,
+        #[command(flatten)]
+        config: ConfigPath,
+        /// Listen IP address [config: receive_listen, default: 0.0.0.0]
+        #[arg(short, long)]
+        listen: Option<String>,
+        /// Listen port [config: stream_port, default: 8088]
+        #[arg(short, long)]
+        port: Option<u16>,
+        /// Packet encryption used on the wire [config: stream_transport, default: aead]
+        #[arg(short, long, value_enum)]
+        transport: Option<Transport>,
+    },
+    /// Serve the live camera over RTSP (requires the `rtsp` build feature)
+    Serve {
+        #[command(flatten)]
+        config: ConfigPath,
+        /// Listen IP address [config: rtsp_listen, default: 0.0.0.0]
+        #[arg(short, long)]
+        listen: Option<String>,
+        /// Listen port [config: rtsp_port, default: 8554]
+        #[arg(short, long)]
+        port: Option<u16>,
+        /// Mount point path of the stream
+        #[arg(short, long, default_value = "/live")]
+        mount: String,
+        /// Authentication scheme used when credentials are configured
+        #[arg(short, long, value_enum, default_value_t = RtspAuth::Digest)]
+        auth: RtspAuth,
+    },
+    /// Inspect or check the layered configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
     },
     /// Initialize a new configuration file via an interactive wizard
     Init {
@@ -56,6 +169,65 @@ pub enum Commands {
     },
 }
 
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Print the merged configuration with key sources redacted
+    Show {
+        #[command(flatten)]
+        config: ConfigPath,
+        /// Include the built-in defaults and show where each value came from
+        #[arg(long)]
+        effective: bool,
+    },
+    /// Check the configuration and dry-run its pipelines, reporting every problem
+    Check {
+        #[command(flatten)]
+        config: ConfigPath,
+    },
+}
+
+/// The `--config` option shared by the commands that read a configuration.
+#[derive(clap::Args, Debug, Clone, PartialEq, Eq)]
+pub struct ConfigPath {
+    /// Path to the configuration file; drop-ins in conf.d/ next to it are
+    /// merged on top [default: /etc/eightyeightyeight/config.toml, or
+    /// ./config.toml]
+    #[arg(short, long, env = "EIGHTYEIGHTYEIGHT_CONFIG")]
+    pub config: Option<PathBuf>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Mp4,
+    Mkv,
+}
+
+impl ExportFormat {
+    /// Picks the format from the output file's extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "mp4" | "m4v" | "mov" => Some(Self::Mp4),
+            "mkv" => Some(Self::Mkv),
+            _ => None,
+        }
+    }
+}
+
+fn parse_position(input: &str) -> Result<Duration, String> {
+    crate::controls::parse_position(input)
+        .map(Duration::from)
+        .ok_or_else(|| format!("invalid position {:?}, expected [[hh:]mm:]ss", input))
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RtspAuth {
+    /// HTTP Basic; the password is only base64 encoded on the wire
+    Basic,
+    /// HTTP Digest
+    Digest,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -64,7 +236,9 @@ mod tests {
     fn test_record_command() {
         let args = Args::parse_from(["app", "record", "--config", "my_config.toml"]);
         match args.command {
-            Commands::Record { config } => assert_eq!(config, "my_config.toml"),
+            Commands::Record { config } => {
+                assert_eq!(config.config, Some(PathBuf::from("my_config.toml")))
+            }
             _ => panic!("Expected Record command"),
         }
     }
@@ -89,14 +263,213 @@ mod tests {
             "my_config.toml",
         ]);
         match args.command {
-            Commands::Play { config, input } => {
-                assert_eq!(config, "my_config.toml");
+            Commands::Play {
+                config,
+                input,
+                from,
+                to,
+                seek_step,
+            } => {
+                assert_eq!(config.config, Some(PathBuf::from("my_config.toml")));
                 assert_eq!(input, "video.enc");
+                assert_eq!(from, None);
+                assert_eq!(to, None);
+                assert_eq!(seek_step, 5);
             }
             _ => panic!("Expected Play command"),
         }
     }
 
+    #[test]
+    fn test_play_command_time_range() {
+        let args = Args::parse_from([
+            "app",
+            "play",
+            "--input",
+            "rec/",
+            "--from",
+            "2026-10-18T12:00:00Z",
+            "--to",
+            "2026-10-18T12:30:00Z",
+        ]);
+        match args.command {
+            Commands::Play {
+                input, from, to, ..
+            } => {
+                assert_eq!(input, "rec/");
+                assert_eq!(from.unwrap().to_rfc3339(), "2026-10-18T12:00:00+00:00");
+                assert_eq!(to.unwrap().to_rfc3339(), "2026-10-18T12:30:00+00:00");
+            }
+            _ => panic!("Expected Play command"),
+        }
+    }
+
+    #[test]
+    fn test_export_command() {
+        let args = Args::parse_from([
+            "app", "export", "--input", "rec/", "--output", "clip.mkv", "--start", "1:30", "--end",
+            "2:00",
+        ]);
+        match args.command {
+            Commands::Export {
+                input,
+                output,
+                format,
+                start,
+                end,
+                ..
+            } => {
+                assert_eq!(input, "rec/");
+                assert_eq!(output, PathBuf::from("clip.mkv"));
+                assert_eq!(format, None);
+                assert_eq!(start, Some(Duration::from_secs(90)));
+                assert_eq!(end, Some(Duration::from_secs(120)));
+            }
+            _ => panic!("Expected Export command"),
+        }
+
+        assert!(
+            Args::try_parse_from([
+                "app",
+                "export",
+                "--input",
+                "rec/",
+                "--output",
+                "clip.mp4",
+                "--start",
+                "10",
+                "--from",
+                "2026-10-18T12:00:00Z",
+            ])
+            .is_err()
+        );
+        assert!(
+            Args::try_parse_from(["app", "export", "-i", "a", "-o", "b.mp4", "--end", "x"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_verify_command() {
+        let args = Args::parse_from(["app", "verify", "--input", "/var/rec"]);
+        match args.command {
+            Commands::Verify { config, input } => {
+                assert_eq!(config.config, None);
+                assert_eq!(input, "/var/rec");
+            }
+            _ => panic!("Expected Verify command"),
+        }
+    }
+
+    #[test]
+    fn test_recover_command() {
+        let args = Args::parse_from(["app", "recover", "-i", "cam0.ts.enc"]);
+        match args.command {
+            Commands::Recover {
+                config,
+                input,
+                output,
+            } => {
+                assert_eq!(config.config, None);
+                assert_eq!(input, PathBuf::from("cam0.ts.enc"));
+                assert_eq!(output, None);
+            }
+            _ => panic!("Expected Recover command"),
+        }
+    }
+
+    #[test]
+    fn test_rekey_command() {
+        let args = Args::parse_from([
+            "app",
+            "rekey",
+            "-i",
+            "recordings/",
+            "--old-key",
+            "file:/run/old.key",
+            "--new-key",
+            "env:NEW_KEY",
+        ]);
+        match args.command {
+            Commands::Rekey {
+                input,
+                old_key,
+                new_key,
+            } => {
+                assert_eq!(input, "recordings/");
+                assert_eq!(old_key, "file:/run/old.key");
+                assert_eq!(new_key, "env:NEW_KEY");
+            }
+            _ => panic!("Expected Rekey command"),
+        }
+    }
+
+    #[test]
+    fn test_keygen_command() {
+        let args = Args::parse_from(["eightyeightyeight", "keygen", "-o", "reviewer.key"]);
+        match args.command {
+            Commands::Keygen { output } => assert_eq!(output, PathBuf::from("reviewer.key")),
+            _ => panic!("Expected Keygen command"),
+        }
+    }
+
+    #[test]
+    fn test_wrap_key_command() {
+        let args = Args::parse_from([
+            "eightyeightyeight",
+            "wrap-key",
+            "--pkcs11",
+            "module=m.so,token=cams,key=kek,pin=env:HSM_PIN",
+            "--key",
+            "file:master.key",
+        ]);
+        match args.command {
+            Commands::WrapKey { pkcs11, key } => {
+                assert_eq!(pkcs11, "module=m.so,token=cams,key=kek,pin=env:HSM_PIN");
+                assert_eq!(key, "file:master.key");
+            }
+            _ => panic!("Expected WrapKey command"),
+        }
+    }
+
+    #[test]
+    fn test_config_show_command() {
+        let args = Args::parse_from(["app", "config", "show", "--effective", "-c", "cam0.toml"]);
+        match args.command {
+            Commands::Config {
+                command: ConfigCommand::Show { config, effective },
+            } => {
+                assert_eq!(config.config, Some(PathBuf::from("cam0.toml")));
+                assert!(effective);
+            }
+            _ => panic!("Expected Config Show command"),
+        }
+    }
+
+    #[test]
+    fn test_config_check_command() {
+        let args = Args::parse_from(["app", "config", "check"]);
+        match args.command {
+            Commands::Config {
+                command: ConfigCommand::Check { config },
+            } => assert_eq!(config.config, None),
+            _ => panic!("Expected Config Check command"),
+        }
+    }
+
+    #[test]
+    fn test_export_format_from_path() {
+        assert_eq!(
+            ExportFormat::from_path(Path::new("clip.MP4")),
+            Some(ExportFormat::Mp4)
+        );
+        assert_eq!(
+            ExportFormat::from_path(Path::new("out/clip.mkv")),
+            Some(ExportFormat::Mkv)
+        );
+        assert_eq!(ExportFormat::from_path(Path::new("clip.ts")), None);
+    }
+
     #[test]
     fn test_stream_command() {
         let args = Args::parse_from([
@@ -110,10 +483,18 @@ mod tests {
             "stream_config.toml",
         ]);
         match args.command {
-            Commands::Stream { config, dest, port } => {
-                assert_eq!(config, "stream_config.toml");
-                assert_eq!(dest, "192.168.1.10");
-                assert_eq!(port, 9000);
+            Commands::Stream {
+                config,
+                dest,
+                port,
+                transport,
+                sdp,
+            } => {
+                assert_eq!(config.config, Some(PathBuf::from("stream_config.toml")));
+                assert_eq!(dest.as_deref(), Some("192.168.1.10"));
+                assert_eq!(port, Some(9000));
+                assert_eq!(transport, None);
+                assert_eq!(sdp, None);
             }
             _ => panic!("Expected Stream command"),
         }
@@ -136,12 +517,54 @@ mod tests {
                 config,
                 listen,
                 port,
+                transport,
             } => {
-                assert_eq!(config, "recv_config.toml");
-                assert_eq!(listen, "127.0.0.1");
-                assert_eq!(port, 9001);
+                assert_eq!(config.config, Some(PathBuf::from("recv_config.toml")));
+                assert_eq!(listen.as_deref(), Some("127.0.0.1"));
+                assert_eq!(port, Some(9001));
+                assert_eq!(transport, None);
             }
             _ => panic!("Expected Receive command"),
         }
     }
+
+    #[test]
+    fn test_stream_command_srtp() {
+        let args = Args::parse_from([
+            "app",
+            "stream",
+            "--transport",
+            "srtp",
+            "--sdp",
+            "stream.sdp",
+        ]);
+        match args.command {
+            Commands::Stream { transport, sdp, .. } => {
+                assert_eq!(transport, Some(Transport::Srtp));
+                assert_eq!(sdp.as_deref(), Some("stream.sdp"));
+            }
+            _ => panic!("Expected Stream command"),
+        }
+    }
+
+    #[test]
+    fn test_serve_command() {
+        let args = Args::parse_from(["app", "serve", "--port", "9554", "--auth", "basic"]);
+        match args.command {
+            Commands::Serve {
+                config,
+                listen,
+                port,
+                mount,
+                auth,
+            } => {
+                assert_eq!(config.config, None);
+                assert_eq!(listen, None);
+                assert_eq!(port, Some(9554));
+                assert_eq!(mount, "/live");
+                assert_eq!(auth, RtspAuth::Basic);
+            }
+            _ => panic!("Expected Serve command"),
+        }
+    }
 }
@@ -1,14 +1,172 @@
+use crate::secrets::Secret;
+use anyhow::Context;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fmt::Write;
+use std::num::{NonZeroU32, NonZeroU64};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Port `record` streams to when `stream_dest` is set without `stream_port`.
+pub const DEFAULT_STREAM_PORT: u16 = 8088;
+/// Destination of `stream` when `stream_dest` is not set.
+pub const DEFAULT_STREAM_DEST: &str = "127.0.0.1";
+/// Address `receive` and `serve` listen on by default.
+pub const DEFAULT_LISTEN: &str = "0.0.0.0";
+/// Port of the RTSP server started by `serve`.
+pub const DEFAULT_RTSP_PORT: u16 = 8554;
+
+/// Configuration file read when `--config` is not given. Drop-ins in
+/// `conf.d/*.toml` next to the file are merged on top of it.
+pub const SYSTEM_CONFIG: &str = "/etc/eightyeightyeight/config.toml";
+/// Fallback for running from a checkout, where `init` writes its config.
+const LOCAL_CONFIG: &str = "config.toml";
+/// Environment variables overriding single fields, e.g.
+/// `EIGHTYEIGHTYEIGHT_STREAM_PORT=9000`.
+pub const ENV_PREFIX: &str = "EIGHTYEIGHTYEIGHT_";
+/// Names `--config` through the environment rather than setting a field.
+pub const CONFIG_ENV: &str = "EIGHTYEIGHTYEIGHT_CONFIG";
+
+/// Fields holding key sources; `config show` redacts them.
+const SECRET_FIELDS: &[&str] = &["key", "keyring", "identity", "rtsp_password"];
+
+/// How `stream` and `receive` protect the RTP packets.
+#[derive(clap::ValueEnum, Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    /// AES-GCM sealed RTP packets (eightyeightyeight on both ends)
+    Aead,
+    /// Standard SRTP (AES_CM_128_HMAC_SHA1_80) for third-party receivers
+    Srtp,
+}
+
+/// The video source to capture from.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Device {
+    /// `auto`: whatever `autovideosrc` picks.
+    Auto,
+    /// `videotestsrc`: a test pattern, for trying things out without a camera.
+    TestSource,
+    /// A V4L2 device node such as `/dev/video0`. Relative paths are
+    /// accepted, with a warning, and opened from the working directory.
+    V4l2(PathBuf),
+}
+
+impl std::str::FromStr for Device {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "videotestsrc" => Ok(Self::TestSource),
+            path if !path.is_empty() => Ok(Self::V4l2(PathBuf::from(path))),
+            _ => Err(format!(
+                "invalid device {:?}, expected \"auto\", \"videotestsrc\" or a device path such as \"/dev/video0\"",
+                s
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Device {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Auto => f.write_str("auto"),
+            Self::TestSource => f.write_str("videotestsrc"),
+            Self::V4l2(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+impl TryFrom<String> for Device {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Device> for String {
+    fn from(device: Device) -> Self {
+        device.to_string()
+    }
+}
+
+/// A frame rate as a fraction of frames per second, written `30/1` or
+/// `30000/1001` like GStreamer caps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Fraction {
+    pub numer: NonZeroU32,
+    pub denom: NonZeroU32,
+}
+
+impl std::str::FromStr for Fraction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid frame rate {:?}, expected a fraction of non-zero numbers such as \"30/1\"",
+                s
+            )
+        };
+        let (numer, denom) = s.split_once('/').ok_or_else(invalid)?;
+        Ok(Self {
+            numer: numer.trim().parse().map_err(|_| invalid())?,
+            denom: denom.trim().parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl std::fmt::Display for Fraction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.numer, self.denom)
+    }
+}
+
+impl TryFrom<String> for Fraction {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Fraction> for String {
+    fn from(fraction: Fraction) -> Self {
+        fraction.to_string()
+    }
+}
 
 #[derive(Debug, Deserialize, Serialize, PartialEq)]
 pub struct Config {
-    pub device: String,
-    pub width: u32,
-    pub height: u32,
-    pub framerate: String,
-    pub bitrate: u32,
-    pub key: String,
+    pub device: Device,
+    pub width: NonZeroU32,
+    pub height: NonZeroU32,
+    pub framerate: Fraction,
+    /// x264 bitrate in kbit/s.
+    pub bitrate: NonZeroU32,
+    /// Master key. May be left out when `recipient` or `identity` is set.
+    /// Like the other secrets it is never serialized, since after loading
+    /// it holds the resolved key rather than its source.
+    #[serde(default, skip_serializing)]
+    pub key: Secret,
+    /// Further keys for reading older recordings, in the same forms as
+    /// `key`. Readers pick the key matching a recording's key ID; new
+    /// recordings always use `key`.
+    #[serde(default, skip_serializing)]
+    pub keyring: Vec<Secret>,
+    /// X25519 public key (hex). When set, new recordings are sealed to it
+    /// instead of `key`, and only its `identity` can read them back.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recipient: Option<String>,
+    /// X25519 private key for reading recordings sealed to a recipient, in
+    /// the same forms as `key`.
+    #[serde(default, skip_serializing)]
+    pub identity: Option<Secret>,
     pub output_path: PathBuf,
     #[serde(default)]
     pub cv_enabled: bool,
@@ -18,20 +176,182 @@ pub struct Config {
     pub max_files: Option<u32>,
     #[serde(default)]
     pub max_file_size_mb: Option<u64>,
+    /// Rotate every N seconds, aligned to multiples of N since the epoch
+    /// (60 splits at the top of each minute). Segments are named by UTC
+    /// start time instead of a counter.
+    #[serde(default)]
+    pub segment_duration_secs: Option<NonZeroU64>,
+    /// Bind address for the Prometheus scrape endpoint, e.g. "0.0.0.0:9091".
+    #[serde(default)]
+    pub metrics_listen: Option<String>,
+    /// Credentials required by `serve`. The password accepts the same
+    /// sources as `key` (literal:, env:, file:, ...).
+    #[serde(default)]
+    pub rtsp_username: Option<String>,
+    #[serde(default, skip_serializing)]
+    pub rtsp_password: Option<Secret>,
+    /// When set, `record` also streams the same encoded video to this host
+    /// over RTP/UDP, from a single capture.
+    #[serde(default)]
+    pub stream_dest: Option<String>,
+    #[serde(default)]
+    pub stream_port: Option<u16>,
+    #[serde(default)]
+    pub stream_transport: Option<Transport>,
+    /// Address `receive` listens on for the stream.
+    #[serde(default)]
+    pub receive_listen: Option<String>,
+    /// Address and port of the RTSP server started by `serve`.
+    #[serde(default)]
+    pub rtsp_listen: Option<String>,
+    #[serde(default)]
+    pub rtsp_port: Option<u16>,
+}
+
+/// The built-in defaults, the bottom configuration layer.
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            device: Device::V4l2(PathBuf::from("/dev/video0")),
+            width: NonZeroU32::new(640).unwrap(),
+            height: NonZeroU32::new(480).unwrap(),
+            framerate: "30/1".parse().unwrap(),
+            bitrate: NonZeroU32::new(1000).unwrap(),
+            key: Secret::default(),
+            keyring: Vec::new(),
+            recipient: None,
+            identity: None,
+            output_path: PathBuf::from("output.ts.enc"),
+            cv_enabled: false,
+            min_disk_space_mb: None,
+            max_files: None,
+            max_file_size_mb: None,
+            segment_duration_secs: None,
+            metrics_listen: None,
+            rtsp_username: None,
+            rtsp_password: None,
+            stream_dest: None,
+            stream_port: Some(DEFAULT_STREAM_PORT),
+            stream_transport: Some(Transport::Aead),
+            receive_listen: Some(DEFAULT_LISTEN.to_string()),
+            rtsp_listen: Some(DEFAULT_LISTEN.to_string()),
+            rtsp_port: Some(DEFAULT_RTSP_PORT),
+        }
+    }
 }
 
 impl Config {
+    /// Loads the built-in defaults overridden by a single file.
+    #[cfg(test)]
     pub fn load(path: &str) -> anyhow::Result<Self> {
-        let content = std::fs::read_to_string(path)?;
-        let mut config: Config = toml::from_str(&content)?;
+        Layers::new().file(Path::new(path))?.build()
+    }
+
+    /// Resolves the key sources and checks the settings that depend on each
+    /// other. Returns every problem found, each with the field it concerns.
+    fn resolve(&mut self) -> Vec<(&'static str, String)> {
+        use crate::secrets::SecretResolver;
 
-        // Resolve the key immediately
-        let resolver = crate::secrets::get_resolver(&config.key);
-        config.key = resolver.resolve()?;
+        let mut problems = Vec::new();
+        let mut check = |field, result: anyhow::Result<()>| {
+            if let Err(e) = result {
+                problems.push((field, format!("{:#}", e)));
+            }
+        };
 
-        Self::validate_key(&config.key)?;
+        if self.key.is_empty() {
+            if self.recipient.is_none() && self.identity.is_none() {
+                check(
+                    "key",
+                    Err(anyhow::anyhow!(
+                        "key is required unless recipient or identity is set"
+                    )),
+                );
+            }
+        } else {
+            // Resolve the key immediately
+            check(
+                "key",
+                crate::secrets::get_resolver(self.key.expose())
+                    .resolve()
+                    .and_then(|key| {
+                        Self::validate_key(key.expose())?;
+                        self.key = key;
+                        Ok(())
+                    }),
+            );
+        }
+        if let Device::V4l2(path) = &self.device
+            && path.is_relative()
+        {
+            let hint = path
+                .file_name()
+                .map(|name| format!("; did you mean /dev/{}?", name.to_string_lossy()))
+                .unwrap_or_default();
+            warn!(
+                "device {} is a relative path and is opened from the working directory{}",
+                path.display(),
+                hint
+            );
+        }
+        if let Some(recipient) = &self.recipient {
+            check(
+                "recipient",
+                crate::container::parse_x25519_key(recipient)
+                    .map(drop)
+                    .map_err(|e| anyhow::anyhow!("Invalid recipient: {}", e)),
+            );
+        }
+        if let Some(identity) = &mut self.identity {
+            check(
+                "identity",
+                crate::secrets::PrivateKeyResolver::new(identity.expose())
+                    .resolve()
+                    .map(|resolved| *identity = resolved),
+            );
+        }
+        for entry in &mut self.keyring {
+            check(
+                "keyring",
+                crate::secrets::get_resolver(entry.expose())
+                    .resolve()
+                    .and_then(|key| {
+                        Self::validate_key(key.expose())?;
+                        *entry = key;
+                        Ok(())
+                    }),
+            );
+        }
+
+        if let Some(password) = &mut self.rtsp_password {
+            check(
+                "rtsp_password",
+                crate::secrets::get_resolver(password.expose())
+                    .resolve()
+                    .map(|resolved| *password = resolved),
+            );
+        }
+        if self.rtsp_username.is_some() != self.rtsp_password.is_some() {
+            check(
+                "rtsp_username",
+                Err(anyhow::anyhow!(
+                    "rtsp_username and rtsp_password must be set together"
+                )),
+            );
+        }
+
+        problems
+    }
 
-        Ok(config)
+    /// `key` followed by the keyring and the identity, for picking a
+    /// recording's key.
+    pub fn keys(&self) -> Vec<Secret> {
+        std::iter::once(&self.key)
+            .filter(|key| !key.is_empty())
+            .chain(&self.keyring)
+            .chain(&self.identity)
+            .cloned()
+            .collect()
     }
 
     fn validate_key(key: &str) -> anyhow::Result<()> {
@@ -46,6 +366,359 @@ impl Config {
         }
         Ok(())
     }
+
+    /// The names of the fields, as they appear in the TOML file.
+    fn fields() -> &'static [&'static str] {
+        use serde::de::{self, Visitor};
+
+        /// Captures the field list serde hands to `deserialize_struct`.
+        struct Fields(&'static [&'static str]);
+
+        impl<'de> de::Deserializer<'de> for &mut Fields {
+            type Error = de::value::Error;
+
+            fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+                Err(de::Error::custom("not a struct"))
+            }
+
+            fn deserialize_struct<V: Visitor<'de>>(
+                self,
+                _: &'static str,
+                fields: &'static [&'static str],
+                _: V,
+            ) -> Result<V::Value, Self::Error> {
+                self.0 = fields;
+                Err(de::Error::custom("fields captured"))
+            }
+
+            serde::forward_to_deserialize_any! {
+                bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+                bytes byte_buf option unit unit_struct newtype_struct seq tuple
+                tuple_struct map enum identifier ignored_any
+            }
+        }
+
+        static FIELDS: std::sync::OnceLock<&'static [&'static str]> = std::sync::OnceLock::new();
+        FIELDS.get_or_init(|| {
+            let mut fields = Fields(&[]);
+            let _ = Config::deserialize(&mut fields);
+            fields.0
+        })
+    }
+}
+
+/// Where a configuration value came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin {
+    Default,
+    File { path: PathBuf, line: usize },
+    Env(String),
+    Flag(&'static str),
+}
+
+impl std::fmt::Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Origin::Default => f.write_str("default"),
+            Origin::File { path, line } => write!(f, "{}:{}", path.display(), line),
+            Origin::Env(var) => write!(f, "env {}", var),
+            Origin::Flag(flag) => f.write_str(flag),
+        }
+    }
+}
+
+/// The configuration, built up in layers that each override single fields:
+/// built-in defaults, the config file, its `conf.d/*.toml` drop-ins,
+/// `EIGHTYEIGHTYEIGHT_*` environment variables and command-line flags.
+///
+/// Layers are merged as TOML tables before `Config` is deserialized, so a
+/// drop-in may hold a single line such as `stream_port = 9000`. Each value is
+/// checked against the type of its field as it is merged, so problems are
+/// reported where the value was set, and all of them at once.
+pub struct Layers {
+    values: toml::Table,
+    origins: BTreeMap<String, Origin>,
+    files: Vec<PathBuf>,
+    problems: Vec<String>,
+}
+
+impl Layers {
+    /// Starts from the built-in defaults.
+    pub fn new() -> Self {
+        let values = defaults();
+        let origins = values
+            .keys()
+            .map(|field| (field.clone(), Origin::Default))
+            .collect();
+        Self {
+            values,
+            origins,
+            files: Vec::new(),
+            problems: Vec::new(),
+        }
+    }
+
+    /// Merges the config file and then the drop-ins in `conf.d/` next to it,
+    /// in name order. Without `path` the file is `SYSTEM_CONFIG`, or
+    /// `config.toml` in the working directory; neither has to exist.
+    pub fn files(self, path: Option<&Path>) -> anyhow::Result<Self> {
+        let path = match path {
+            Some(path) => Some(path.to_path_buf()),
+            None => [SYSTEM_CONFIG, LOCAL_CONFIG]
+                .iter()
+                .map(PathBuf::from)
+                .find(|path| path.is_file()),
+        };
+        let dir = match &path {
+            Some(path) => path
+                .parent()
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .unwrap_or(Path::new("."))
+                .join("conf.d"),
+            None => Path::new(SYSTEM_CONFIG).with_file_name("conf.d"),
+        };
+
+        let mut layers = match &path {
+            Some(path) => self.file(path)?,
+            None => self,
+        };
+        let mut drop_ins = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>()
+                .with_context(|| format!("Failed to list {}", dir.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to list {}", dir.display()));
+            }
+        };
+        drop_ins.retain(|path| path.extension().is_some_and(|ext| ext == "toml"));
+        drop_ins.sort();
+        for drop_in in drop_ins {
+            layers = layers.file(&drop_in)?;
+        }
+        Ok(layers)
+    }
+
+    /// Merges a single TOML file. Only failing to read it is an error;
+    /// syntax errors and invalid values are collected as problems.
+    pub fn file(mut self, path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        self.files.push(path.to_path_buf());
+
+        let table: BTreeMap<toml::Spanned<String>, toml::Spanned<toml::Value>> =
+            match toml::from_str(&content) {
+                Ok(table) => table,
+                Err(e) => {
+                    let (line, column) = e
+                        .span()
+                        .map(|span| line_column(&content, span.start))
+                        .unwrap_or((1, 1));
+                    self.problems.push(format!(
+                        "{}:{}:{}: {}",
+                        path.display(),
+                        line,
+                        column,
+                        e.message().trim_end()
+                    ));
+                    return Ok(self);
+                }
+            };
+        // In file order, so problems are reported top to bottom.
+        let mut entries: Vec<_> = table.into_iter().collect();
+        entries.sort_by_key(|(field, _)| field.span().start);
+        for (field, value) in entries {
+            let (line, column) = line_column(&content, field.span().start);
+            let location = format!("{}:{}:{}", path.display(), line, column);
+            let origin = Origin::File {
+                path: path.to_path_buf(),
+                line,
+            };
+            self.set(field.into_inner(), value.into_inner(), origin, location);
+        }
+        Ok(self)
+    }
+
+    /// Merges `EIGHTYEIGHTYEIGHT_<FIELD>` variables from `vars`, e.g.
+    /// `std::env::vars_os()`. A value is taken as a string where the field
+    /// accepts one, and read as TOML otherwise (`9000`, `true`,
+    /// `["env:OLD_KEY"]`). Other variables are ignored, whatever their
+    /// encoding; a value that is not UTF-8 is reported as a problem.
+    pub fn env<K, V>(mut self, vars: impl IntoIterator<Item = (K, V)>) -> Self
+    where
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        for (var, raw) in vars {
+            let var = var.as_ref().to_string_lossy();
+            let Some(field) = var.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            let field = field.to_ascii_lowercase();
+            if !Config::fields().contains(&field.as_str()) {
+                if var != CONFIG_ENV {
+                    warn!("Ignoring {}: there is no `{}` setting", var, field);
+                }
+                continue;
+            }
+            let location = format!("env {}", var);
+            let Some(raw) = raw.as_ref().to_str() else {
+                self.problems
+                    .push(format!("{}: {}: value is not valid UTF-8", location, field));
+                continue;
+            };
+            let string = toml::Value::String(raw.to_string());
+            let value = if check_field(&field, &string).is_ok() {
+                string
+            } else {
+                toml::from_str::<toml::Table>(&format!("value = {}", raw))
+                    .ok()
+                    .and_then(|mut table| table.remove("value"))
+                    .unwrap_or(string)
+            };
+            self.set(field, value, Origin::Env(var.into_owned()), location);
+        }
+        self
+    }
+
+    /// Applies a command-line flag, if it was given.
+    pub fn flag<T: Serialize>(
+        mut self,
+        field: &str,
+        flag: &'static str,
+        value: Option<T>,
+    ) -> anyhow::Result<Self> {
+        if let Some(value) = value {
+            let value = toml::Value::try_from(value)
+                .with_context(|| format!("Invalid value for {}", flag))?;
+            self.set(
+                field.to_string(),
+                value,
+                Origin::Flag(flag),
+                flag.to_string(),
+            );
+        }
+        Ok(self)
+    }
+
+    /// Merges a value that fits its field; otherwise records a problem at
+    /// `location` and keeps the previous value.
+    fn set(&mut self, field: String, value: toml::Value, origin: Origin, location: String) {
+        match check_field(&field, &value) {
+            Ok(()) => {
+                self.origins.insert(field.clone(), origin);
+                self.values.insert(field, value);
+            }
+            Err(problem) => self.problems.push(format!("{}: {}", location, problem)),
+        }
+    }
+
+    /// The files merged so far, in order.
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// Deserializes the merged layers and resolves the keys, failing with
+    /// every problem found.
+    pub fn build(&self) -> anyhow::Result<Config> {
+        let (config, problems) = self.check();
+        if problems.is_empty() {
+            Ok(config)
+        } else {
+            Err(anyhow::anyhow!(
+                "Invalid configuration:\n  {}",
+                problems.join("\n  ")
+            ))
+        }
+    }
+
+    /// Like `build`, but returns the configuration as far as it could be
+    /// resolved together with the list of problems, for `config check`.
+    pub fn check(&self) -> (Config, Vec<String>) {
+        // Every merged value fits its field, so only the resolution can fail.
+        let mut config: Config = toml::Value::Table(self.values.clone())
+            .try_into()
+            .expect("merged values are checked field by field");
+        let mut problems = self.problems.clone();
+        for (field, problem) in config.resolve() {
+            problems.push(match self.origins.get(field) {
+                Some(origin) if *origin != Origin::Default => {
+                    format!("{}: {}: {}", origin, field, problem)
+                }
+                _ => format!("{}: {}", field, problem),
+            });
+        }
+        (config, problems)
+    }
+
+    /// Prints the merged configuration as TOML, with key sources redacted.
+    /// `effective` includes the built-in defaults and notes where each
+    /// value came from; otherwise only the values set somewhere are shown.
+    pub fn show(&self, effective: bool) -> String {
+        let mut out = String::new();
+        for (field, value) in &self.values {
+            let origin = &self.origins[field];
+            if !effective && *origin == Origin::Default {
+                continue;
+            }
+            let value = if SECRET_FIELDS.contains(&field.as_str()) {
+                redact(value)
+            } else {
+                value.clone()
+            };
+            let line = format!("{} = {}", field, value);
+            if effective {
+                let _ = writeln!(out, "{:<40} # {}", line, origin);
+            } else {
+                let _ = writeln!(out, "{}", line);
+            }
+        }
+        out
+    }
+}
+
+impl Default for Layers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The built-in defaults as a TOML table.
+fn defaults() -> toml::Table {
+    toml::Table::try_from(Config::default()).expect("the defaults serialize to a table")
+}
+
+/// Checks that `value` has the type of `field` by deserializing it together
+/// with the defaults for every other field.
+fn check_field(field: &str, value: &toml::Value) -> Result<(), String> {
+    if !Config::fields().contains(&field) {
+        return Err(format!("unknown setting `{}`", field));
+    }
+    let mut table = defaults();
+    table.insert(field.to_string(), value.clone());
+    toml::Value::Table(table)
+        .try_into::<Config>()
+        .map(drop)
+        .map_err(|e| format!("{}: {}", field, e.message().trim_end()))
+}
+
+/// The 1-based line and column of byte `offset` in `content`.
+fn line_column(content: &str, offset: usize) -> (usize, usize) {
+    let before = &content[..offset.min(content.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+fn redact(value: &toml::Value) -> toml::Value {
+    match value {
+        toml::Value::String(source) => toml::Value::String(crate::secrets::redact(source)),
+        toml::Value::Array(values) => toml::Value::Array(values.iter().map(redact).collect()),
+        _ => toml::Value::String("[REDACTED]".to_string()),
+    }
 }
 
 #[cfg(test)]
@@ -199,4 +872,455 @@ mod tests {
         assert_eq!(config.max_files, Some(10));
         assert_eq!(config.max_file_size_mb, Some(50));
     }
+
+    #[test]
+    fn test_load_with_segment_duration() {
+        let valid_key = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+        let toml_str = format!(
+            r#"
+            device = "/dev/video_test"
+            width = 1280
+            height = 720
+            framerate = "30/1"
+            bitrate = 2500
+            key = "literal:{}"
+            output_path = "cam0.ts.enc"
+            segment_duration_secs = 60
+            max_files = 1440
+        "#,
+            valid_key
+        );
+
+        let mut file = NamedTempFile::new().unwrap();
+        write!(file, "{}", toml_str).unwrap();
+
+        let config = Config::load(file.path().to_str().unwrap()).expect("Failed to load config");
+        assert_eq!(config.segment_duration_secs, NonZeroU64::new(60));
+
+        let mut file = NamedTempFile::new().unwrap();
+        write!(
+            file,
+            "{}",
+            toml_str.replace("segment_duration_secs = 60", "segment_duration_secs = 0")
+        )
+        .unwrap();
+        assert!(Config::load(file.path().to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_load_with_metrics_listen() {
+        let valid_key = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+        let toml_str = format!(
+            r#"
+            device = "/dev/video_test"
+            width = 1280
+            height = 720
+            framerate = "30/1"
+            bitrate = 2500
+            key = "literal:{}"
+            output_path = "test_output.ts.enc"
+            metrics_listen = "0.0.0.0:9091"
+        "#,
+            valid_key
+        );
+
+        let mut file = NamedTempFile::new().unwrap();
+        write!(file, "{}", toml_str).unwrap();
+
+        let config = Config::load(file.path().to_str().unwrap()).expect("Failed to load config");
+        assert_eq!(config.metrics_listen.as_deref(), Some("0.0.0.0:9091"));
+    }
+
+    #[test]
+    fn test_load_with_rtsp_credentials() {
+        let valid_key = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+        let toml_str = format!(
+            r#"
+            device = "videotestsrc"
+            width = 1280
+            height = 720
+            framerate = "30/1"
+            bitrate = 2500
+            key = "literal:{}"
+            output_path = "test_output.ts.enc"
+            rtsp_username = "operator"
+            rtsp_password = "literal:hunter2"
+        "#,
+            valid_key
+        );
+
+        let mut file = NamedTempFile::new().unwrap();
+        write!(file, "{}", toml_str).unwrap();
+
+        let config = Config::load(file.path().to_str().unwrap()).expect("Failed to load config");
+        assert_eq!(config.rtsp_username.as_deref(), Some("operator"));
+        assert_eq!(
+            config.rtsp_password.as_ref().map(Secret::expose),
+            Some("hunter2")
+        );
+        // The resolved secrets never make it back into a serialized config.
+        let table = toml::Table::try_from(&config).unwrap();
+        assert_eq!(table["rtsp_username"].as_str(), Some("operator"));
+        assert!(!table.contains_key("key"));
+        assert!(!table.contains_key("rtsp_password"));
+
+        let mut file = NamedTempFile::new().unwrap();
+        write!(
+            file,
+            "{}",
+            toml_str.replace("rtsp_password = \"literal:hunter2\"", "")
+        )
+        .unwrap();
+        assert!(Config::load(file.path().to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_load_with_stream_dest() {
+        let valid_key = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+        let toml_str = format!(
+            r#"
+            device = "/dev/video_test"
+            width = 1280
+            height = 720
+            framerate = "30/1"
+            bitrate = 2500
+            key = "literal:{}"
+            output_path = "test_output.ts.enc"
+            stream_dest = "192.168.1.10"
+            stream_port = 9000
+            stream_transport = "srtp"
+        "#,
+            valid_key
+        );
+
+        let mut file = NamedTempFile::new().unwrap();
+        write!(file, "{}", toml_str).unwrap();
+
+        let config = Config::load(file.path().to_str().unwrap()).expect("Failed to load config");
+        assert_eq!(config.stream_dest.as_deref(), Some("192.168.1.10"));
+        assert_eq!(config.stream_port, Some(9000));
+        assert_eq!(config.stream_transport, Some(Transport::Srtp));
+    }
+
+    #[test]
+    fn test_load_with_keyring() {
+        let valid_key = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+        let old_key = "ffeeddccbbaa99887766554433221100";
+        unsafe {
+            std::env::set_var("TEST_KEYRING_OLD_KEY", old_key);
+        }
+        let toml_str = format!(
+            r#"
+            device = "/dev/video_test"
+            width = 1280
+            height = 720
+            framerate = "30/1"
+            bitrate = 2500
+            key = "literal:{}"
+            keyring = ["env:TEST_KEYRING_OLD_KEY"]
+            output_path = "test_output.ts.enc"
+        "#,
+            valid_key
+        );
+
+        let mut file = NamedTempFile::new().unwrap();
+        write!(file, "{}", toml_str).unwrap();
+
+        let config = Config::load(file.path().to_str().unwrap()).expect("Failed to load config");
+        unsafe {
+            std::env::remove_var("TEST_KEYRING_OLD_KEY");
+        }
+        assert_eq!(config.keyring, [old_key]);
+        assert_eq!(config.keys(), [valid_key, old_key]);
+
+        let mut file = NamedTempFile::new().unwrap();
+        write!(
+            file,
+            "{}",
+            toml_str.replace("env:TEST_KEYRING_OLD_KEY", "literal:abc")
+        )
+        .unwrap();
+        assert!(Config::load(file.path().to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_load_with_recipient_or_identity() {
+        let identity = crate::secrets::PrivateKeyResolver::generate();
+        let recipient = crate::secrets::PrivateKeyResolver::recipient(&identity).unwrap();
+        let load = |keys: &str| {
+            let mut file = NamedTempFile::new().unwrap();
+            write!(
+                file,
+                r#"
+                device = "/dev/video_test"
+                width = 1280
+                height = 720
+                framerate = "30/1"
+                bitrate = 2500
+                {}
+                output_path = "test_output.ts.enc"
+            "#,
+                keys
+            )
+            .unwrap();
+            Config::load(file.path().to_str().unwrap())
+        };
+
+        // A recorder holds only the public key.
+        let config = load(&format!("recipient = \"{}\"", recipient)).unwrap();
+        assert_eq!(config.key, "");
+        assert_eq!(config.recipient.as_deref(), Some(recipient.as_str()));
+        assert!(config.keys().is_empty());
+
+        // A reviewer holds the private key.
+        let config = load(&format!("identity = \"literal:{}\"", identity)).unwrap();
+        assert_eq!(config.keys(), [identity]);
+
+        assert!(load("").is_err());
+        assert!(load("recipient = \"abcd\"").is_err());
+        assert!(load("identity = \"literal:abcd\"").is_err());
+    }
+
+    #[test]
+    fn test_layers() {
+        let key = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            format!(
+                "device = \"/dev/video2\"\nkey = \"literal:{}\"\nstream_port = 9000\n",
+                key
+            ),
+        )
+        .unwrap();
+        let conf_d = dir.path().join("conf.d");
+        std::fs::create_dir(&conf_d).unwrap();
+        std::fs::write(conf_d.join("10-port.toml"), "stream_port = 9100\n").unwrap();
+        std::fs::write(
+            conf_d.join("20-port.toml"),
+            "stream_port = 9200\nwidth = 1280\n",
+        )
+        .unwrap();
+        std::fs::write(conf_d.join("README"), "not toml").unwrap();
+
+        let env = [
+            ("EIGHTYEIGHTYEIGHT_WIDTH", "1920"),
+            ("EIGHTYEIGHTYEIGHT_DEVICE", "/dev/video9"),
+            ("EIGHTYEIGHTYEIGHT_RTSP_USERNAME", "0"),
+            ("EIGHTYEIGHTYEIGHT_RTSP_PASSWORD", "literal:1234"),
+            ("EIGHTYEIGHTYEIGHT_CV_ENABLED", "true"),
+            (
+                "EIGHTYEIGHTYEIGHT_KEYRING",
+                "[\"literal:ffeeddccbbaa99887766554433221100\"]",
+            ),
+            ("EIGHTYEIGHTYEIGHT_METRICS_LISTEN", "127.0.0.1:9091"),
+            ("EIGHTYEIGHTYEIGHT_NO_SUCH_SETTING", "1"),
+            ("EIGHTYEIGHTYEIGHT_CONFIG", "ignored.toml"),
+            ("PATH", "/usr/bin"),
+        ];
+        let layers = Layers::new()
+            .files(Some(&path))
+            .unwrap()
+            .env(env.map(|(var, value)| (var.to_string(), value.to_string())))
+            .flag("stream_transport", "--transport", Some(Transport::Srtp))
+            .unwrap()
+            .flag("stream_dest", "--dest", None::<String>)
+            .unwrap();
+        assert_eq!(
+            layers.paths(),
+            [
+                path.clone(),
+                conf_d.join("10-port.toml"),
+                conf_d.join("20-port.toml")
+            ]
+        );
+
+        let config = layers.build().unwrap();
+        assert_eq!(config.device, Device::V4l2(PathBuf::from("/dev/video9")));
+        assert_eq!(config.width.get(), 1920);
+        assert_eq!(config.height.get(), 480);
+        assert_eq!(config.rtsp_username.as_deref(), Some("0"));
+        assert!(config.cv_enabled);
+        assert_eq!(config.key, key);
+        assert_eq!(config.keyring, ["ffeeddccbbaa99887766554433221100"]);
+        assert_eq!(config.metrics_listen.as_deref(), Some("127.0.0.1:9091"));
+        assert_eq!(config.stream_port, Some(9200));
+        assert_eq!(config.stream_transport, Some(Transport::Srtp));
+        assert_eq!(config.stream_dest, None);
+        assert_eq!(config.rtsp_port, Some(DEFAULT_RTSP_PORT));
+
+        let effective = layers.show(true);
+        let line = |field: &str| {
+            effective
+                .lines()
+                .find(|line| line.starts_with(&format!("{} =", field)))
+                .unwrap_or_else(|| panic!("no {} in {}", field, effective))
+                .to_string()
+        };
+        assert!(line("height").ends_with("# default"));
+        assert!(
+            line("stream_port").contains(&format!("# {}", conf_d.join("20-port.toml").display()))
+        );
+        assert!(line("width").ends_with("# env EIGHTYEIGHTYEIGHT_WIDTH"));
+        assert!(line("stream_transport").starts_with("stream_transport = \"srtp\""));
+        assert!(line("stream_transport").ends_with("# --transport"));
+        assert!(line("key").starts_with("key = \"[REDACTED]\""));
+        assert!(line("keyring").starts_with("keyring = [\"[REDACTED]\"]"));
+        assert!(!effective.contains(key));
+        assert!(!effective.contains("no_such_setting"));
+        assert!(!effective.contains("ignored.toml"));
+
+        let set = layers.show(false);
+        assert!(!set.contains("height"));
+        assert!(set.contains("stream_port = 9200\n"));
+        assert!(toml::from_str::<toml::Table>(&set).is_ok());
+
+        // Only an explicitly named file has to exist.
+        assert!(
+            Layers::new()
+                .files(Some(&dir.path().join("missing.toml")))
+                .is_err()
+        );
+        std::fs::write(conf_d.join("30-bad.toml"), "width = ").unwrap();
+        let err = Layers::new()
+            .files(Some(&path))
+            .unwrap()
+            .build()
+            .unwrap_err();
+        assert!(err.to_string().contains("30-bad.toml:1:"));
+    }
+
+    #[test]
+    fn test_problems_are_located_and_collected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            "device = \"\"\nwidth = 0\n  framerate = \"30\"\nstream_prot = 9000\nkey = \"literal:abcd\"\nsegment_duration_secs = 0\n",
+        )
+        .unwrap();
+        let layers = Layers::new()
+            .file(&path)
+            .unwrap()
+            .env([("EIGHTYEIGHTYEIGHT_HEIGHT".to_string(), "-1".to_string())]);
+
+        let (config, problems) = layers.check();
+        let at = |line: usize, column: usize| format!("{}:{}:{}: ", path.display(), line, column);
+        assert_eq!(problems.len(), 7, "{:#?}", problems);
+        assert!(problems[0].starts_with(&format!("{}device: invalid device \"\"", at(1, 1))));
+        assert!(problems[1].starts_with(&format!("{}width: invalid value: integer `0`", at(2, 1))));
+        assert!(
+            problems[2].starts_with(&format!("{}framerate: invalid frame rate \"30\"", at(3, 3)))
+        );
+        assert_eq!(
+            problems[3],
+            format!("{}unknown setting `stream_prot`", at(4, 1))
+        );
+        assert!(problems[4].starts_with(&format!("{}segment_duration_secs:", at(6, 1))));
+        assert!(problems[5].starts_with("env EIGHTYEIGHTYEIGHT_HEIGHT: height:"));
+        assert!(problems[6].starts_with(&format!("{}:5: key: Invalid key length", path.display())));
+
+        // Invalid values leave the lower layers in place.
+        assert_eq!(config.width.get(), 640);
+        assert_eq!(config.framerate.to_string(), "30/1");
+
+        let err = layers.build().unwrap_err().to_string();
+        assert!(err.starts_with("Invalid configuration:\n"));
+        assert_eq!(err.lines().count(), 8);
+    }
+
+    #[test]
+    fn test_env_tolerates_non_utf8() {
+        use std::ffi::OsString;
+        use std::os::unix::ffi::OsStringExt;
+
+        let invalid = || OsString::from_vec(vec![0x66, 0x6f, 0xff]);
+        let layers = Layers::new().env([
+            (OsString::from("UNRELATED"), invalid()),
+            (invalid(), OsString::from("value")),
+            (
+                OsString::from("EIGHTYEIGHTYEIGHT_STREAM_PORT"),
+                "9000".into(),
+            ),
+            (OsString::from("EIGHTYEIGHTYEIGHT_OUTPUT_PATH"), invalid()),
+        ]);
+        let (config, problems) = layers.check();
+        assert_eq!(config.stream_port, Some(9000));
+        assert_eq!(
+            problems[0],
+            "env EIGHTYEIGHTYEIGHT_OUTPUT_PATH: output_path: value is not valid UTF-8"
+        );
+        assert_eq!(problems.len(), 2, "{:#?}", problems); // and the missing key
+    }
+
+    #[test]
+    fn test_typed_fields() {
+        assert_eq!("auto".parse::<Device>().unwrap(), Device::Auto);
+        assert_eq!(
+            "videotestsrc".parse::<Device>().unwrap(),
+            Device::TestSource
+        );
+        assert_eq!(
+            "/dev/v4l/by-id/usb-cam-video-index0"
+                .parse::<Device>()
+                .unwrap(),
+            Device::V4l2(PathBuf::from("/dev/v4l/by-id/usb-cam-video-index0"))
+        );
+        // Relative paths were accepted before the field was typed.
+        assert_eq!(
+            "video0".parse::<Device>().unwrap(),
+            Device::V4l2(PathBuf::from("video0"))
+        );
+        assert!("".parse::<Device>().is_err());
+
+        let ntsc: Fraction = "30000/1001".parse().unwrap();
+        assert_eq!((ntsc.numer.get(), ntsc.denom.get()), (30000, 1001));
+        assert_eq!(ntsc.to_string(), "30000/1001");
+        for invalid in ["30", "30/0", "0/1", "30/1/1", "x/1", "-30/1", ""] {
+            assert!(invalid.parse::<Fraction>().is_err(), "{}", invalid);
+        }
+
+        // Both serialize back to the strings they were read from.
+        let config = Config {
+            device: Device::Auto,
+            framerate: ntsc,
+            ..Config::default()
+        };
+        let table = toml::Table::try_from(&config).unwrap();
+        assert_eq!(table["device"].as_str(), Some("auto"));
+        assert_eq!(table["framerate"].as_str(), Some("30000/1001"));
+    }
+
+    #[test]
+    fn test_redacts_only_literal_key_sources() {
+        let layers = Layers::new().env([
+            (
+                "EIGHTYEIGHTYEIGHT_KEY".to_string(),
+                "env:VIDEO_KEY".to_string(),
+            ),
+            (
+                "EIGHTYEIGHTYEIGHT_RTSP_PASSWORD".to_string(),
+                "hunter2".to_string(),
+            ),
+            (
+                "EIGHTYEIGHTYEIGHT_IDENTITY".to_string(),
+                "pkcs11:module=m.so,slot=0,key=k,pin=1234,wrapped=00".to_string(),
+            ),
+        ]);
+        let shown = layers.show(false);
+        assert!(shown.contains("key = \"env:VIDEO_KEY\""));
+        assert!(shown.contains("rtsp_password = \"[REDACTED]\""));
+        assert!(shown.contains("identity = \"pkcs11:[REDACTED]\""));
+        assert!(!shown.contains("hunter2") && !shown.contains("1234"));
+    }
+
+    #[test]
+    fn test_fields() {
+        let fields = Config::fields();
+        assert_eq!(fields.first(), Some(&"device"));
+        assert!(fields.contains(&"key"));
+        assert!(fields.contains(&"rtsp_port"));
+        assert!(!fields.contains(&"config"));
+    }
 }
@@ -0,0 +1,1133 @@
+//! Chunked AEAD container used for recordings.
+//!
+//! The file starts with a small versioned header followed by a sequence of
+//! independently authenticated chunks (the STREAM construction):
+//!
+//! ```text
+//! header: magic "8088AEAD" | version u8 | algorithm u8 | chunk_size u32 BE
+//!         | nonce_prefix [u8; 7] | ext_len u16 BE | ext [u8; ext_len]
+//! ext:    (type u8 | len u16 BE | value [u8; len])*
+//! keys:   block_len u16 BE | kind u8 | key_id [u8; 8]
+//!         | ephemeral_public [u8; 32]                    (kind 2 only)
+//!         | wrap_nonce [u8; 12] | wrapped_data_key [u8; key_len + 16]
+//! chunk:  ciphertext [u8; n] | tag [u8; 16]
+//! ```
+//!
+//! Every chunk except the last holds exactly `chunk_size` bytes of plaintext;
+//! the last one holds fewer (possibly zero). The 96-bit GCM nonce of chunk `i`
+//! is `nonce_prefix || i as u32 BE || last_flag`, and the header bytes up to
+//! the key block are the associated data of every chunk. This detects flipped
+//! bits, reordered or spliced chunks, and truncation (a missing final chunk).
+//!
+//! The chunks are encrypted with a random data key per file, stored in the
+//! key block. A leaked data key exposes one file. The block sits outside the
+//! chunks' associated data, so it can be replaced without touching them.
+//! Kind 1 wraps the data key (AES-GCM) with the master key from the config;
+//! moving to a new master key only means rewrapping it, which keeps its
+//! size. Kind 2 seals it to an X25519 recipient public key: the wrapping key
+//! is derived (HKDF-SHA256) from a Diffie-Hellman exchange between a fresh
+//! ephemeral key, stored in the block, and the recipient, so a recorder
+//! holding only the public key cannot decrypt what it wrote.
+//!
+//! The key ID names the key that opens the file: the first 8 bytes of
+//! HMAC-SHA256 keyed with the master key, or with the recipient public key,
+//! over a fixed label. It is not secret and lets readers pick the right key
+//! from a keyring, or say which one is missing. The key block carries it.
+//! No extensions are defined yet; readers skip the ones they do not know.
+
+use aes_gcm::aead::{AeadInPlace, KeyInit};
+use aes_gcm::{Aes128Gcm, Aes256Gcm, Nonce, Tag};
+use anyhow::{Context, Result};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+pub const MAGIC: &[u8; 8] = b"8088AEAD";
+pub const VERSION: u8 = 1;
+pub const TAG_LEN: usize = 16;
+pub const NONCE_PREFIX_LEN: usize = 7;
+pub const DEFAULT_CHUNK_SIZE: u32 = 64 * 1024;
+pub const KEY_ID_LEN: usize = 8;
+
+const WRAP_NONCE_LEN: usize = 12;
+const KEY_ID_LABEL: &[u8] = b"eightyeightyeight key id";
+const RECIPIENT_LABEL: &[u8] = b"eightyeightyeight recipient";
+const KIND_MASTER: u8 = 1;
+const KIND_RECIPIENT: u8 = 2;
+/// Length of X25519 keys, public and private.
+pub const X25519_KEY_LEN: usize = 32;
+
+/// Length of the fixed part of the header, up to and including `ext_len`.
+const FIXED_HEADER_LEN: usize = MAGIC.len() + 1 + 1 + 4 + NONCE_PREFIX_LEN + 2;
+
+/// On-disk format of an encrypted recording.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Chunked AES-GCM container described in this module.
+    Aead,
+    /// Legacy `aesenc cipher=aes-256-cbc serialize-iv=true` stream, read-only.
+    LegacyCbc,
+}
+
+/// Sniffs the magic bytes of `path` to tell new recordings from legacy ones.
+pub fn detect_format(path: &Path) -> Result<Format> {
+    let mut file =
+        File::open(path).with_context(|| format!("Failed to open recording: {:?}", path))?;
+    let mut magic = [0u8; MAGIC.len()];
+    match file.read_exact(&mut magic) {
+        Ok(()) if &magic == MAGIC => Ok(Format::Aead),
+        Ok(()) => Ok(Format::LegacyCbc),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(Format::LegacyCbc),
+        Err(e) => Err(e).with_context(|| format!("Failed to read recording: {:?}", path)),
+    }
+}
+
+/// Picks the key for the recording at `path` from `keys` (hex). Legacy
+/// recordings have no header and get the first key.
+pub fn select_key<'a, K: AsRef<str>>(path: &Path, keys: &'a [K]) -> Result<&'a str> {
+    if detect_format(path)? == Format::LegacyCbc {
+        return keys.first().map(K::as_ref).context("No key configured");
+    }
+    let mut file =
+        File::open(path).with_context(|| format!("Failed to open recording: {:?}", path))?;
+    Header::read_from(&mut file)?.select_key(keys)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    Aes128Gcm = 1,
+    Aes256Gcm = 2,
+}
+
+impl Algorithm {
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(Self::Aes128Gcm),
+            2 => Some(Self::Aes256Gcm),
+            _ => None,
+        }
+    }
+
+    fn for_key(key: &[u8]) -> Result<Self> {
+        match key.len() {
+            16 => Ok(Self::Aes128Gcm),
+            32 => Ok(Self::Aes256Gcm),
+            n => anyhow::bail!("Invalid key length: {} bytes. Expected 16 or 32.", n),
+        }
+    }
+
+    fn key_len(self) -> usize {
+        match self {
+            Self::Aes128Gcm => 16,
+            Self::Aes256Gcm => 32,
+        }
+    }
+}
+
+/// Non-secret identifier of a recording key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyId(pub [u8; KEY_ID_LEN]);
+
+impl KeyId {
+    pub fn of(key: &[u8]) -> Self {
+        let mut mac =
+            <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts any key length");
+        mac.update(KEY_ID_LABEL);
+        let digest = mac.finalize().into_bytes();
+        Self(digest[..KEY_ID_LEN].try_into().unwrap())
+    }
+}
+
+impl fmt::Display for KeyId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+/// Errors that callers may want to tell apart from I/O failures.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContainerError {
+    /// The file does not start with a valid header.
+    InvalidHeader(String),
+    /// A chunk failed authentication: wrong key, or tampered data.
+    Authentication { chunk: u32 },
+    /// None of the available keys has the recording's key ID.
+    UnknownKeyId(KeyId),
+    /// The data key does not unwrap: wrong master key, or a tampered key
+    /// block.
+    KeyUnwrap,
+}
+
+impl fmt::Display for ContainerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidHeader(reason) => write!(f, "Invalid recording header: {}", reason),
+            Self::Authentication { chunk } => write!(
+                f,
+                "Chunk {} failed authentication (wrong key or tampered data)",
+                chunk
+            ),
+            Self::UnknownKeyId(id) => write!(f, "No key for key-id {}", id),
+            Self::KeyUnwrap => write!(
+                f,
+                "Data key failed authentication (wrong key or tampered header)"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ContainerError {}
+
+/// How the data key in a key block is protected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Wrap {
+    /// AES-GCM under the master key.
+    Master,
+    /// AES-256-GCM under a key agreed between this ephemeral public key and
+    /// the recipient.
+    Recipient([u8; X25519_KEY_LEN]),
+}
+
+/// The data key of a file, wrapped by the master key or sealed to a
+/// recipient.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct KeyBlock {
+    key_id: KeyId,
+    wrap: Wrap,
+    nonce: [u8; WRAP_NONCE_LEN],
+    wrapped: Vec<u8>,
+}
+
+impl KeyBlock {
+    fn seal(master: &[u8], data_key: &[u8]) -> Result<Self> {
+        let key_id = KeyId::of(master);
+        let mut nonce = [0u8; WRAP_NONCE_LEN];
+        rand::rng().fill_bytes(&mut nonce);
+        let mut wrapped = data_key.to_vec();
+        Cipher::from_key(master)?.seal(&nonce, &key_id.0, &mut wrapped);
+        Ok(Self {
+            key_id,
+            wrap: Wrap::Master,
+            nonce,
+            wrapped,
+        })
+    }
+
+    fn seal_to(recipient: &[u8; X25519_KEY_LEN], data_key: &[u8]) -> Result<Self> {
+        let key_id = KeyId::of(recipient);
+        let mut secret = [0u8; X25519_KEY_LEN];
+        rand::rng().fill_bytes(&mut secret);
+        let secret = StaticSecret::from(secret);
+        let ephemeral = PublicKey::from(&secret).to_bytes();
+        let shared = secret.diffie_hellman(&PublicKey::from(*recipient));
+        if !shared.was_contributory() {
+            anyhow::bail!("Invalid recipient public key");
+        }
+
+        let mut nonce = [0u8; WRAP_NONCE_LEN];
+        rand::rng().fill_bytes(&mut nonce);
+        let mut wrapped = data_key.to_vec();
+        recipient_cipher(shared.as_bytes(), &ephemeral, recipient).seal(
+            &nonce,
+            &recipient_aad(&key_id, &ephemeral),
+            &mut wrapped,
+        );
+        Ok(Self {
+            key_id,
+            wrap: Wrap::Recipient(ephemeral),
+            nonce,
+            wrapped,
+        })
+    }
+
+    /// Unwraps the data key with `key`: the master key, or the recipient's
+    /// private key.
+    fn open(&self, key: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+        let mut data_key = Zeroizing::new(self.wrapped.clone());
+        let opened = match self.wrap {
+            Wrap::Master => Cipher::from_key(key)?.open(&self.nonce, &self.key_id.0, &mut data_key),
+            Wrap::Recipient(ephemeral) => {
+                let secret: [u8; X25519_KEY_LEN] =
+                    key.try_into().map_err(|_| ContainerError::KeyUnwrap)?;
+                let secret = StaticSecret::from(secret);
+                let recipient = PublicKey::from(&secret).to_bytes();
+                let shared = secret.diffie_hellman(&PublicKey::from(ephemeral));
+                recipient_cipher(shared.as_bytes(), &ephemeral, &recipient).open(
+                    &self.nonce,
+                    &recipient_aad(&self.key_id, &ephemeral),
+                    &mut data_key,
+                )
+            }
+        };
+        if !opened {
+            return Err(ContainerError::KeyUnwrap.into());
+        }
+        Ok(data_key)
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut body = vec![match self.wrap {
+            Wrap::Master => KIND_MASTER,
+            Wrap::Recipient(_) => KIND_RECIPIENT,
+        }];
+        body.extend_from_slice(&self.key_id.0);
+        if let Wrap::Recipient(ephemeral) = &self.wrap {
+            body.extend_from_slice(ephemeral);
+        }
+        body.extend_from_slice(&self.nonce);
+        body.extend_from_slice(&self.wrapped);
+
+        let mut bytes = Vec::with_capacity(2 + body.len());
+        bytes.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        bytes.extend(body);
+        bytes
+    }
+
+    fn decode(bytes: &[u8], algorithm: Algorithm) -> Option<Self> {
+        let (kind, bytes) = bytes.split_first()?;
+        let (key_id, rest) = bytes.split_at_checked(KEY_ID_LEN)?;
+        let (wrap, rest) = match *kind {
+            KIND_MASTER => (Wrap::Master, rest),
+            KIND_RECIPIENT => {
+                let (ephemeral, rest) = rest.split_at_checked(X25519_KEY_LEN)?;
+                (Wrap::Recipient(ephemeral.try_into().unwrap()), rest)
+            }
+            _ => return None,
+        };
+        let (nonce, wrapped) = rest.split_at_checked(WRAP_NONCE_LEN)?;
+        if wrapped.len() != algorithm.key_len() + TAG_LEN {
+            return None;
+        }
+        Some(Self {
+            key_id: KeyId(key_id.try_into().unwrap()),
+            wrap,
+            nonce: nonce.try_into().unwrap(),
+            wrapped: wrapped.to_vec(),
+        })
+    }
+}
+
+/// The AES-256-GCM key that seals a data key to a recipient.
+fn recipient_cipher(shared: &[u8], ephemeral: &[u8], recipient: &[u8]) -> Cipher {
+    let salt = [ephemeral, recipient].concat();
+    let mut key = [0u8; 32];
+    hkdf::Hkdf::<Sha256>::new(Some(&salt), shared)
+        .expand(RECIPIENT_LABEL, &mut key)
+        .expect("32 bytes is a valid HKDF-SHA256 length");
+    Cipher::new(Algorithm::Aes256Gcm, &key).expect("key has the algorithm's length")
+}
+
+fn recipient_aad(key_id: &KeyId, ephemeral: &[u8]) -> Vec<u8> {
+    [&key_id.0[..], ephemeral].concat()
+}
+
+/// The public key of an X25519 private key, for `recipient`.
+pub fn recipient_of(identity: &[u8; X25519_KEY_LEN]) -> [u8; X25519_KEY_LEN] {
+    PublicKey::from(&StaticSecret::from(*identity)).to_bytes()
+}
+
+/// Parses a hex X25519 public (or private) key.
+pub fn parse_x25519_key(hex_key: &str) -> Result<[u8; X25519_KEY_LEN]> {
+    hex::decode(hex_key)
+        .context("Key is not valid hex")?
+        .try_into()
+        .map_err(|key: Vec<u8>| {
+            anyhow::anyhow!(
+                "Invalid X25519 key length: {} bytes. Expected {}.",
+                key.len(),
+                X25519_KEY_LEN
+            )
+        })
+}
+
+/// Whether `key` opens files with key ID `id`, as the master key or as the
+/// recipient's private key.
+fn has_key_id(key: &[u8], id: KeyId) -> bool {
+    KeyId::of(key) == id
+        || <[u8; X25519_KEY_LEN]>::try_from(key)
+            .is_ok_and(|identity| KeyId::of(&recipient_of(&identity)) == id)
+}
+
+/// Parsed container header together with its raw bytes (the chunks' AAD).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub algorithm: Algorithm,
+    pub chunk_size: u32,
+    pub nonce_prefix: [u8; NONCE_PREFIX_LEN],
+    /// ID of the key that opens the data key.
+    pub key_id: KeyId,
+    raw: Vec<u8>,
+    /// Not part of `raw`, so it can be replaced without touching the chunks.
+    key_block: KeyBlock,
+}
+
+impl Header {
+    /// A header for a new recording whose data key is in `key_block`.
+    fn new(algorithm: Algorithm, chunk_size: u32, key_block: KeyBlock) -> Self {
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_LEN];
+        rand::rng().fill_bytes(&mut nonce_prefix);
+
+        let mut raw = Vec::with_capacity(FIXED_HEADER_LEN);
+        raw.extend_from_slice(MAGIC);
+        raw.push(VERSION);
+        raw.push(algorithm as u8);
+        raw.extend_from_slice(&chunk_size.to_be_bytes());
+        raw.extend_from_slice(&nonce_prefix);
+        raw.extend_from_slice(&0u16.to_be_bytes());
+
+        Self {
+            algorithm,
+            chunk_size,
+            nonce_prefix,
+            key_id: key_block.key_id,
+            raw,
+            key_block,
+        }
+    }
+
+    /// Reads and validates a header from the start of `reader`.
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut raw = vec![0u8; FIXED_HEADER_LEN];
+        reader
+            .read_exact(&mut raw)
+            .map_err(|_| ContainerError::InvalidHeader("file too short".to_string()))?;
+
+        if &raw[..MAGIC.len()] != MAGIC {
+            return Err(ContainerError::InvalidHeader("bad magic".to_string()).into());
+        }
+        let mut pos = MAGIC.len();
+        let version = raw[pos];
+        if version != VERSION {
+            return Err(
+                ContainerError::InvalidHeader(format!("unsupported version {}", version)).into(),
+            );
+        }
+        pos += 1;
+        let algorithm = Algorithm::from_id(raw[pos]).ok_or_else(|| {
+            ContainerError::InvalidHeader(format!("unknown algorithm {}", raw[pos]))
+        })?;
+        pos += 1;
+        let chunk_size = u32::from_be_bytes(raw[pos..pos + 4].try_into().unwrap());
+        if chunk_size == 0 {
+            return Err(ContainerError::InvalidHeader("zero chunk size".to_string()).into());
+        }
+        pos += 4;
+        let nonce_prefix: [u8; NONCE_PREFIX_LEN] =
+            raw[pos..pos + NONCE_PREFIX_LEN].try_into().unwrap();
+        pos += NONCE_PREFIX_LEN;
+        let ext_len = u16::from_be_bytes(raw[pos..pos + 2].try_into().unwrap()) as usize;
+
+        let mut ext = vec![0u8; ext_len];
+        reader
+            .read_exact(&mut ext)
+            .map_err(|_| ContainerError::InvalidHeader("truncated extensions".to_string()))?;
+        raw.extend_from_slice(&ext);
+
+        let mut ext = &ext[..];
+        while !ext.is_empty() {
+            if ext.len() < 3 {
+                return Err(
+                    ContainerError::InvalidHeader("malformed extension".to_string()).into(),
+                );
+            }
+            let len = u16::from_be_bytes([ext[1], ext[2]]) as usize;
+            ext = ext
+                .get(3 + len..)
+                .ok_or_else(|| ContainerError::InvalidHeader("malformed extension".to_string()))?;
+        }
+
+        let mut len = [0u8; 2];
+        let mut block = vec![0u8; 0];
+        reader
+            .read_exact(&mut len)
+            .and_then(|()| {
+                block.resize(u16::from_be_bytes(len) as usize, 0);
+                reader.read_exact(&mut block)
+            })
+            .map_err(|_| ContainerError::InvalidHeader("truncated key block".to_string()))?;
+        let key_block = KeyBlock::decode(&block, algorithm)
+            .ok_or_else(|| ContainerError::InvalidHeader("malformed key block".to_string()))?;
+
+        Ok(Self {
+            algorithm,
+            chunk_size,
+            nonce_prefix,
+            key_id: key_block.key_id,
+            raw,
+            key_block,
+        })
+    }
+
+    /// The key that encrypts the chunks, unwrapped from the key block with
+    /// the master key or the recipient's private key.
+    pub fn data_key(&self, key: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+        self.key_block.open(key)
+    }
+
+    /// Whether the data key is sealed to a recipient public key.
+    pub fn is_sealed_to_recipient(&self) -> bool {
+        matches!(self.key_block.wrap, Wrap::Recipient(_))
+    }
+
+    /// Wraps the data key under `new_master` instead of `old_master`. The
+    /// encoded header keeps its length, so the chunks stay where they are.
+    pub fn rewrap(&mut self, old_master: &[u8], new_master: &[u8]) -> Result<()> {
+        if self.is_sealed_to_recipient() {
+            anyhow::bail!("The data key is sealed to a recipient, not wrapped by a master key");
+        }
+        let data_key = self.data_key(old_master)?;
+        let block = KeyBlock::seal(new_master, &data_key)?;
+        self.key_id = block.key_id;
+        self.key_block = block;
+        Ok(())
+    }
+
+    /// Picks the key for this recording from `keys` (hex).
+    pub fn select_key<'a, K: AsRef<str>>(&self, keys: &'a [K]) -> Result<&'a str> {
+        let key_id = self.key_id;
+        keys.iter()
+            .map(K::as_ref)
+            .find(|key| parse_key(key).is_ok_and(|key| has_key_id(&key, key_id)))
+            .ok_or_else(|| ContainerError::UnknownKeyId(key_id).into())
+    }
+
+    /// The authenticated header bytes, without the key block.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.raw
+    }
+
+    /// The header as stored on disk, key block included.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.raw.clone();
+        bytes.extend(self.key_block.encode());
+        bytes
+    }
+
+    /// Offset of the first chunk.
+    pub fn encoded_len(&self) -> u64 {
+        (self.raw.len() + self.key_block.encode().len()) as u64
+    }
+
+    /// Size of one full chunk on disk, including its tag.
+    pub fn record_size(&self) -> u64 {
+        self.chunk_size as u64 + TAG_LEN as u64
+    }
+
+    fn nonce(&self, index: u32, last: bool) -> [u8; 12] {
+        let mut nonce = [0u8; 12];
+        nonce[..NONCE_PREFIX_LEN].copy_from_slice(&self.nonce_prefix);
+        nonce[NONCE_PREFIX_LEN..11].copy_from_slice(&index.to_be_bytes());
+        nonce[11] = last as u8;
+        nonce
+    }
+}
+
+/// AES-GCM with the key size picked from the key, shared with `packet`.
+pub(crate) enum Cipher {
+    Aes128(Box<Aes128Gcm>),
+    Aes256(Box<Aes256Gcm>),
+}
+
+impl Cipher {
+    pub(crate) fn from_key(key: &[u8]) -> Result<Self> {
+        Self::new(Algorithm::for_key(key)?, key)
+    }
+
+    fn new(algorithm: Algorithm, key: &[u8]) -> Result<Self> {
+        if key.len() != algorithm.key_len() {
+            anyhow::bail!(
+                "Key is {} bytes but the recording uses {:?}",
+                key.len(),
+                algorithm
+            );
+        }
+        Ok(match algorithm {
+            Algorithm::Aes128Gcm => Self::Aes128(Box::new(
+                Aes128Gcm::new_from_slice(key).expect("key length checked above"),
+            )),
+            Algorithm::Aes256Gcm => Self::Aes256(Box::new(
+                Aes256Gcm::new_from_slice(key).expect("key length checked above"),
+            )),
+        })
+    }
+
+    pub(crate) fn seal(&self, nonce: &[u8; 12], aad: &[u8], buf: &mut Vec<u8>) {
+        let nonce = Nonce::from_slice(nonce);
+        let tag = match self {
+            Self::Aes128(c) => c.encrypt_in_place_detached(nonce, aad, buf),
+            Self::Aes256(c) => c.encrypt_in_place_detached(nonce, aad, buf),
+        }
+        .expect("chunk is within AES-GCM length limits");
+        buf.extend_from_slice(&tag);
+    }
+
+    pub(crate) fn open(&self, nonce: &[u8; 12], aad: &[u8], buf: &mut Vec<u8>) -> bool {
+        if buf.len() < TAG_LEN {
+            return false;
+        }
+        let tag_pos = buf.len() - TAG_LEN;
+        let tag = *Tag::from_slice(&buf[tag_pos..]);
+        buf.truncate(tag_pos);
+        let nonce = Nonce::from_slice(nonce);
+        match self {
+            Self::Aes128(c) => c.decrypt_in_place_detached(nonce, aad, buf, &tag),
+            Self::Aes256(c) => c.decrypt_in_place_detached(nonce, aad, buf, &tag),
+        }
+        .is_ok()
+    }
+}
+
+/// Decodes a hex key as resolved by `config::Layers::build` into raw key bytes,
+/// which are wiped when dropped.
+pub fn parse_key(hex_key: &str) -> Result<Zeroizing<Vec<u8>>> {
+    let key = Zeroizing::new(hex::decode(hex_key).context("Key is not valid hex")?);
+    match key.len() {
+        16 | 32 => Ok(key),
+        n => anyhow::bail!("Invalid key length: {} bytes. Expected 16 or 32.", n),
+    }
+}
+
+/// Opens individual chunks of a container, in any order.
+pub struct Decryptor {
+    header: Header,
+    cipher: Cipher,
+}
+
+impl Decryptor {
+    /// `key` is the master key or the recipient's private key; the data key
+    /// is unwrapped with it.
+    pub fn new(header: Header, key: &[u8]) -> Result<Self> {
+        let cipher = Cipher::new(header.algorithm, &header.data_key(key)?)?;
+        Ok(Self { header, cipher })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Authenticates and decrypts chunk `index` (ciphertext plus tag).
+    pub fn open_chunk(&self, index: u32, record: &[u8], last: bool) -> Result<Vec<u8>> {
+        let mut buf = record.to_vec();
+        let nonce = self.header.nonce(index, last);
+        if !self.cipher.open(&nonce, self.header.as_bytes(), &mut buf) {
+            return Err(ContainerError::Authentication { chunk: index }.into());
+        }
+        Ok(buf)
+    }
+}
+
+/// One record as read by `ChunkReader`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Record {
+    /// An authenticated chunk; `last` marks the final one.
+    Chunk {
+        index: u32,
+        plaintext: Vec<u8>,
+        last: bool,
+    },
+    /// A full-size record that failed authentication. Reading continues with
+    /// the next one.
+    Corrupt { index: u32 },
+    /// The file ends without an authenticated final chunk, leaving `len`
+    /// bytes of an incomplete record.
+    Truncated { index: u32, len: usize },
+}
+
+/// Reads a container front to back, one record at a time.
+///
+/// Unlike `aeadfilesrc` it does not stop at the first bad chunk, so it can
+/// report every damaged chunk and salvage the rest.
+pub struct ChunkReader<R: Read> {
+    reader: R,
+    decryptor: Decryptor,
+    index: u32,
+    done: bool,
+}
+
+impl<R: Read> ChunkReader<R> {
+    pub fn new(mut reader: R, key: &[u8]) -> Result<Self> {
+        let header = Header::read_from(&mut reader)?;
+        Ok(Self {
+            reader,
+            decryptor: Decryptor::new(header, key)?,
+            index: 0,
+            done: false,
+        })
+    }
+
+    pub fn header(&self) -> &Header {
+        self.decryptor.header()
+    }
+
+    /// Fills `buf` as far as the input allows and returns the length read.
+    fn read_record(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            match self.reader.read(&mut buf[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(filled)
+    }
+}
+
+impl<R: Read> Iterator for ChunkReader<R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let mut record = vec![0u8; self.header().record_size() as usize];
+        let len = match self.read_record(&mut record) {
+            Ok(len) => len,
+            Err(e) => {
+                self.done = true;
+                return Some(Err(e));
+            }
+        };
+        let index = self.index;
+        // The final chunk is always shorter than a full one, so a full
+        // record is never the last.
+        let last = len < record.len();
+        record.truncate(len);
+        let result = match self.decryptor.open_chunk(index, &record, last) {
+            Ok(plaintext) => Record::Chunk {
+                index,
+                plaintext,
+                last,
+            },
+            Err(_) if last => Record::Truncated { index, len },
+            Err(_) => Record::Corrupt { index },
+        };
+        self.done = last;
+        self.index = match index.checked_add(1) {
+            Some(next) => next,
+            None => {
+                self.done = true;
+                index
+            }
+        };
+        Some(Ok(result))
+    }
+}
+
+fn random_key(algorithm: Algorithm) -> Zeroizing<Vec<u8>> {
+    let mut key = Zeroizing::new(vec![0u8; algorithm.key_len()]);
+    rand::rng().fill_bytes(&mut key);
+    key
+}
+
+/// Encrypting `Write` adapter producing the container format.
+///
+/// Plaintext is buffered until a full chunk is available. `finish` must be
+/// called to write the final chunk; without it readers report truncation.
+pub struct Writer<W: Write> {
+    inner: W,
+    header: Header,
+    cipher: Cipher,
+    index: u32,
+    pending: Vec<u8>,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(inner: W, key: &[u8]) -> Result<Self> {
+        Self::with_chunk_size(inner, key, DEFAULT_CHUNK_SIZE)
+    }
+
+    /// Encrypts with a fresh data key, wrapped by the master `key`.
+    pub fn with_chunk_size(inner: W, key: &[u8], chunk_size: u32) -> Result<Self> {
+        let algorithm = Algorithm::for_key(key)?;
+        let data_key = random_key(algorithm);
+        let key_block = KeyBlock::seal(key, &data_key)?;
+        Self::start(
+            inner,
+            Header::new(algorithm, chunk_size, key_block),
+            &data_key,
+        )
+    }
+
+    /// Encrypts with a fresh AES-256 data key sealed to `recipient`, an
+    /// X25519 public key. Only its private key can read the recording back.
+    pub fn for_recipient(
+        inner: W,
+        recipient: &[u8; X25519_KEY_LEN],
+        chunk_size: u32,
+    ) -> Result<Self> {
+        let algorithm = Algorithm::Aes256Gcm;
+        let data_key = random_key(algorithm);
+        let key_block = KeyBlock::seal_to(recipient, &data_key)?;
+        Self::start(
+            inner,
+            Header::new(algorithm, chunk_size, key_block),
+            &data_key,
+        )
+    }
+
+    fn start(mut inner: W, header: Header, data_key: &[u8]) -> Result<Self> {
+        let cipher = Cipher::new(header.algorithm, data_key)?;
+        inner
+            .write_all(&header.to_bytes())
+            .context("Failed to write recording header")?;
+
+        Ok(Self {
+            inner,
+            pending: Vec::with_capacity(header.chunk_size as usize),
+            header,
+            cipher,
+            index: 0,
+        })
+    }
+
+    fn write_chunk(&mut self, mut chunk: Vec<u8>, last: bool) -> io::Result<()> {
+        let nonce = self.header.nonce(self.index, last);
+        self.cipher.seal(&nonce, self.header.as_bytes(), &mut chunk);
+        self.inner.write_all(&chunk)?;
+        self.index = self
+            .index
+            .checked_add(1)
+            .ok_or_else(|| io::Error::other("Recording exceeds the maximum chunk count"))?;
+        Ok(())
+    }
+
+    /// Writes the final (short) chunk and returns the inner writer.
+    pub fn finish(mut self) -> Result<W> {
+        let last = std::mem::take(&mut self.pending);
+        self.write_chunk(last, true)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let chunk_size = self.header.chunk_size as usize;
+        self.pending.extend_from_slice(buf);
+        while self.pending.len() >= chunk_size {
+            let rest = self.pending.split_off(chunk_size);
+            let chunk = std::mem::replace(&mut self.pending, rest);
+            self.write_chunk(chunk, false)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const KEY: &str = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+    /// Fixed header plus the key block of a 256-bit data key.
+    const HEADER_LEN: usize = FIXED_HEADER_LEN + 2 + 1 + KEY_ID_LEN + WRAP_NONCE_LEN + 32 + TAG_LEN;
+
+    fn encrypt(plaintext: &[u8], chunk_size: u32) -> Vec<u8> {
+        let key = parse_key(KEY).unwrap();
+        let mut writer = Writer::with_chunk_size(Vec::new(), &key, chunk_size).unwrap();
+        writer.write_all(plaintext).unwrap();
+        writer.finish().unwrap()
+    }
+
+    fn decrypt_with(ciphertext: &[u8], key: &str) -> Result<Vec<u8>> {
+        let key = parse_key(key).unwrap();
+        let mut cursor = Cursor::new(ciphertext);
+        let header = Header::read_from(&mut cursor)?;
+        let record_size = header.record_size() as usize;
+        let decryptor = Decryptor::new(header, &key)?;
+
+        let body = &ciphertext[cursor.position() as usize..];
+        let mut out = Vec::new();
+        for (index, record) in body.chunks(record_size).enumerate() {
+            let last = record.len() < record_size;
+            out.extend(decryptor.open_chunk(index as u32, record, last)?);
+        }
+        Ok(out)
+    }
+
+    fn decrypt(ciphertext: &[u8]) -> Result<Vec<u8>> {
+        decrypt_with(ciphertext, KEY)
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let plaintext: Vec<u8> = (0..10_000u32).map(|i| i as u8).collect();
+        for chunk_size in [16, 100, 10_000, 65536] {
+            let ciphertext = encrypt(&plaintext, chunk_size);
+            assert_eq!(decrypt(&ciphertext).unwrap(), plaintext);
+        }
+    }
+
+    #[test]
+    fn test_final_chunk_is_always_short() {
+        // 300 bytes in 100-byte chunks: three full chunks and an empty final one.
+        let ciphertext = encrypt(&[7u8; 300], 100);
+        assert_eq!(ciphertext.len(), HEADER_LEN + 3 * 116 + TAG_LEN);
+        assert_eq!(decrypt(&ciphertext).unwrap(), vec![7u8; 300]);
+    }
+
+    #[test]
+    fn test_aes128_key() {
+        let key = "00112233445566778899aabbccddeeff";
+        let mut writer = Writer::new(Vec::new(), &parse_key(key).unwrap()).unwrap();
+        writer.write_all(b"hello").unwrap();
+        let ciphertext = writer.finish().unwrap();
+
+        let header = Header::read_from(&mut Cursor::new(&ciphertext)).unwrap();
+        assert_eq!(header.algorithm, Algorithm::Aes128Gcm);
+        assert_eq!(header.chunk_size, DEFAULT_CHUNK_SIZE);
+        assert_eq!(decrypt_with(&ciphertext, key).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_key_id_selects_key() {
+        let other = "ffeeddccbbaa99887766554433221100ffeeddccbbaa99887766554433221100";
+        let ciphertext = encrypt(b"hello", 100);
+        let header = Header::read_from(&mut Cursor::new(&ciphertext)).unwrap();
+        let key_id = KeyId::of(&parse_key(KEY).unwrap());
+        assert_eq!(header.key_id, key_id);
+        assert_eq!(key_id.to_string().len(), 2 * KEY_ID_LEN);
+
+        let keys = [other.to_string(), KEY.to_string()];
+        assert_eq!(header.select_key(&keys).unwrap(), KEY);
+        let err = header.select_key(&keys[..1]).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ContainerError>(),
+            Some(&ContainerError::UnknownKeyId(key_id))
+        );
+        assert_eq!(err.to_string(), format!("No key for key-id {}", key_id));
+    }
+
+    #[test]
+    fn test_skips_unknown_extensions() {
+        let ciphertext = encrypt(&[3u8; 250], 100);
+        let mut extended = ciphertext[..FIXED_HEADER_LEN].to_vec();
+        extended[FIXED_HEADER_LEN - 2..].copy_from_slice(&5u16.to_be_bytes());
+        extended.extend_from_slice(&[9, 0, 2, 0xaa, 0xbb]);
+        extended.extend_from_slice(&ciphertext[FIXED_HEADER_LEN..HEADER_LEN]);
+        let header = Header::read_from(&mut Cursor::new(&extended)).unwrap();
+        assert_eq!(header.key_id, KeyId::of(&parse_key(KEY).unwrap()));
+        assert_eq!(header.encoded_len(), (HEADER_LEN + 5) as u64);
+
+        // An extension longer than the extension area is rejected.
+        extended[FIXED_HEADER_LEN + 2] = 3;
+        assert!(Header::read_from(&mut Cursor::new(&extended)).is_err());
+
+        let mut unsupported = ciphertext.clone();
+        unsupported[MAGIC.len()] = VERSION + 1;
+        assert!(Header::read_from(&mut Cursor::new(&unsupported)).is_err());
+    }
+
+    #[test]
+    fn test_nonce_prefix_is_random_per_file() {
+        let a = encrypt(b"same", 100);
+        let b = encrypt(b"same", 100);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn test_detects_tampering() {
+        let mut ciphertext = encrypt(&[1u8; 1000], 100);
+        ciphertext[HEADER_LEN + 250] ^= 0x01;
+        let err = decrypt(&ciphertext).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ContainerError>(),
+            Some(&ContainerError::Authentication { chunk: 2 })
+        );
+    }
+
+    #[test]
+    fn test_detects_header_tampering() {
+        let mut ciphertext = encrypt(&[1u8; 1000], 100);
+        ciphertext[MAGIC.len() + 6] ^= 0x01; // first byte of the nonce prefix
+        let err = decrypt(&ciphertext).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ContainerError>(),
+            Some(&ContainerError::Authentication { chunk: 0 })
+        );
+    }
+
+    #[test]
+    fn test_detects_reordered_chunks() {
+        let mut ciphertext = encrypt(&[1u8; 1000], 100);
+        let (a, b) = (HEADER_LEN, HEADER_LEN + 116);
+        let first: Vec<u8> = ciphertext[a..b].to_vec();
+        ciphertext.copy_within(b..b + 116, a);
+        ciphertext[b..b + 116].copy_from_slice(&first);
+        assert!(decrypt(&ciphertext).is_err());
+    }
+
+    #[test]
+    fn test_wrong_key_fails_authentication() {
+        let ciphertext = encrypt(b"secret footage", 100);
+        let err = decrypt_with(&ciphertext, &"ff".repeat(32)).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ContainerError>(),
+            Some(&ContainerError::KeyUnwrap)
+        );
+
+        // So does a tampered key block.
+        let mut tampered = ciphertext.clone();
+        tampered[HEADER_LEN - 1] ^= 0x01;
+        let err = decrypt(&tampered).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ContainerError>(),
+            Some(&ContainerError::KeyUnwrap)
+        );
+    }
+
+    #[test]
+    fn test_data_key_is_random_per_file() {
+        let master = parse_key(KEY).unwrap();
+        let read = |ciphertext: &[u8]| Header::read_from(&mut Cursor::new(ciphertext)).unwrap();
+        let (a, b) = (read(&encrypt(b"a", 100)), read(&encrypt(b"b", 100)));
+        assert_eq!(a.encoded_len(), HEADER_LEN as u64);
+        let key = a.data_key(&master).unwrap();
+        assert_eq!(key.len(), 32);
+        assert_ne!(key, master);
+        assert_ne!(key, b.data_key(&master).unwrap());
+    }
+
+    #[test]
+    fn test_rewrap() {
+        let other = "ffeeddccbbaa99887766554433221100ffeeddccbbaa99887766554433221100";
+        let (old, new) = (parse_key(KEY).unwrap(), parse_key(other).unwrap());
+        let plaintext = [5u8; 1000];
+        let mut ciphertext = encrypt(&plaintext, 100);
+
+        let mut header = Header::read_from(&mut Cursor::new(&ciphertext)).unwrap();
+        let data_key = header.data_key(&old).unwrap();
+        assert!(header.rewrap(&new, &new).is_err());
+        header.rewrap(&old, &new).unwrap();
+        assert_eq!(header.key_id, KeyId::of(&new));
+        assert_eq!(header.data_key(&new).unwrap(), data_key);
+
+        // Only the key block changes; the chunks are untouched.
+        let bytes = header.to_bytes();
+        assert_eq!(bytes.len(), HEADER_LEN);
+        ciphertext[..HEADER_LEN].copy_from_slice(&bytes);
+        assert_eq!(decrypt_with(&ciphertext, other).unwrap(), plaintext);
+        assert!(decrypt(&ciphertext).is_err());
+    }
+
+    #[test]
+    fn test_recipient() {
+        let identity = [0x42u8; X25519_KEY_LEN];
+        let recipient = recipient_of(&identity);
+        let mut writer = Writer::for_recipient(Vec::new(), &recipient, 100).unwrap();
+        writer.write_all(&[4u8; 250]).unwrap();
+        let ciphertext = writer.finish().unwrap();
+
+        let mut header = Header::read_from(&mut Cursor::new(&ciphertext)).unwrap();
+        assert!(header.is_sealed_to_recipient());
+        assert_eq!(header.algorithm, Algorithm::Aes256Gcm);
+        assert_eq!(header.key_id, KeyId::of(&recipient));
+        assert_eq!(header.encoded_len(), (HEADER_LEN + X25519_KEY_LEN) as u64);
+
+        // The identity is picked by the recipient's key ID and opens the
+        // file; the recipient public key and other keys do not.
+        let keys = [KEY.to_string(), hex::encode(identity)];
+        assert_eq!(header.select_key(&keys).unwrap(), keys[1]);
+        assert_eq!(decrypt_with(&ciphertext, &keys[1]).unwrap(), vec![4u8; 250]);
+        for key in [KEY.to_string(), hex::encode(recipient)] {
+            let err = decrypt_with(&ciphertext, &key).unwrap_err();
+            assert_eq!(
+                err.downcast_ref::<ContainerError>(),
+                Some(&ContainerError::KeyUnwrap)
+            );
+        }
+        assert!(header.rewrap(&identity, &parse_key(KEY).unwrap()).is_err());
+
+        // A recorder cannot seal to a low-order point.
+        assert!(Writer::for_recipient(Vec::new(), &[0u8; X25519_KEY_LEN], 100).is_err());
+    }
+
+    #[test]
+    fn test_rejects_bad_header() {
+        let err = Header::read_from(&mut Cursor::new(b"not a recording at all")).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ContainerError>(),
+            Some(ContainerError::InvalidHeader(_))
+        ));
+    }
+
+    fn read_records(ciphertext: &[u8]) -> Vec<Record> {
+        ChunkReader::new(Cursor::new(ciphertext), &parse_key(KEY).unwrap())
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_chunk_reader() {
+        let ciphertext = encrypt(&[3u8; 250], 100);
+        let records = read_records(&ciphertext);
+        assert_eq!(records.len(), 3);
+        assert!(matches!(
+            records[0],
+            Record::Chunk {
+                index: 0,
+                last: false,
+                ..
+            }
+        ));
+        assert_eq!(
+            records[2],
+            Record::Chunk {
+                index: 2,
+                plaintext: vec![3u8; 50],
+                last: true
+            }
+        );
+    }
+
+    #[test]
+    fn test_chunk_reader_reports_damage_and_continues() {
+        let mut ciphertext = encrypt(&[3u8; 450], 100);
+        ciphertext[HEADER_LEN + 116 + 5] ^= 0x01;
+        // Cut into the final record.
+        ciphertext.truncate(ciphertext.len() - 10);
+
+        let records = read_records(&ciphertext);
+        assert!(matches!(records[0], Record::Chunk { index: 0, .. }));
+        assert_eq!(records[1], Record::Corrupt { index: 1 });
+        assert!(matches!(
+            records[3],
+            Record::Chunk {
+                index: 3,
+                last: false,
+                ..
+            }
+        ));
+        assert_eq!(records[4], Record::Truncated { index: 4, len: 56 });
+        assert_eq!(records.len(), 5);
+
+        // Missing the final record altogether.
+        let ciphertext = encrypt(&[3u8; 200], 100);
+        let records = read_records(&ciphertext[..HEADER_LEN + 2 * 116]);
+        assert_eq!(records[2], Record::Truncated { index: 2, len: 0 });
+    }
+
+    #[test]
+    fn test_detect_format() {
+        let mut aead = tempfile::NamedTempFile::new().unwrap();
+        aead.write_all(&encrypt(b"x", 100)).unwrap();
+        assert_eq!(detect_format(aead.path()).unwrap(), Format::Aead);
+
+        let mut legacy = tempfile::NamedTempFile::new().unwrap();
+        legacy.write_all(&[0xAB; 64]).unwrap();
+        assert_eq!(detect_format(legacy.path()).unwrap(), Format::LegacyCbc);
+    }
+}
@@ -0,0 +1,421 @@
+//! Keyboard controls for `play`.
+//!
+//! Keys are read from the terminal on a background thread and applied to
+//! the pipeline directly; `aeadfilesrc` is seekable, so `tsdemux` can jump
+//! anywhere in a recording or a sequence of segments. The terminal is only
+//! switched out of line mode (no canonical input, no echo); output
+//! processing and signals stay on, so log lines and Ctrl+C behave as usual.
+
+use anyhow::{Context, Result};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use std::io::{IsTerminal, Write};
+use std::os::fd::AsRawFd;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Seek distance for Up/Down.
+const BIG_STEP: Duration = Duration::from_secs(60);
+
+pub const HELP: &str = "Controls: space pause/resume, ←/→ seek, ↓/↑ seek 1 min, \
+    . next frame, g jump to time, q quit";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    TogglePause,
+    /// Relative seek in nanoseconds.
+    SeekBy(i64),
+    SeekTo(gst::ClockTime),
+    StepFrame,
+    Quit,
+}
+
+/// Turns key presses into commands. `g` starts a prompt for a position,
+/// which is confirmed with Enter or cancelled with Esc.
+pub struct KeyMap {
+    seek_step: Duration,
+    jump: Option<String>,
+}
+
+impl KeyMap {
+    pub fn new(seek_step: Duration) -> Self {
+        Self {
+            seek_step,
+            jump: None,
+        }
+    }
+
+    pub fn handle(&mut self, key: KeyEvent) -> Option<Command> {
+        if let Some(input) = &mut self.jump {
+            match key.code {
+                KeyCode::Char(c) if c.is_ascii_digit() || c == ':' || c == '.' => {
+                    input.push(c);
+                    echo(&c.to_string());
+                }
+                KeyCode::Backspace if input.pop().is_some() => echo("\x08 \x08"),
+                KeyCode::Enter => {
+                    let input = self.jump.take().unwrap_or_default();
+                    echo("\n");
+                    match parse_position(&input) {
+                        Some(position) => return Some(Command::SeekTo(position)),
+                        None => warn!("Invalid position: {:?}", input),
+                    }
+                }
+                KeyCode::Esc => {
+                    self.jump = None;
+                    echo("\n");
+                }
+                _ => (),
+            }
+            return None;
+        }
+
+        let step = self.seek_step.as_nanos() as i64;
+        let big_step = BIG_STEP.as_nanos() as i64;
+        match key.code {
+            KeyCode::Char(' ') | KeyCode::Char('p') => Some(Command::TogglePause),
+            KeyCode::Right => Some(Command::SeekBy(step)),
+            KeyCode::Left => Some(Command::SeekBy(-step)),
+            KeyCode::Up => Some(Command::SeekBy(big_step)),
+            KeyCode::Down => Some(Command::SeekBy(-big_step)),
+            KeyCode::Char('.') => Some(Command::StepFrame),
+            KeyCode::Char('g') => {
+                self.jump = Some(String::new());
+                echo("Jump to [[hh:]mm:]ss: ");
+                None
+            }
+            KeyCode::Char('q') | KeyCode::Esc => Some(Command::Quit),
+            _ => None,
+        }
+    }
+}
+
+fn echo(text: &str) {
+    let mut stderr = std::io::stderr();
+    let _ = stderr.write_all(text.as_bytes());
+    let _ = stderr.flush();
+}
+
+/// Parses a position in the recording: `ss`, `mm:ss` or `hh:mm:ss`, with
+/// optional fractional seconds.
+pub fn parse_position(input: &str) -> Option<gst::ClockTime> {
+    let mut seconds = 0.0;
+    let fields: Vec<&str> = input.split(':').collect();
+    if fields.len() > 3 {
+        return None;
+    }
+    for (i, field) in fields.iter().enumerate() {
+        let value: f64 = field.parse().ok()?;
+        // Only the last field may have a fraction or exceed 59.
+        if i + 1 < fields.len() && (value.fract() != 0.0 || (i > 0 && value >= 60.0)) {
+            return None;
+        }
+        seconds = seconds * 60.0 + value;
+    }
+    if !seconds.is_finite() || seconds < 0.0 {
+        return None;
+    }
+    Some(gst::ClockTime::from_nseconds((seconds * 1e9) as u64))
+}
+
+pub fn apply(pipeline: &gst::Pipeline, command: Command) -> Result<()> {
+    match command {
+        Command::TogglePause => {
+            let (_, current, pending) = pipeline.state(gst::ClockTime::ZERO);
+            let target = match pending {
+                gst::State::VoidPending => current,
+                pending => pending,
+            };
+            let (next, label) = if target == gst::State::Playing {
+                (gst::State::Paused, "Paused")
+            } else {
+                (gst::State::Playing, "Playing")
+            };
+            pipeline
+                .set_state(next)
+                .with_context(|| format!("Failed to set pipeline to {:?}", next))?;
+            info!("{}", label);
+        }
+        Command::SeekBy(delta) => {
+            let position = pipeline
+                .query_position::<gst::ClockTime>()
+                .context("Current position is unknown")?;
+            let target = (position.nseconds() as i64).saturating_add(delta).max(0);
+            seek(pipeline, gst::ClockTime::from_nseconds(target as u64))?;
+        }
+        Command::SeekTo(position) => seek(pipeline, position)?,
+        Command::StepFrame => {
+            let (_, current, _) = pipeline.state(gst::ClockTime::ZERO);
+            if current == gst::State::Playing {
+                pipeline
+                    .set_state(gst::State::Paused)
+                    .context("Failed to pause pipeline")?;
+                pipeline.state(gst::ClockTime::from_seconds(5)).0?;
+            }
+            let step = gst::event::Step::new(gst::format::Buffers::ONE, 1.0, true, false);
+            if !pipeline.send_event(step) {
+                anyhow::bail!("Pipeline does not support frame stepping");
+            }
+        }
+        Command::Quit => {
+            info!("Stopping playback");
+            pipeline.send_event(gst::event::Eos::new());
+        }
+    }
+    Ok(())
+}
+
+fn seek(pipeline: &gst::Pipeline, position: gst::ClockTime) -> Result<()> {
+    let position = match pipeline.query_duration::<gst::ClockTime>() {
+        Some(duration) => position.min(duration),
+        None => position,
+    };
+    pipeline
+        .seek_simple(gst::SeekFlags::FLUSH | gst::SeekFlags::ACCURATE, position)
+        .context("Seek failed")?;
+    info!("Seeked to {:.1}", position);
+    Ok(())
+}
+
+/// Restores the terminal's line mode when dropped.
+struct TerminalGuard {
+    fd: i32,
+    saved: libc::termios,
+}
+
+impl TerminalGuard {
+    fn new() -> std::io::Result<Self> {
+        let fd = std::io::stdin().as_raw_fd();
+        // SAFETY: `termios` is plain data and `fd` stays open for the
+        // lifetime of the process.
+        unsafe {
+            let mut saved: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(fd, &mut saved) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            let mut keys = saved;
+            keys.c_lflag &= !(libc::ICANON | libc::ECHO);
+            if libc::tcsetattr(fd, libc::TCSANOW, &keys) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(Self { fd, saved })
+        }
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        // SAFETY: see `new`.
+        unsafe {
+            libc::tcsetattr(self.fd, libc::TCSANOW, &self.saved);
+        }
+    }
+}
+
+/// Reads keys until dropped. Must be dropped before the pipeline stops.
+pub struct Controls {
+    stop: Arc<AtomicBool>,
+    handle: Option<thread::JoinHandle<()>>,
+    _terminal: TerminalGuard,
+}
+
+impl Drop for Controls {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Starts reading keys for `pipeline`. Returns `None` when stdin is not a
+/// terminal, e.g. when run from a script.
+pub fn spawn(pipeline: &gst::Pipeline, seek_step: Duration) -> Result<Option<Controls>> {
+    if !std::io::stdin().is_terminal() {
+        return Ok(None);
+    }
+    let terminal = TerminalGuard::new().context("Failed to configure terminal")?;
+    let stop = Arc::new(AtomicBool::new(false));
+
+    let pipeline_weak = pipeline.downgrade();
+    let thread_stop = stop.clone();
+    let handle = thread::spawn(move || {
+        let mut keys = KeyMap::new(seek_step);
+        while !thread_stop.load(Ordering::Relaxed) {
+            match event::poll(Duration::from_millis(100)) {
+                Ok(true) => (),
+                Ok(false) => continue,
+                Err(e) => {
+                    warn!("Keyboard controls stopped: {}", e);
+                    return;
+                }
+            }
+            let Ok(Event::Key(key)) = event::read() else {
+                continue;
+            };
+            if key.kind == KeyEventKind::Release {
+                continue;
+            }
+            let (Some(command), Some(pipeline)) = (keys.handle(key), pipeline_weak.upgrade())
+            else {
+                continue;
+            };
+            if let Err(e) = apply(&pipeline, command) {
+                warn!("{}", e);
+            }
+        }
+    });
+
+    info!("{}", HELP);
+    Ok(Some(Controls {
+        stop,
+        handle: Some(handle),
+        _terminal: terminal,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::KeyModifiers;
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn test_parse_position() {
+        assert_eq!(parse_position("75"), Some(gst::ClockTime::from_seconds(75)));
+        assert_eq!(
+            parse_position("1:02:03"),
+            Some(gst::ClockTime::from_seconds(3723))
+        );
+        assert_eq!(
+            parse_position("02:03.5"),
+            Some(gst::ClockTime::from_mseconds(123_500))
+        );
+        assert_eq!(parse_position(""), None);
+        assert_eq!(parse_position("1:60:00"), None);
+        assert_eq!(parse_position("1.5:00"), None);
+        assert_eq!(parse_position("1:2:3:4"), None);
+    }
+
+    #[test]
+    fn test_key_map() {
+        let mut keys = KeyMap::new(Duration::from_secs(5));
+        assert_eq!(
+            keys.handle(key(KeyCode::Char(' '))),
+            Some(Command::TogglePause)
+        );
+        assert_eq!(
+            keys.handle(key(KeyCode::Left)),
+            Some(Command::SeekBy(-5_000_000_000))
+        );
+        assert_eq!(
+            keys.handle(key(KeyCode::Up)),
+            Some(Command::SeekBy(60_000_000_000))
+        );
+        assert_eq!(
+            keys.handle(key(KeyCode::Char('.'))),
+            Some(Command::StepFrame)
+        );
+
+        // Jump prompt: keys are taken as input until Enter.
+        assert_eq!(keys.handle(key(KeyCode::Char('g'))), None);
+        for c in "1:3q0".chars() {
+            assert_eq!(keys.handle(key(KeyCode::Char(c))), None);
+        }
+        assert_eq!(keys.handle(key(KeyCode::Backspace)), None);
+        assert_eq!(
+            keys.handle(key(KeyCode::Enter)),
+            Some(Command::SeekTo(gst::ClockTime::from_seconds(63)))
+        );
+        assert_eq!(keys.handle(key(KeyCode::Char('q'))), Some(Command::Quit));
+    }
+
+    #[test]
+    #[ignore = "needs videotestsrc, x264enc, mpegtsmux, tsdemux and an H.264 decoder"]
+    fn test_apply_to_encrypted_recording() {
+        gst::init().unwrap();
+        crate::elements::register().unwrap();
+        let key = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+        let dir = tempfile::tempdir().unwrap();
+        let recording = dir.path().join("clip.ts.enc");
+
+        // Ten seconds at 25 fps with a keyframe every second.
+        let record = gst::parse::launch(&format!(
+            "videotestsrc num-buffers=250 ! video/x-raw,width=320,height=240,framerate=25/1 \
+             ! x264enc key-int-max=25 ! h264parse ! mpegtsmux \
+             ! aeadfilesink name=sink location={}",
+            recording.display()
+        ))
+        .unwrap()
+        .dynamic_cast::<gst::Pipeline>()
+        .unwrap();
+        record.by_name("sink").unwrap().set_property("key", key);
+        record.set_state(gst::State::Playing).unwrap();
+        let message = record
+            .bus()
+            .unwrap()
+            .timed_pop_filtered(
+                gst::ClockTime::from_seconds(30),
+                &[gst::MessageType::Eos, gst::MessageType::Error],
+            )
+            .unwrap();
+        assert_eq!(message.type_(), gst::MessageType::Eos);
+        record.set_state(gst::State::Null).unwrap();
+
+        let pipeline = gst::parse::launch(&format!(
+            "aeadfilesrc name=src location={} ! tsdemux ! h264parse ! decodebin ! fakesink sync=true",
+            recording.display()
+        ))
+        .unwrap()
+        .dynamic_cast::<gst::Pipeline>()
+        .unwrap();
+        pipeline.by_name("src").unwrap().set_property("key", key);
+        let wait = |pipeline: &gst::Pipeline| {
+            pipeline.state(gst::ClockTime::from_seconds(5)).0.unwrap();
+        };
+        let position =
+            |pipeline: &gst::Pipeline| pipeline.query_position::<gst::ClockTime>().unwrap();
+
+        pipeline.set_state(gst::State::Paused).unwrap();
+        wait(&pipeline);
+
+        apply(&pipeline, Command::SeekTo(gst::ClockTime::from_seconds(7))).unwrap();
+        wait(&pipeline);
+        assert_eq!(position(&pipeline), gst::ClockTime::from_seconds(7));
+
+        apply(&pipeline, Command::SeekBy(-4_500_000_000)).unwrap();
+        wait(&pipeline);
+        assert_eq!(position(&pipeline), gst::ClockTime::from_mseconds(2500));
+
+        apply(&pipeline, Command::StepFrame).unwrap();
+        wait(&pipeline);
+        assert_eq!(position(&pipeline), gst::ClockTime::from_mseconds(2540));
+
+        // Seeking past the end lands on the end of the recording.
+        apply(&pipeline, Command::SeekTo(gst::ClockTime::from_seconds(60))).unwrap();
+        wait(&pipeline);
+        let end = position(&pipeline);
+        assert!(end >= gst::ClockTime::from_seconds(9) && end <= gst::ClockTime::from_seconds(10));
+
+        apply(&pipeline, Command::SeekTo(gst::ClockTime::from_seconds(1))).unwrap();
+        wait(&pipeline);
+        apply(&pipeline, Command::TogglePause).unwrap();
+        wait(&pipeline);
+        assert_eq!(pipeline.current_state(), gst::State::Playing);
+        thread::sleep(Duration::from_millis(300));
+        assert!(position(&pipeline) > gst::ClockTime::from_seconds(1));
+
+        apply(&pipeline, Command::TogglePause).unwrap();
+        wait(&pipeline);
+        assert_eq!(pipeline.current_state(), gst::State::Paused);
+
+        pipeline.set_state(gst::State::Null).unwrap();
+    }
+}
@@ -0,0 +1,239 @@
+//! `aeadfilesink`: writes its input to a file in the chunked AEAD container.
+//!
+//! It exposes a `location` property like `filesink`, so `splitmuxsink` can
+//! drive it directly and every rotated segment gets its own header and nonce.
+//! With `recipient` set instead of `key`, each file's data key is sealed to
+//! that X25519 public key and the element holds nothing that decrypts it.
+
+use gstreamer as gst;
+use gstreamer::glib;
+use gstreamer_base as gst_base;
+
+glib::wrapper! {
+    pub struct AeadFileSink(ObjectSubclass<imp::AeadFileSink>)
+        @extends gst_base::BaseSink, gst::Element, gst::Object;
+}
+
+mod imp {
+    use super::*;
+    use crate::container;
+    use crate::secrets::Secret;
+    use gst::prelude::*;
+    use gst::subclass::prelude::*;
+    use gst_base::subclass::prelude::*;
+    use std::fs::File;
+    use std::io::Write;
+    use std::sync::{LazyLock, Mutex};
+
+    #[derive(Default)]
+    struct Settings {
+        location: Option<String>,
+        key: Option<Secret>,
+        recipient: Option<String>,
+    }
+
+    #[derive(Default)]
+    pub struct AeadFileSink {
+        settings: Mutex<Settings>,
+        writer: Mutex<Option<container::Writer<File>>>,
+    }
+
+    impl AeadFileSink {
+        /// Writes the final chunk and syncs the file. A no-op once finished.
+        fn finish(&self) -> Result<(), gst::ErrorMessage> {
+            if let Some(writer) = self.writer.lock().unwrap().take() {
+                let file = writer.finish().map_err(|e| {
+                    gst::error_msg!(
+                        gst::ResourceError::Write,
+                        ("Failed to finalize recording: {}", e)
+                    )
+                })?;
+                file.sync_all().map_err(|e| {
+                    gst::error_msg!(gst::ResourceError::Sync, ("Failed to sync file: {}", e))
+                })?;
+            }
+            Ok(())
+        }
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for AeadFileSink {
+        const NAME: &'static str = "EeeAeadFileSink";
+        type Type = super::AeadFileSink;
+        type ParentType = gst_base::BaseSink;
+    }
+
+    impl ObjectImpl for AeadFileSink {
+        fn properties() -> &'static [glib::ParamSpec] {
+            static PROPERTIES: LazyLock<Vec<glib::ParamSpec>> = LazyLock::new(|| {
+                vec![
+                    glib::ParamSpecString::builder("location")
+                        .nick("File Location")
+                        .blurb("Location of the file to write")
+                        .build(),
+                    glib::ParamSpecString::builder("key")
+                        .nick("Key")
+                        .blurb("Hex-encoded AES-128 or AES-256 key")
+                        .write_only()
+                        .build(),
+                    glib::ParamSpecString::builder("recipient")
+                        .nick("Recipient")
+                        .blurb(
+                            "Hex-encoded X25519 public key to seal recordings to, instead of key",
+                        )
+                        .build(),
+                ]
+            });
+            PROPERTIES.as_ref()
+        }
+
+        fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+            let mut settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "location" => settings.location = value.get().expect("type checked upstream"),
+                "key" => {
+                    settings.key = value
+                        .get::<Option<String>>()
+                        .expect("type checked upstream")
+                        .map(Secret::from)
+                }
+                "recipient" => settings.recipient = value.get().expect("type checked upstream"),
+                _ => unreachable!(),
+            }
+        }
+
+        fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+            let settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "location" => settings.location.to_value(),
+                "recipient" => settings.recipient.to_value(),
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    impl GstObjectImpl for AeadFileSink {}
+
+    impl ElementImpl for AeadFileSink {
+        fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+            static ELEMENT_METADATA: LazyLock<gst::subclass::ElementMetadata> =
+                LazyLock::new(|| {
+                    gst::subclass::ElementMetadata::new(
+                        "AEAD File Sink",
+                        "Sink/File",
+                        "Encrypts a stream with chunked AES-GCM and writes it to a file",
+                        "eightyeightyeight",
+                    )
+                });
+            Some(&*ELEMENT_METADATA)
+        }
+
+        fn pad_templates() -> &'static [gst::PadTemplate] {
+            static PAD_TEMPLATES: LazyLock<Vec<gst::PadTemplate>> = LazyLock::new(|| {
+                vec![
+                    gst::PadTemplate::new(
+                        "sink",
+                        gst::PadDirection::Sink,
+                        gst::PadPresence::Always,
+                        &gst::Caps::new_any(),
+                    )
+                    .unwrap(),
+                ]
+            });
+            PAD_TEMPLATES.as_ref()
+        }
+    }
+
+    impl BaseSinkImpl for AeadFileSink {
+        fn start(&self) -> Result<(), gst::ErrorMessage> {
+            let settings = self.settings.lock().unwrap();
+            let location = settings.location.as_deref().ok_or_else(|| {
+                gst::error_msg!(gst::ResourceError::Settings, ("No location set"))
+            })?;
+            let recipient = settings
+                .recipient
+                .as_deref()
+                .map(|recipient| {
+                    container::parse_x25519_key(recipient).map_err(|e| {
+                        gst::error_msg!(gst::ResourceError::Settings, ("Invalid recipient: {}", e))
+                    })
+                })
+                .transpose()?;
+            // The key is not needed when sealing to a recipient.
+            let key = match recipient {
+                Some(_) => Default::default(),
+                None => settings
+                    .key
+                    .as_ref()
+                    .map(Secret::expose)
+                    .ok_or_else(|| gst::error_msg!(gst::ResourceError::Settings, ("No key set")))
+                    .and_then(|key| {
+                        container::parse_key(key).map_err(|e| {
+                            gst::error_msg!(gst::ResourceError::Settings, ("Invalid key: {}", e))
+                        })
+                    })?,
+            };
+
+            let file = File::create(location).map_err(|e| {
+                gst::error_msg!(
+                    gst::ResourceError::OpenWrite,
+                    ("Could not open file {} for writing: {}", location, e)
+                )
+            })?;
+            let writer = match &recipient {
+                Some(recipient) => {
+                    container::Writer::for_recipient(file, recipient, container::DEFAULT_CHUNK_SIZE)
+                }
+                None => container::Writer::new(file, &key),
+            };
+            let writer = writer.map_err(|e| {
+                gst::error_msg!(
+                    gst::ResourceError::Write,
+                    ("Failed to write header to {}: {}", location, e)
+                )
+            })?;
+
+            *self.writer.lock().unwrap() = Some(writer);
+            Ok(())
+        }
+
+        fn stop(&self) -> Result<(), gst::ErrorMessage> {
+            // Normally finished on EOS already; this covers a plain state
+            // change to NULL so the file is still closed cleanly.
+            self.finish()
+        }
+
+        fn render(&self, buffer: &gst::Buffer) -> Result<gst::FlowSuccess, gst::FlowError> {
+            let mut writer = self.writer.lock().unwrap();
+            let Some(writer) = writer.as_mut() else {
+                gst::element_imp_error!(self, gst::CoreError::Failed, ("Not started"));
+                return Err(gst::FlowError::Error);
+            };
+
+            let map = buffer.map_readable().map_err(|_| {
+                gst::element_imp_error!(self, gst::CoreError::Failed, ("Failed to map buffer"));
+                gst::FlowError::Error
+            })?;
+            writer.write_all(&map).map_err(|e| {
+                gst::element_imp_error!(
+                    self,
+                    gst::ResourceError::Write,
+                    ("Failed to write: {}", e)
+                );
+                gst::FlowError::Error
+            })?;
+
+            Ok(gst::FlowSuccess::Ok)
+        }
+
+        fn event(&self, event: gst::Event) -> bool {
+            if let gst::EventView::Eos(..) = event.view()
+                && let Err(err) = self.finish()
+            {
+                self.post_error_message(err);
+                return false;
+            }
+            self.parent_event(event)
+        }
+    }
+}
@@ -0,0 +1,427 @@
+//! `aeadfilesrc`: reads a chunked AEAD recording and outputs the plaintext.
+//!
+//! Chunks have a fixed size, so any plaintext byte offset maps directly to a
+//! chunk on disk. The source is therefore seekable and reports its size like
+//! `filesrc`, which lets demuxers seek in encrypted recordings.
+//!
+//! With `locations` set instead of `location`, the files are read back to
+//! back as one byte stream. Rotated MPEG-TS segments concatenate cleanly, so
+//! a demuxer sees a single continuous, seekable recording.
+//!
+//! Each file is decrypted with `key` or, if its header names another key ID,
+//! with the matching entry of `keyring`.
+
+use gstreamer as gst;
+use gstreamer::glib;
+use gstreamer_base as gst_base;
+
+glib::wrapper! {
+    pub struct AeadFileSrc(ObjectSubclass<imp::AeadFileSrc>)
+        @extends gst_base::BaseSrc, gst::Element, gst::Object;
+}
+
+mod imp {
+    use super::*;
+    use crate::container::{self, Decryptor, Header};
+    use crate::secrets::Secret;
+    use gst::prelude::*;
+    use gst::subclass::prelude::*;
+    use gst_base::subclass::base_src::CreateSuccess;
+    use gst_base::subclass::prelude::*;
+    use std::fs::File;
+    use std::io::{Read, Seek, SeekFrom};
+    use std::sync::{LazyLock, Mutex};
+
+    #[derive(Default)]
+    struct Settings {
+        location: Option<String>,
+        locations: Vec<String>,
+        key: Option<Secret>,
+        keyring: Vec<Secret>,
+    }
+
+    impl Settings {
+        fn locations(&self) -> Vec<String> {
+            if self.locations.is_empty() {
+                self.location.iter().cloned().collect()
+            } else {
+                self.locations.clone()
+            }
+        }
+    }
+
+    /// One encrypted file.
+    struct Part {
+        location: String,
+        file: File,
+        decryptor: Decryptor,
+        header_len: u64,
+        /// Chunks stored before the final one, all `chunk_size` long.
+        full_chunks: u64,
+        /// Plaintext length of the final chunk, if it authenticated.
+        last_len: Option<u64>,
+        /// Plaintext bytes available to downstream.
+        size: u64,
+        cached: Option<(u64, Vec<u8>)>,
+    }
+
+    impl Part {
+        fn open(location: &str, keys: &[Secret]) -> anyhow::Result<Self> {
+            let mut file = File::open(location)?;
+            let file_len = file.metadata()?.len();
+            let header = Header::read_from(&mut file)?;
+            let key = container::parse_key(header.select_key(keys)?)?;
+            let header_len = header.encoded_len();
+            let chunk_size = header.chunk_size as u64;
+            let record_size = header.record_size();
+            let decryptor = Decryptor::new(header, &key)?;
+
+            let body_len = file_len - header_len;
+            let full_chunks = body_len / record_size;
+            let tail_len = body_len % record_size;
+            if full_chunks >= u32::MAX as u64 {
+                anyhow::bail!("Recording has too many chunks");
+            }
+
+            let mut part = Self {
+                location: location.to_string(),
+                file,
+                decryptor,
+                header_len,
+                full_chunks,
+                last_len: None,
+                size: full_chunks * chunk_size,
+                cached: None,
+            };
+
+            // Fail early with a clear error when the key is wrong instead of
+            // handing garbage or a late error to the demuxer.
+            if full_chunks > 0 {
+                part.load_chunk(0)?;
+            }
+
+            // A tail that does not authenticate as the final chunk is a
+            // partially written record, e.g. after a power loss.
+            let mut tail = vec![0u8; tail_len as usize];
+            part.read_record(full_chunks, &mut tail)?;
+            if let Ok(plaintext) = part.decryptor.open_chunk(full_chunks as u32, &tail, true) {
+                part.last_len = Some(plaintext.len() as u64);
+                part.size += plaintext.len() as u64;
+                part.cached = Some((full_chunks, plaintext));
+            }
+
+            Ok(part)
+        }
+
+        fn chunk_size(&self) -> u64 {
+            self.decryptor.header().chunk_size as u64
+        }
+
+        fn read_record(&mut self, index: u64, buf: &mut [u8]) -> std::io::Result<()> {
+            let offset = self.header_len + index * self.decryptor.header().record_size();
+            self.file.seek(SeekFrom::Start(offset))?;
+            self.file.read_exact(buf)
+        }
+
+        fn load_chunk(&mut self, index: u64) -> anyhow::Result<&[u8]> {
+            if self.cached.as_ref().map(|(i, _)| *i) != Some(index) {
+                let last = index == self.full_chunks;
+                let record_len = if last {
+                    self.last_len.unwrap_or(0) as usize + container::TAG_LEN
+                } else {
+                    self.decryptor.header().record_size() as usize
+                };
+                let mut record = vec![0u8; record_len];
+                self.read_record(index, &mut record)?;
+                let plaintext = self.decryptor.open_chunk(index as u32, &record, last)?;
+                self.cached = Some((index, plaintext));
+            }
+            Ok(&self.cached.as_ref().unwrap().1)
+        }
+
+        fn is_truncated(&self) -> bool {
+            self.last_len.is_none()
+        }
+    }
+
+    struct State {
+        parts: Vec<Part>,
+        /// Plaintext offset of each part in the combined stream.
+        starts: Vec<u64>,
+        /// Plaintext bytes available to downstream, over all parts.
+        size: u64,
+        /// Parts whose truncation was already reported.
+        truncation_reported: Vec<bool>,
+    }
+
+    impl State {
+        /// `keys` holds `key` first, then the keyring.
+        fn open(locations: &[String], keys: &[Secret]) -> anyhow::Result<Self> {
+            let mut parts = Vec::with_capacity(locations.len());
+            let mut starts = Vec::with_capacity(locations.len());
+            let mut size = 0;
+            for location in locations {
+                let part = Part::open(location, keys)
+                    .map_err(|e| anyhow::anyhow!("Could not open {}: {}", location, e))?;
+                starts.push(size);
+                size += part.size;
+                parts.push(part);
+            }
+            Ok(Self {
+                truncation_reported: vec![false; parts.len()],
+                parts,
+                starts,
+                size,
+            })
+        }
+
+        /// Index of the part holding plaintext byte `offset`.
+        fn part_at(&self, offset: u64) -> usize {
+            self.starts.partition_point(|&start| start <= offset) - 1
+        }
+    }
+
+    #[derive(Default)]
+    pub struct AeadFileSrc {
+        settings: Mutex<Settings>,
+        state: Mutex<Option<State>>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for AeadFileSrc {
+        const NAME: &'static str = "EeeAeadFileSrc";
+        type Type = super::AeadFileSrc;
+        type ParentType = gst_base::BaseSrc;
+    }
+
+    impl ObjectImpl for AeadFileSrc {
+        fn properties() -> &'static [glib::ParamSpec] {
+            static PROPERTIES: LazyLock<Vec<glib::ParamSpec>> = LazyLock::new(|| {
+                vec![
+                    glib::ParamSpecString::builder("location")
+                        .nick("File Location")
+                        .blurb("Location of the file to read")
+                        .build(),
+                    gst::ParamSpecArray::builder("locations")
+                        .nick("File Locations")
+                        .blurb("Files to read back to back as one stream, overrides location")
+                        .element_spec(
+                            &glib::ParamSpecString::builder("location")
+                                .nick("File Location")
+                                .blurb("Location of one file to read")
+                                .build(),
+                        )
+                        .build(),
+                    glib::ParamSpecString::builder("key")
+                        .nick("Key")
+                        .blurb("Hex-encoded AES-128 or AES-256 key")
+                        .write_only()
+                        .build(),
+                    gst::ParamSpecArray::builder("keyring")
+                        .nick("Keyring")
+                        .blurb("Further hex keys, picked by the key ID in each file's header")
+                        .element_spec(
+                            &glib::ParamSpecString::builder("key")
+                                .nick("Key")
+                                .blurb("Hex-encoded AES-128 or AES-256 key")
+                                .build(),
+                        )
+                        .write_only()
+                        .build(),
+                ]
+            });
+            PROPERTIES.as_ref()
+        }
+
+        fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+            let mut settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "location" => settings.location = value.get().expect("type checked upstream"),
+                "locations" => {
+                    let array = value.get::<gst::Array>().expect("type checked upstream");
+                    settings.locations = array
+                        .iter()
+                        .filter_map(|v| v.get::<String>().ok())
+                        .collect();
+                }
+                "key" => {
+                    settings.key = value
+                        .get::<Option<String>>()
+                        .expect("type checked upstream")
+                        .map(Secret::from)
+                }
+                "keyring" => {
+                    let array = value.get::<gst::Array>().expect("type checked upstream");
+                    settings.keyring = array
+                        .iter()
+                        .filter_map(|v| v.get::<String>().ok())
+                        .map(Secret::from)
+                        .collect();
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+            let settings = self.settings.lock().unwrap();
+            match pspec.name() {
+                "location" => settings.location.to_value(),
+                "locations" => gst::Array::new(&settings.locations).to_value(),
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    impl GstObjectImpl for AeadFileSrc {}
+
+    impl ElementImpl for AeadFileSrc {
+        fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+            static ELEMENT_METADATA: LazyLock<gst::subclass::ElementMetadata> =
+                LazyLock::new(|| {
+                    gst::subclass::ElementMetadata::new(
+                        "AEAD File Source",
+                        "Source/File",
+                        "Reads and authenticates a chunked AES-GCM encrypted file",
+                        "eightyeightyeight",
+                    )
+                });
+            Some(&*ELEMENT_METADATA)
+        }
+
+        fn pad_templates() -> &'static [gst::PadTemplate] {
+            static PAD_TEMPLATES: LazyLock<Vec<gst::PadTemplate>> = LazyLock::new(|| {
+                vec![
+                    gst::PadTemplate::new(
+                        "src",
+                        gst::PadDirection::Src,
+                        gst::PadPresence::Always,
+                        &gst::Caps::new_any(),
+                    )
+                    .unwrap(),
+                ]
+            });
+            PAD_TEMPLATES.as_ref()
+        }
+    }
+
+    impl BaseSrcImpl for AeadFileSrc {
+        fn start(&self) -> Result<(), gst::ErrorMessage> {
+            let settings = self.settings.lock().unwrap();
+            let locations = settings.locations();
+            if locations.is_empty() {
+                return Err(gst::error_msg!(
+                    gst::ResourceError::Settings,
+                    ("No location set")
+                ));
+            }
+            let key = settings
+                .key
+                .clone()
+                .ok_or_else(|| gst::error_msg!(gst::ResourceError::Settings, ("No key set")))?;
+            let keys: Vec<Secret> = std::iter::once(key)
+                .chain(settings.keyring.iter().cloned())
+                .collect();
+            for key in &keys {
+                container::parse_key(key.expose()).map_err(|e| {
+                    gst::error_msg!(gst::ResourceError::Settings, ("Invalid key: {}", e))
+                })?;
+            }
+
+            let state = State::open(&locations, &keys)
+                .map_err(|e| gst::error_msg!(gst::StreamError::Decrypt, ("{}", e)))?;
+            for part in &state.parts {
+                if part.size == 0 && part.is_truncated() {
+                    gst::element_imp_warning!(
+                        self,
+                        gst::StreamError::Decrypt,
+                        (
+                            "{} is truncated and contains no authenticated data",
+                            part.location
+                        )
+                    );
+                }
+            }
+
+            *self.state.lock().unwrap() = Some(state);
+            Ok(())
+        }
+
+        fn stop(&self) -> Result<(), gst::ErrorMessage> {
+            *self.state.lock().unwrap() = None;
+            Ok(())
+        }
+
+        fn is_seekable(&self) -> bool {
+            true
+        }
+
+        fn size(&self) -> Option<u64> {
+            self.state.lock().unwrap().as_ref().map(|state| state.size)
+        }
+
+        fn create(
+            &self,
+            offset: u64,
+            _buffer: Option<&mut gst::BufferRef>,
+            length: u32,
+        ) -> Result<CreateSuccess, gst::FlowError> {
+            let mut state = self.state.lock().unwrap();
+            let state = state.as_mut().ok_or(gst::FlowError::Flushing)?;
+
+            if offset >= state.size {
+                return Err(gst::FlowError::Eos);
+            }
+
+            let end = state.size.min(offset + length as u64);
+            let mut data = Vec::with_capacity((end - offset) as usize);
+            let mut pos = offset;
+            while pos < end {
+                let index = state.part_at(pos);
+                let part_start = state.starts[index];
+                let part = &mut state.parts[index];
+                let part_end = part_start + part.size;
+                let chunk_size = part.chunk_size();
+                let part_pos = pos - part_start;
+                let chunk_index = part_pos / chunk_size;
+                let chunk = match part.load_chunk(chunk_index) {
+                    Ok(chunk) => chunk,
+                    Err(e) => {
+                        gst::element_imp_error!(
+                            self,
+                            gst::StreamError::Decrypt,
+                            ("{}: {}", state.parts[index].location, e)
+                        );
+                        return Err(gst::FlowError::Error);
+                    }
+                };
+                let start = (part_pos - chunk_index * chunk_size) as usize;
+                let take = (chunk.len() - start).min((end - pos) as usize);
+                data.extend_from_slice(&chunk[start..start + take]);
+                pos += take as u64;
+
+                // Report a truncated file when handing out its last
+                // authenticated bytes; BaseSrc never asks past `size()`.
+                if pos == part_end && part.is_truncated() && !state.truncation_reported[index] {
+                    state.truncation_reported[index] = true;
+                    gst::element_imp_warning!(
+                        self,
+                        gst::StreamError::Decrypt,
+                        (
+                            "{} is truncated or its final chunk is corrupt; stopped after {} authenticated bytes",
+                            state.parts[index].location,
+                            state.parts[index].size
+                        )
+                    );
+                }
+            }
+
+            let mut buffer = gst::Buffer::from_mut_slice(data);
+            {
+                let buffer = buffer.get_mut().unwrap();
+                buffer.set_offset(offset);
+                buffer.set_offset_end(end);
+            }
+            Ok(CreateSuccess::NewBuffer(buffer))
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! `aeadpktdec`: authenticates and decrypts packets sealed by `aeadpktenc`.
+//!
+//! Datagrams that fail authentication, and replays of ones already seen, are
+//! dropped with a warning in the log rather than stopping the pipeline, since
+//! anyone on the network can send them; the depayloader then treats them like
+//! any other lost packet.
+
+use gstreamer as gst;
+use gstreamer::glib;
+use gstreamer_base as gst_base;
+
+glib::wrapper! {
+    pub struct AeadPktDec(ObjectSubclass<imp::AeadPktDec>)
+        @extends gst_base::BaseTransform, gst::Element, gst::Object;
+}
+
+mod imp {
+    use super::*;
+    use crate::container;
+    use crate::packet::{self, Opener};
+    use crate::secrets::Secret;
+    use gst::prelude::*;
+    use gst::subclass::prelude::*;
+    use gst_base::subclass::prelude::*;
+    use std::sync::{LazyLock, Mutex};
+
+    static CAT: LazyLock<gst::DebugCategory> = LazyLock::new(|| {
+        gst::DebugCategory::new(
+            "aeadpktdec",
+            gst::DebugColorFlags::empty(),
+            Some("AEAD packet decrypter"),
+        )
+    });
+
+    #[derive(Default)]
+    pub struct AeadPktDec {
+        key: Mutex<Option<Secret>>,
+        opener: Mutex<Option<Opener>>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for AeadPktDec {
+        const NAME: &'static str = "EeeAeadPktDec";
+        type Type = super::AeadPktDec;
+        type ParentType = gst_base::BaseTransform;
+    }
+
+    impl ObjectImpl for AeadPktDec {
+        fn properties() -> &'static [glib::ParamSpec] {
+            static PROPERTIES: LazyLock<Vec<glib::ParamSpec>> = LazyLock::new(|| {
+                vec![
+                    glib::ParamSpecString::builder("key")
+                        .nick("Key")
+                        .blurb("Hex-encoded AES-128 or AES-256 key")
+                        .write_only()
+                        .build(),
+                ]
+            });
+            PROPERTIES.as_ref()
+        }
+
+        fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+            match pspec.name() {
+                "key" => {
+                    *self.key.lock().unwrap() = value
+                        .get::<Option<String>>()
+                        .expect("type checked upstream")
+                        .map(Secret::from)
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    impl GstObjectImpl for AeadPktDec {}
+
+    impl ElementImpl for AeadPktDec {
+        fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+            static ELEMENT_METADATA: LazyLock<gst::subclass::ElementMetadata> =
+                LazyLock::new(|| {
+                    gst::subclass::ElementMetadata::new(
+                        "AEAD Packet Decrypter",
+                        "Filter/Network",
+                        "Authenticates and decrypts AES-GCM sealed buffers, dropping forgeries",
+                        "eightyeightyeight",
+                    )
+                });
+            Some(&*ELEMENT_METADATA)
+        }
+
+        fn pad_templates() -> &'static [gst::PadTemplate] {
+            static PAD_TEMPLATES: LazyLock<Vec<gst::PadTemplate>> = LazyLock::new(|| {
+                let caps = gst::Caps::new_any();
+                vec![
+                    gst::PadTemplate::new(
+                        "src",
+                        gst::PadDirection::Src,
+                        gst::PadPresence::Always,
+                        &caps,
+                    )
+                    .unwrap(),
+                    gst::PadTemplate::new(
+                        "sink",
+                        gst::PadDirection::Sink,
+                        gst::PadPresence::Always,
+                        &caps,
+                    )
+                    .unwrap(),
+                ]
+            });
+            PAD_TEMPLATES.as_ref()
+        }
+    }
+
+    impl BaseTransformImpl for AeadPktDec {
+        const MODE: gst_base::subclass::BaseTransformMode =
+            gst_base::subclass::BaseTransformMode::NeverInPlace;
+        const PASSTHROUGH_ON_SAME_CAPS: bool = false;
+        const TRANSFORM_IP_ON_PASSTHROUGH: bool = false;
+
+        fn start(&self) -> Result<(), gst::ErrorMessage> {
+            let key = self
+                .key
+                .lock()
+                .unwrap()
+                .as_ref()
+                .map(Secret::expose)
+                .ok_or_else(|| gst::error_msg!(gst::ResourceError::Settings, ("No key set")))
+                .and_then(|key| {
+                    container::parse_key(key).map_err(|e| {
+                        gst::error_msg!(gst::ResourceError::Settings, ("Invalid key: {}", e))
+                    })
+                })?;
+            let opener = Opener::new(&key).map_err(|e| {
+                gst::error_msg!(gst::ResourceError::Settings, ("Invalid key: {}", e))
+            })?;
+
+            *self.opener.lock().unwrap() = Some(opener);
+            Ok(())
+        }
+
+        fn stop(&self) -> Result<(), gst::ErrorMessage> {
+            *self.opener.lock().unwrap() = None;
+            Ok(())
+        }
+
+        fn transform_size(
+            &self,
+            direction: gst::PadDirection,
+            _caps: &gst::Caps,
+            size: usize,
+            _othercaps: &gst::Caps,
+        ) -> Option<usize> {
+            match direction {
+                gst::PadDirection::Sink => Some(size.saturating_sub(packet::OVERHEAD)),
+                _ => Some(size + packet::OVERHEAD),
+            }
+        }
+
+        fn transform(
+            &self,
+            inbuf: &gst::Buffer,
+            outbuf: &mut gst::BufferRef,
+        ) -> Result<gst::FlowSuccess, gst::FlowError> {
+            let mut opener = self.opener.lock().unwrap();
+            let opener = opener.as_mut().ok_or(gst::FlowError::Flushing)?;
+
+            let input = inbuf.map_readable().map_err(|_| {
+                gst::element_imp_error!(self, gst::CoreError::Failed, ("Failed to map buffer"));
+                gst::FlowError::Error
+            })?;
+            let plaintext = match opener.open(&input) {
+                Ok(plaintext) => plaintext,
+                Err(e) => {
+                    gst::warning!(CAT, imp = self, "Dropping packet: {}", e);
+                    return Ok(gst_base::BASE_TRANSFORM_FLOW_DROPPED);
+                }
+            };
+
+            let mut output = outbuf.map_writable().map_err(|_| {
+                gst::element_imp_error!(self, gst::CoreError::Failed, ("Failed to map buffer"));
+                gst::FlowError::Error
+            })?;
+            output.copy_from_slice(&plaintext);
+
+            Ok(gst::FlowSuccess::Ok)
+        }
+    }
+}
@@ -0,0 +1,174 @@
+//! `aeadpktenc`: seals each buffer on its own with AES-GCM for the network.
+//!
+//! Meant to sit right before `udpsink` so every RTP packet becomes one
+//! self-contained datagram; see `packet` for the wire format.
+
+use gstreamer as gst;
+use gstreamer::glib;
+use gstreamer_base as gst_base;
+
+glib::wrapper! {
+    pub struct AeadPktEnc(ObjectSubclass<imp::AeadPktEnc>)
+        @extends gst_base::BaseTransform, gst::Element, gst::Object;
+}
+
+mod imp {
+    use super::*;
+    use crate::container;
+    use crate::packet::{self, Sealer};
+    use crate::secrets::Secret;
+    use gst::prelude::*;
+    use gst::subclass::prelude::*;
+    use gst_base::subclass::prelude::*;
+    use std::sync::{LazyLock, Mutex};
+
+    #[derive(Default)]
+    pub struct AeadPktEnc {
+        key: Mutex<Option<Secret>>,
+        sealer: Mutex<Option<Sealer>>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for AeadPktEnc {
+        const NAME: &'static str = "EeeAeadPktEnc";
+        type Type = super::AeadPktEnc;
+        type ParentType = gst_base::BaseTransform;
+    }
+
+    impl ObjectImpl for AeadPktEnc {
+        fn properties() -> &'static [glib::ParamSpec] {
+            static PROPERTIES: LazyLock<Vec<glib::ParamSpec>> = LazyLock::new(|| {
+                vec![
+                    glib::ParamSpecString::builder("key")
+                        .nick("Key")
+                        .blurb("Hex-encoded AES-128 or AES-256 key")
+                        .write_only()
+                        .build(),
+                ]
+            });
+            PROPERTIES.as_ref()
+        }
+
+        fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+            match pspec.name() {
+                "key" => {
+                    *self.key.lock().unwrap() = value
+                        .get::<Option<String>>()
+                        .expect("type checked upstream")
+                        .map(Secret::from)
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    impl GstObjectImpl for AeadPktEnc {}
+
+    impl ElementImpl for AeadPktEnc {
+        fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+            static ELEMENT_METADATA: LazyLock<gst::subclass::ElementMetadata> = LazyLock::new(
+                || {
+                    gst::subclass::ElementMetadata::new(
+                        "AEAD Packet Encrypter",
+                        "Filter/Network",
+                        "Encrypts and authenticates each buffer with AES-GCM under a per-session key",
+                        "eightyeightyeight",
+                    )
+                },
+            );
+            Some(&*ELEMENT_METADATA)
+        }
+
+        fn pad_templates() -> &'static [gst::PadTemplate] {
+            static PAD_TEMPLATES: LazyLock<Vec<gst::PadTemplate>> = LazyLock::new(|| {
+                let caps = gst::Caps::new_any();
+                vec![
+                    gst::PadTemplate::new(
+                        "src",
+                        gst::PadDirection::Src,
+                        gst::PadPresence::Always,
+                        &caps,
+                    )
+                    .unwrap(),
+                    gst::PadTemplate::new(
+                        "sink",
+                        gst::PadDirection::Sink,
+                        gst::PadPresence::Always,
+                        &caps,
+                    )
+                    .unwrap(),
+                ]
+            });
+            PAD_TEMPLATES.as_ref()
+        }
+    }
+
+    impl BaseTransformImpl for AeadPktEnc {
+        const MODE: gst_base::subclass::BaseTransformMode =
+            gst_base::subclass::BaseTransformMode::NeverInPlace;
+        const PASSTHROUGH_ON_SAME_CAPS: bool = false;
+        const TRANSFORM_IP_ON_PASSTHROUGH: bool = false;
+
+        fn start(&self) -> Result<(), gst::ErrorMessage> {
+            let key = self
+                .key
+                .lock()
+                .unwrap()
+                .as_ref()
+                .map(Secret::expose)
+                .ok_or_else(|| gst::error_msg!(gst::ResourceError::Settings, ("No key set")))
+                .and_then(|key| {
+                    container::parse_key(key).map_err(|e| {
+                        gst::error_msg!(gst::ResourceError::Settings, ("Invalid key: {}", e))
+                    })
+                })?;
+            let sealer = Sealer::new(&key).map_err(|e| {
+                gst::error_msg!(gst::ResourceError::Settings, ("Invalid key: {}", e))
+            })?;
+
+            *self.sealer.lock().unwrap() = Some(sealer);
+            Ok(())
+        }
+
+        fn stop(&self) -> Result<(), gst::ErrorMessage> {
+            *self.sealer.lock().unwrap() = None;
+            Ok(())
+        }
+
+        fn transform_size(
+            &self,
+            direction: gst::PadDirection,
+            _caps: &gst::Caps,
+            size: usize,
+            _othercaps: &gst::Caps,
+        ) -> Option<usize> {
+            match direction {
+                gst::PadDirection::Sink => Some(size + packet::OVERHEAD),
+                _ => Some(size.saturating_sub(packet::OVERHEAD)),
+            }
+        }
+
+        fn transform(
+            &self,
+            inbuf: &gst::Buffer,
+            outbuf: &mut gst::BufferRef,
+        ) -> Result<gst::FlowSuccess, gst::FlowError> {
+            let mut sealer = self.sealer.lock().unwrap();
+            let sealer = sealer.as_mut().ok_or(gst::FlowError::Flushing)?;
+
+            let input = inbuf.map_readable().map_err(|_| {
+                gst::element_imp_error!(self, gst::CoreError::Failed, ("Failed to map buffer"));
+                gst::FlowError::Error
+            })?;
+            let sealed = sealer.seal(&input);
+
+            let mut output = outbuf.map_writable().map_err(|_| {
+                gst::element_imp_error!(self, gst::CoreError::Failed, ("Failed to map buffer"));
+                gst::FlowError::Error
+            })?;
+            output.copy_from_slice(&sealed);
+
+            Ok(gst::FlowSuccess::Ok)
+        }
+    }
+}
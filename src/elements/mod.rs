@@ -0,0 +1,319 @@
+//! In-process GStreamer elements for the encrypted recording container and
+//! the encrypted RTP stream.
+//!
+//! They are registered statically (no plugin `.so`) so that pipelines built
+//! with `gst::parse::launch` can refer to them by name like any other element.
+
+use gstreamer as gst;
+use gstreamer::glib;
+use gstreamer::prelude::*;
+
+mod aeadfilesink;
+mod aeadfilesrc;
+mod aeadpktdec;
+mod aeadpktenc;
+
+pub use aeadfilesink::AeadFileSink;
+pub use aeadfilesrc::AeadFileSrc;
+pub use aeadpktdec::AeadPktDec;
+pub use aeadpktenc::AeadPktEnc;
+
+/// Registers all custom elements. Safe to call more than once.
+pub fn register() -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        None,
+        "aeadfilesink",
+        gst::Rank::NONE,
+        AeadFileSink::static_type(),
+    )?;
+    gst::Element::register(
+        None,
+        "aeadfilesrc",
+        gst::Rank::NONE,
+        AeadFileSrc::static_type(),
+    )?;
+    gst::Element::register(
+        None,
+        "aeadpktenc",
+        gst::Rank::NONE,
+        AeadPktEnc::static_type(),
+    )?;
+    gst::Element::register(
+        None,
+        "aeadpktdec",
+        gst::Rank::NONE,
+        AeadPktDec::static_type(),
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const KEY: &str = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+
+    /// Runs a pipeline to EOS and returns the warnings it posted.
+    fn run(pipeline_str: &str) -> Result<Vec<String>, String> {
+        let pipeline = gst::parse::launch(pipeline_str).map_err(|e| e.to_string())?;
+        // A source that fails to start posts its error on the bus as well.
+        let _ = pipeline.set_state(gst::State::Playing);
+        let bus = pipeline.bus().unwrap();
+        let mut warnings = Vec::new();
+        let result = loop {
+            let msg = bus
+                .timed_pop(gst::ClockTime::from_seconds(10))
+                .expect("pipeline timed out");
+            match msg.view() {
+                gst::MessageView::Eos(..) => break Ok(warnings),
+                gst::MessageView::Error(err) => break Err(err.error().to_string()),
+                gst::MessageView::Warning(w) => warnings.push(w.error().to_string()),
+                _ => (),
+            }
+        };
+        pipeline.set_state(gst::State::Null).unwrap();
+        result
+    }
+
+    #[test]
+    fn test_roundtrip_through_elements() {
+        gst::init().unwrap();
+        register().unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let plain = dir.path().join("plain.ts");
+        let encrypted = dir.path().join("plain.ts.enc");
+        let decrypted = dir.path().join("decrypted.ts");
+
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        std::fs::File::create(&plain)
+            .unwrap()
+            .write_all(&data)
+            .unwrap();
+
+        run(&format!(
+            "filesrc location={} ! aeadfilesink key={} location={}",
+            plain.display(),
+            KEY,
+            encrypted.display()
+        ))
+        .unwrap();
+        assert_ne!(std::fs::read(&encrypted).unwrap()[..], data[..]);
+
+        run(&format!(
+            "aeadfilesrc key={} location={} ! filesink location={}",
+            KEY,
+            encrypted.display(),
+            decrypted.display()
+        ))
+        .unwrap();
+        assert_eq!(std::fs::read(&decrypted).unwrap(), data);
+    }
+
+    #[test]
+    fn test_roundtrip_through_elements_with_recipient() {
+        gst::init().unwrap();
+        register().unwrap();
+
+        let identity = crate::secrets::PrivateKeyResolver::generate();
+        let recipient = crate::secrets::PrivateKeyResolver::recipient(&identity).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let plain = dir.path().join("plain.ts");
+        let encrypted = dir.path().join("plain.ts.enc");
+        let decrypted = dir.path().join("decrypted.ts");
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 241) as u8).collect();
+        std::fs::write(&plain, &data).unwrap();
+
+        // The sink needs no key when sealing to a recipient.
+        run(&format!(
+            "filesrc location={} ! aeadfilesink recipient={} location={}",
+            plain.display(),
+            recipient,
+            encrypted.display()
+        ))
+        .unwrap();
+
+        // Only the identity reads it back, also from the keyring.
+        run(&format!(
+            "aeadfilesrc key={} keyring=<\"{}\"> location={} ! filesink location={}",
+            KEY,
+            identity,
+            encrypted.display(),
+            decrypted.display()
+        ))
+        .unwrap();
+        assert_eq!(std::fs::read(&decrypted).unwrap(), data);
+
+        let err = run(&format!(
+            "aeadfilesrc key={} location={} ! fakesink",
+            KEY,
+            encrypted.display()
+        ))
+        .unwrap_err();
+        assert!(err.contains("No key for key-id"), "{}", err);
+    }
+
+    fn write_recording(path: &std::path::Path, data: &[u8]) {
+        write_recording_with(path, KEY, data);
+    }
+
+    fn write_recording_with(path: &std::path::Path, key: &str, data: &[u8]) {
+        let key = crate::container::parse_key(key).unwrap();
+        let mut writer =
+            crate::container::Writer::new(std::fs::File::create(path).unwrap(), &key).unwrap();
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn test_src_plays_authenticated_part_of_truncated_recording() {
+        gst::init().unwrap();
+        register().unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let encrypted = dir.path().join("rec.ts.enc");
+        let decrypted = dir.path().join("rec.ts");
+        let data: Vec<u8> = (0..300_000u32).map(|i| (i % 13) as u8).collect();
+        write_recording(&encrypted, &data);
+
+        // Simulate a power loss in the middle of the third chunk.
+        let bytes = std::fs::read(&encrypted).unwrap();
+        let chunk = crate::container::DEFAULT_CHUNK_SIZE as usize;
+        std::fs::write(&encrypted, &bytes[..bytes.len() / 2]).unwrap();
+
+        let warnings = run(&format!(
+            "aeadfilesrc key={} location={} ! filesink location={}",
+            KEY,
+            encrypted.display(),
+            decrypted.display()
+        ))
+        .unwrap();
+        assert!(warnings.iter().any(|w| w.contains("truncated")));
+        assert_eq!(std::fs::read(&decrypted).unwrap(), data[..2 * chunk]);
+    }
+
+    #[test]
+    fn test_src_reads_locations_back_to_back() {
+        gst::init().unwrap();
+        register().unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("cam0_00000.ts.enc");
+        let second = dir.path().join("cam0_00001.ts.enc");
+        let decrypted = dir.path().join("all.ts");
+        let data: Vec<u8> = (0..250_000u32).map(|i| (i % 241) as u8).collect();
+        write_recording(&first, &data[..100_000]);
+        write_recording(&second, &data[100_000..]);
+
+        run(&format!(
+            "aeadfilesrc key={} locations=<\"{}\",\"{}\"> ! filesink location={}",
+            KEY,
+            first.display(),
+            second.display(),
+            decrypted.display()
+        ))
+        .unwrap();
+        assert_eq!(std::fs::read(&decrypted).unwrap(), data);
+    }
+
+    #[test]
+    fn test_src_picks_keys_from_keyring() {
+        gst::init().unwrap();
+        register().unwrap();
+
+        // Segments from before and after a key rotation.
+        let old_key = "ffeeddccbbaa99887766554433221100";
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("cam0_00000.ts.enc");
+        let second = dir.path().join("cam0_00001.ts.enc");
+        let decrypted = dir.path().join("all.ts");
+        let data: Vec<u8> = (0..150_000u32).map(|i| (i % 239) as u8).collect();
+        write_recording_with(&first, old_key, &data[..70_000]);
+        write_recording(&second, &data[70_000..]);
+
+        run(&format!(
+            "aeadfilesrc key={} keyring=<\"{}\"> locations=<\"{}\",\"{}\"> ! filesink location={}",
+            KEY,
+            old_key,
+            first.display(),
+            second.display(),
+            decrypted.display()
+        ))
+        .unwrap();
+        assert_eq!(std::fs::read(&decrypted).unwrap(), data);
+
+        let err = run(&format!(
+            "aeadfilesrc key={} location={} ! fakesink",
+            KEY,
+            first.display()
+        ))
+        .unwrap_err();
+        assert!(err.contains("No key for key-id"), "{}", err);
+    }
+
+    #[test]
+    fn test_src_rejects_tampered_recording() {
+        gst::init().unwrap();
+        register().unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let encrypted = dir.path().join("rec.ts.enc");
+        write_recording(&encrypted, &vec![0x47u8; 300_000]);
+
+        let mut bytes = std::fs::read(&encrypted).unwrap();
+        bytes[150_000] ^= 0xff;
+        std::fs::write(&encrypted, bytes).unwrap();
+
+        let err = run(&format!(
+            "aeadfilesrc key={} location={} ! fakesink",
+            KEY,
+            encrypted.display()
+        ))
+        .unwrap_err();
+        assert!(err.contains("failed authentication"), "{}", err);
+    }
+
+    #[test]
+    fn test_packet_roundtrip_through_elements() {
+        gst::init().unwrap();
+        register().unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let plain = dir.path().join("packets.bin");
+        let decrypted = dir.path().join("decrypted.bin");
+        let data: Vec<u8> = (0..50_000u32).map(|i| (i % 7) as u8).collect();
+        std::fs::write(&plain, &data).unwrap();
+
+        run(&format!(
+            "filesrc blocksize=1400 location={} ! application/x-rtp ! aeadpktenc key={} ! queue ! aeadpktdec key={} ! filesink location={}",
+            plain.display(),
+            KEY,
+            KEY,
+            decrypted.display()
+        ))
+        .unwrap();
+        assert_eq!(std::fs::read(&decrypted).unwrap(), data);
+    }
+
+    #[test]
+    fn test_packet_dec_drops_packets_under_wrong_key() {
+        gst::init().unwrap();
+        register().unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let plain = dir.path().join("packets.bin");
+        let decrypted = dir.path().join("decrypted.bin");
+        std::fs::write(&plain, vec![0x80u8; 10_000]).unwrap();
+
+        run(&format!(
+            "filesrc blocksize=1400 location={} ! application/x-rtp ! aeadpktenc key={} ! aeadpktdec key={} ! filesink location={}",
+            plain.display(),
+            KEY,
+            "ff".repeat(32),
+            decrypted.display()
+        ))
+        .unwrap();
+        assert!(std::fs::read(&decrypted).unwrap().is_empty());
+    }
+}
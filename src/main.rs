@@ -1,25 +1,95 @@
 use clap::Parser;
 use tracing::{error, info};
 
+mod aws;
 mod cli;
 mod config;
+mod container;
+mod controls;
+mod elements;
+mod manifest;
 mod metrics;
+#[cfg(test)]
+mod mock_http;
+mod packet;
 mod pipeline;
+mod pkcs11;
+mod playlist;
+mod recover;
+mod rekey;
+#[cfg(feature = "rtsp")]
+mod rtsp;
 mod secrets;
+mod segments;
+mod srtp;
+mod vault;
+mod verify;
 mod wizard;
 
-fn load_config(path: &str) -> anyhow::Result<config::Config> {
-    info!("Loading configuration from: {}", path);
-    match config::Config::load(path) {
+/// Reads the configuration layers below the command-line flags: the
+/// built-in defaults, the config file and its drop-ins, and the
+/// `EIGHTYEIGHTYEIGHT_*` environment variables.
+fn config_layers(path: &cli::ConfigPath) -> anyhow::Result<config::Layers> {
+    match config::Layers::new().files(path.config.as_deref()) {
+        Ok(layers) => Ok(layers.env(std::env::vars_os())),
+        Err(e) => {
+            error!("Failed to load configuration: {:#}", e);
+            Err(e)
+        }
+    }
+}
+
+fn build_config(layers: config::Layers) -> anyhow::Result<config::Config> {
+    let files: Vec<String> = layers
+        .paths()
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect();
+    if files.is_empty() {
+        info!("No configuration file found; using defaults and environment");
+    } else {
+        info!("Loading configuration from: {}", files.join(", "));
+    }
+    match layers.build() {
         Ok(c) => Ok(c),
         Err(e) => {
-            error!("Failed to load configuration: {}", e);
+            error!("Failed to load configuration: {:#}", e);
             Err(e)
         }
     }
 }
 
-fn handle_record_command(config_path: &str) -> anyhow::Result<()> {
+fn load_config(path: &cli::ConfigPath) -> anyhow::Result<config::Config> {
+    build_config(config_layers(path)?)
+}
+
+fn start_metrics_server(config: &config::Config) -> anyhow::Result<()> {
+    if let Some(addr) = &config.metrics_listen {
+        metrics::serve(addr)?;
+    }
+    Ok(())
+}
+
+/// Writes a new identity to `path`, readable only by the owner, and prints
+/// the matching `recipient` for recorder configs.
+fn write_identity(path: &std::path::Path) -> anyhow::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let identity = secrets::PrivateKeyResolver::generate();
+    let recipient = secrets::PrivateKeyResolver::recipient(&identity)?;
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(format!("{}\n", identity).as_bytes())?;
+    info!("Wrote identity to {}", path.display());
+    println!("recipient = \"{}\"", recipient);
+    Ok(())
+}
+
+fn handle_record_command(config_path: &cli::ConfigPath) -> anyhow::Result<()> {
     let config = load_config(config_path)?;
 
     if let (Some(min_space_mb), Some(output_dir)) =
@@ -40,6 +110,16 @@ fn handle_record_command(config_path: &str) -> anyhow::Result<()> {
         config.device
     );
 
+    if let Some(dest) = &config.stream_dest {
+        info!(
+            "Also streaming to {}:{}",
+            dest,
+            config.stream_port.unwrap_or(config::DEFAULT_STREAM_PORT)
+        );
+    }
+
+    start_metrics_server(&config)?;
+
     // Run the pipeline
     pipeline::run_record_pipeline(&config)
 }
@@ -55,24 +135,173 @@ fn main() -> anyhow::Result<()> {
 
     match args.command {
         cli::Commands::Record { config } => handle_record_command(&config)?,
-        cli::Commands::Play { config, input } => {
+        cli::Commands::Play {
+            config,
+            input,
+            from,
+            to,
+            seek_step,
+        } => {
             let config = load_config(&config)?;
-            info!("Playing back file: {}", input);
-            pipeline::run_play_pipeline(&config, &input)?;
+            info!("Playing back: {}", input);
+            let playlist = playlist::resolve(&input, &config.keys(), from, to)?;
+            pipeline::run_play_pipeline(
+                &config,
+                &playlist,
+                std::time::Duration::from_secs(seek_step),
+            )?;
         }
-        cli::Commands::Stream { config, dest, port } => {
+        cli::Commands::Export {
+            config,
+            input,
+            output,
+            format,
+            from,
+            to,
+            start,
+            end,
+        } => {
             let config = load_config(&config)?;
-            info!("Streaming to {}:{}", dest, port);
-            pipeline::run_stream_pipeline(&config, &dest, port)?;
+            let format = format
+                .or_else(|| cli::ExportFormat::from_path(&output))
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Cannot tell the format of {} from its extension; pass --format",
+                        output.display()
+                    )
+                })?;
+            if let (Some(start), Some(end)) = (start, end)
+                && end <= start
+            {
+                anyhow::bail!("--end must be after --start");
+            }
+            info!("Exporting {} to {}", input, output.display());
+            let playlist = playlist::resolve(&input, &config.keys(), from, to)?;
+            pipeline::run_export_pipeline(&config, &playlist, &output, format, start, end)?;
+        }
+        cli::Commands::Verify { config, input } => {
+            let config = load_config(&config)?;
+            verify::run(&config, &input)?;
+        }
+        cli::Commands::Recover {
+            config,
+            input,
+            output,
+        } => {
+            let config = load_config(&config)?;
+            recover::run(&config, &input, output.as_deref())?;
+        }
+        cli::Commands::Rekey {
+            input,
+            old_key,
+            new_key,
+        } => {
+            rekey::run(&input, &old_key, &new_key)?;
+        }
+        cli::Commands::Keygen { output } => write_identity(&output)?,
+        cli::Commands::WrapKey { pkcs11, key } => {
+            let spec = pkcs11::Spec::parse(&pkcs11)?;
+            let wrapped = pkcs11::wrap(&spec, secrets::get_resolver(&key).resolve()?.expose())?;
+            println!("key = \"pkcs11:{},wrapped={}\"", pkcs11, wrapped);
+        }
+        cli::Commands::Stream {
+            config,
+            dest,
+            port,
+            transport,
+            sdp,
+        } => {
+            let config = build_config(
+                config_layers(&config)?
+                    .flag("stream_dest", "--dest", dest)?
+                    .flag("stream_port", "--port", port)?
+                    .flag("stream_transport", "--transport", transport)?,
+            )?;
+            let dest = config
+                .stream_dest
+                .as_deref()
+                .unwrap_or(config::DEFAULT_STREAM_DEST);
+            let port = config.stream_port.unwrap_or(config::DEFAULT_STREAM_PORT);
+            let transport = config.stream_transport.unwrap_or(config::Transport::Aead);
+            if sdp.is_some() && transport != config::Transport::Srtp {
+                anyhow::bail!("--sdp is only supported with --transport srtp");
+            }
+            start_metrics_server(&config)?;
+            info!("Streaming to {}:{} ({:?})", dest, port, transport);
+            pipeline::run_stream_pipeline(
+                &config,
+                dest,
+                port,
+                transport,
+                sdp.as_deref().map(std::path::Path::new),
+            )?;
         }
         cli::Commands::Receive {
             config,
             listen,
             port,
+            transport,
         } => {
-            let config = load_config(&config)?;
-            info!("Receiving on {}:{}", listen, port);
-            pipeline::run_receive_pipeline(&config, &listen, port)?;
+            let config = build_config(
+                config_layers(&config)?
+                    .flag("receive_listen", "--listen", listen)?
+                    .flag("stream_port", "--port", port)?
+                    .flag("stream_transport", "--transport", transport)?,
+            )?;
+            let listen = config
+                .receive_listen
+                .as_deref()
+                .unwrap_or(config::DEFAULT_LISTEN);
+            let port = config.stream_port.unwrap_or(config::DEFAULT_STREAM_PORT);
+            let transport = config.stream_transport.unwrap_or(config::Transport::Aead);
+            start_metrics_server(&config)?;
+            info!("Receiving on {}:{} ({:?})", listen, port, transport);
+            pipeline::run_receive_pipeline(&config, listen, port, transport)?;
+        }
+        #[cfg(feature = "rtsp")]
+        cli::Commands::Serve {
+            config,
+            listen,
+            port,
+            mount,
+            auth,
+        } => {
+            let config = build_config(
+                config_layers(&config)?
+                    .flag("rtsp_listen", "--listen", listen)?
+                    .flag("rtsp_port", "--port", port)?,
+            )?;
+            let listen = config
+                .rtsp_listen
+                .as_deref()
+                .unwrap_or(config::DEFAULT_LISTEN);
+            let port = config.rtsp_port.unwrap_or(config::DEFAULT_RTSP_PORT);
+            rtsp::run_serve(&config, listen, port, &mount, auth)?;
+        }
+        #[cfg(not(feature = "rtsp"))]
+        cli::Commands::Serve { .. } => {
+            anyhow::bail!(
+                "This build does not include RTSP support. Rebuild with `cargo build --release --features rtsp`."
+            );
+        }
+        cli::Commands::Config {
+            command: cli::ConfigCommand::Show { config, effective },
+        } => {
+            print!("{}", config_layers(&config)?.show(effective));
+        }
+        cli::Commands::Config {
+            command: cli::ConfigCommand::Check { config },
+        } => {
+            let (config, mut problems) = config_layers(&config)?.check();
+            problems.extend(pipeline::check(&config));
+            if problems.is_empty() {
+                println!("Configuration OK");
+            } else {
+                for problem in &problems {
+                    println!("{}", problem);
+                }
+                anyhow::bail!("{} problem(s) found", problems.len());
+            }
         }
         cli::Commands::Init { output } => {
             wizard::run(output)?;
@@ -116,7 +345,10 @@ mod tests {
 
         // We need to shadow the pipeline module for this test.
         // The main `handle_record_command` will call our mock `run_record_pipeline`.
-        let err = handle_record_command(file.path().to_str().unwrap()).unwrap_err();
+        let path = cli::ConfigPath {
+            config: Some(file.path().to_path_buf()),
+        };
+        let err = handle_record_command(&path).unwrap_err();
 
         assert!(err.to_string().contains("Not enough disk space"));
     }
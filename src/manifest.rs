@@ -0,0 +1,388 @@
+//! Authenticated segment index for rotated recordings.
+//!
+//! The recorder keeps a JSON sidecar next to the output, e.g.
+//! `cam0.manifest.json` for `output_path = "cam0.ts.enc"`. It lists every
+//! segment with its running-time (PTS) span, wall-clock span, size and the
+//! SHA-256 of the encrypted file, so tools can find footage for a time range
+//! without decrypting anything. The file is readable but carries an
+//! HMAC-SHA256 under a key derived from the recording key, so edits are
+//! detected on load. It is rewritten atomically after every segment.
+
+use crate::container;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+pub const VERSION: u32 = 1;
+const HKDF_INFO: &[u8] = b"eightyeightyeight manifest hmac key";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Segment {
+    /// File name, relative to the manifest's directory.
+    pub path: String,
+    /// Pipeline running time of the first and last frame, in nanoseconds.
+    pub start_pts: u64,
+    pub end_pts: u64,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub bytes: u64,
+    /// SHA-256 of the encrypted file as written.
+    pub sha256: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Manifest {
+    pub version: u32,
+    pub segments: Vec<Segment>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SignedManifest {
+    #[serde(flatten)]
+    manifest: Manifest,
+    hmac_sha256: String,
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Self {
+            version: VERSION,
+            segments: Vec::new(),
+        }
+    }
+}
+
+/// Sidecar path for a recording, next to `output_path`.
+pub fn manifest_path(output_path: &Path) -> PathBuf {
+    let name = output_path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let stem = name.split('.').next().unwrap_or_default();
+    // `%05d` patterns belong to the segment names, not the manifest.
+    let stem = stem
+        .split('%')
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches('_');
+    output_path.with_file_name(format!("{}.manifest.json", stem))
+}
+
+fn mac(key: &str) -> Result<Hmac<Sha256>> {
+    let ikm = container::parse_key(key)?;
+    let mut mac_key = [0u8; 32];
+    Hkdf::<Sha256>::new(None, &ikm)
+        .expand(HKDF_INFO, &mut mac_key)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    Ok(Hmac::<Sha256>::new_from_slice(&mac_key).expect("HMAC accepts any key length"))
+}
+
+impl Manifest {
+    /// Loads a manifest and checks its HMAC with the recording key.
+    pub fn load(path: &Path, key: &str) -> Result<Self> {
+        let content = std::fs::read(path)?;
+        let signed: SignedManifest =
+            serde_json::from_slice(&content).context("Manifest is not valid JSON")?;
+        let expected = hex::decode(&signed.hmac_sha256).context("Manifest HMAC is not hex")?;
+
+        let mut mac = mac(key)?;
+        mac.update(&serde_json::to_vec(&signed.manifest)?);
+        mac.verify_slice(&expected).map_err(|_| {
+            anyhow::anyhow!(
+                "Manifest {} failed authentication (modified or written with another key)",
+                path.display()
+            )
+        })?;
+
+        if signed.manifest.version != VERSION {
+            anyhow::bail!("Unsupported manifest version {}", signed.manifest.version);
+        }
+        Ok(signed.manifest)
+    }
+
+    /// Writes the manifest via a temporary file and rename, so readers never
+    /// see a partial file.
+    pub fn save(&self, path: &Path, key: &str) -> Result<()> {
+        let mut mac = mac(key)?;
+        mac.update(&serde_json::to_vec(self)?);
+        let signed = SignedManifest {
+            manifest: self.clone(),
+            hmac_sha256: hex::encode(mac.finalize().into_bytes()),
+        };
+
+        let tmp = path.with_extension("json.tmp");
+        let mut file = std::fs::File::create(&tmp)?;
+        serde_json::to_writer_pretty(&mut file, &signed)?;
+        file.write_all(b"\n")?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Adds a segment, replacing an older entry for the same file (index
+    /// based names are reused once `max_files` wraps around).
+    pub fn upsert(&mut self, segment: Segment) {
+        self.segments.retain(|s| s.path != segment.path);
+        self.segments.push(segment);
+        self.segments.sort_by_key(|s| s.start_time);
+    }
+
+    /// Drops entries whose file was deleted by retention.
+    pub fn prune_missing(&mut self, dir: &Path) {
+        self.segments.retain(|s| dir.join(&s.path).exists());
+    }
+}
+
+/// A fragment event from `splitmuxsink`, with its running time mapped to
+/// wall-clock time when the message was posted.
+pub enum FragmentEvent {
+    Opened {
+        location: PathBuf,
+        running_time: u64,
+        time: DateTime<Utc>,
+    },
+    Closed {
+        location: PathBuf,
+        running_time: u64,
+        time: DateTime<Utc>,
+    },
+}
+
+/// Turns fragment events into manifest entries.
+pub struct Recorder {
+    path: PathBuf,
+    dir: PathBuf,
+    key: String,
+    manifest: Manifest,
+    open: HashMap<PathBuf, (u64, DateTime<Utc>)>,
+}
+
+impl Recorder {
+    /// Continues an existing manifest, or starts a new one. A manifest that
+    /// fails authentication is moved aside rather than extended.
+    pub fn new(output_path: &Path, key: &str) -> Result<Self> {
+        let path = manifest_path(output_path);
+        let dir = path
+            .parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."))
+            .to_path_buf();
+
+        let manifest = if path.exists() {
+            match Manifest::load(&path, key) {
+                Ok(manifest) => manifest,
+                Err(e) => {
+                    let aside = path.with_extension("json.invalid");
+                    tracing::warn!(
+                        "{}; moving it to {} and starting a new manifest",
+                        e,
+                        aside.display()
+                    );
+                    std::fs::rename(&path, &aside)?;
+                    Manifest::default()
+                }
+            }
+        } else {
+            Manifest::default()
+        };
+
+        Ok(Self {
+            path,
+            dir,
+            key: key.to_string(),
+            manifest,
+            open: HashMap::new(),
+        })
+    }
+
+    pub fn handle(&mut self, event: FragmentEvent) -> Result<()> {
+        match event {
+            FragmentEvent::Opened {
+                location,
+                running_time,
+                time,
+            } => {
+                self.open.insert(location, (running_time, time));
+            }
+            FragmentEvent::Closed {
+                location,
+                running_time,
+                time,
+            } => {
+                let Some((start_pts, start_time)) = self.open.remove(&location) else {
+                    return Ok(());
+                };
+                let (bytes, sha256) = hash_file(&location)?;
+                let name = location
+                    .file_name()
+                    .context("Segment has no file name")?
+                    .to_string_lossy()
+                    .to_string();
+                self.manifest.upsert(Segment {
+                    path: name,
+                    start_pts,
+                    end_pts: running_time,
+                    start_time,
+                    end_time: time,
+                    bytes,
+                    sha256,
+                });
+                self.manifest.prune_missing(&self.dir);
+                self.manifest.save(&self.path, &self.key)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Size and SHA-256 of a file, as listed in the manifest.
+pub fn hash_file(path: &Path) -> Result<(u64, String)> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let bytes = std::io::copy(&mut file, &mut hasher)?;
+    Ok((bytes, hex::encode(hasher.finalize())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    const KEY: &str = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+
+    fn segment(path: &str, minute: u32) -> Segment {
+        Segment {
+            path: path.to_string(),
+            start_pts: minute as u64 * 60_000_000_000,
+            end_pts: (minute as u64 + 1) * 60_000_000_000,
+            start_time: Utc.with_ymd_and_hms(2026, 10, 18, 12, minute, 0).unwrap(),
+            end_time: Utc
+                .with_ymd_and_hms(2026, 10, 18, 12, minute + 1, 0)
+                .unwrap(),
+            bytes: 1234,
+            sha256: "00".repeat(32),
+        }
+    }
+
+    #[test]
+    fn test_manifest_path() {
+        assert_eq!(
+            manifest_path(Path::new("/var/rec/cam0.ts.enc")),
+            PathBuf::from("/var/rec/cam0.manifest.json")
+        );
+        assert_eq!(
+            manifest_path(Path::new("rec/output_%05d.ts.enc")),
+            PathBuf::from("rec/output.manifest.json")
+        );
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cam0.manifest.json");
+
+        let mut manifest = Manifest::default();
+        manifest.upsert(segment("cam0_20261018T120100Z.ts.enc", 1));
+        manifest.upsert(segment("cam0_20261018T120000Z.ts.enc", 0));
+        manifest.save(&path, KEY).unwrap();
+
+        let loaded = Manifest::load(&path, KEY).unwrap();
+        assert_eq!(loaded, manifest);
+        assert_eq!(loaded.segments[0].path, "cam0_20261018T120000Z.ts.enc");
+        assert!(!dir.path().join("cam0.manifest.json.tmp").exists());
+    }
+
+    #[test]
+    fn test_load_rejects_tampering_and_wrong_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cam0.manifest.json");
+
+        let mut manifest = Manifest::default();
+        manifest.upsert(segment("cam0_20261018T120000Z.ts.enc", 0));
+        manifest.save(&path, KEY).unwrap();
+
+        assert!(Manifest::load(&path, &"ff".repeat(32)).is_err());
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, content.replace("\"bytes\": 1234", "\"bytes\": 1235")).unwrap();
+        let err = Manifest::load(&path, KEY).unwrap_err();
+        assert!(err.to_string().contains("failed authentication"));
+    }
+
+    #[test]
+    fn test_recorder_writes_segments() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("cam0.ts.enc");
+        let segment_path = dir.path().join("cam0_20261018T120000Z.ts.enc");
+        let start = Utc.with_ymd_and_hms(2026, 10, 18, 12, 0, 0).unwrap();
+
+        let mut recorder = Recorder::new(&output, KEY).unwrap();
+        recorder
+            .handle(FragmentEvent::Opened {
+                location: segment_path.clone(),
+                running_time: 0,
+                time: start,
+            })
+            .unwrap();
+        std::fs::write(&segment_path, b"encrypted segment").unwrap();
+        recorder
+            .handle(FragmentEvent::Closed {
+                location: segment_path.clone(),
+                running_time: 60_000_000_000,
+                time: start + chrono::Duration::minutes(1),
+            })
+            .unwrap();
+
+        let manifest = Manifest::load(&manifest_path(&output), KEY).unwrap();
+        assert_eq!(manifest.segments.len(), 1);
+        let entry = &manifest.segments[0];
+        assert_eq!(entry.path, "cam0_20261018T120000Z.ts.enc");
+        assert_eq!(entry.end_pts, 60_000_000_000);
+        assert_eq!(entry.start_time, start);
+        assert_eq!(entry.bytes, 17);
+        assert_eq!(
+            entry.sha256,
+            hex::encode(Sha256::digest(b"encrypted segment"))
+        );
+
+        // Retention deleted the segment; the next write drops its entry.
+        std::fs::remove_file(&segment_path).unwrap();
+        let mut recorder = Recorder::new(&output, KEY).unwrap();
+        let next = dir.path().join("cam0_20261018T120100Z.ts.enc");
+        std::fs::write(&next, b"next").unwrap();
+        for event in [
+            FragmentEvent::Opened {
+                location: next.clone(),
+                running_time: 60_000_000_000,
+                time: start + chrono::Duration::minutes(1),
+            },
+            FragmentEvent::Closed {
+                location: next.clone(),
+                running_time: 120_000_000_000,
+                time: start + chrono::Duration::minutes(2),
+            },
+        ] {
+            recorder.handle(event).unwrap();
+        }
+        let manifest = Manifest::load(&manifest_path(&output), KEY).unwrap();
+        assert_eq!(manifest.segments.len(), 1);
+        assert_eq!(manifest.segments[0].path, "cam0_20261018T120100Z.ts.enc");
+    }
+
+    #[test]
+    fn test_recorder_moves_invalid_manifest_aside() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("cam0.ts.enc");
+        std::fs::write(manifest_path(&output), b"{}").unwrap();
+
+        Recorder::new(&output, KEY).unwrap();
+        assert!(dir.path().join("cam0.manifest.json.invalid").exists());
+        assert!(!manifest_path(&output).exists());
+    }
+}
@@ -1,18 +1,31 @@
+use anyhow::{Context, Result};
 use opentelemetry::{
     global,
     metrics::{Counter, Meter},
 };
+use opentelemetry_sdk::metrics::SdkMeterProvider;
+use prometheus::{Encoder, Registry, TextEncoder};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::time::Instant;
-use tracing::info;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// How long a scrape may take to send its request or read the response.
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(5);
+/// Longest request line read; `GET /metrics HTTP/1.1` is far shorter.
+const MAX_REQUEST_LINE: u64 = 8 * 1024;
 
 pub struct Metrics {
     pub frame_counter: Counter<u64>,
     pub byte_counter: Counter<u64>,
+    pub record_drop_counter: Counter<u64>,
     pub start_time: Instant,
     pub last_report_time: std::sync::Mutex<Instant>,
     pub frame_count_total: AtomicU64,
     pub byte_count_total: AtomicU64,
+    pub record_drops_total: AtomicU64,
 }
 
 impl Metrics {
@@ -29,13 +42,22 @@ impl Metrics {
             .with_description("Number of bytes processed")
             .build();
 
+        let record_drop_counter = meter
+            .u64_counter("record_queue_overruns")
+            .with_description(
+                "Times the recording fell behind and its queue dropped the oldest frames",
+            )
+            .build();
+
         Metrics {
             frame_counter,
             byte_counter,
+            record_drop_counter,
             start_time: Instant::now(),
             last_report_time: std::sync::Mutex::new(Instant::now()),
             frame_count_total: AtomicU64::new(0),
             byte_count_total: AtomicU64::new(0),
+            record_drops_total: AtomicU64::new(0),
         }
     }
 
@@ -44,6 +66,15 @@ impl Metrics {
         self.frame_count_total.fetch_add(count, Ordering::Relaxed);
     }
 
+    /// Called when the record queue overflows. Warns the first time only,
+    /// since a slow disk overflows it on every frame.
+    pub fn increment_record_drops(&self) {
+        self.record_drop_counter.add(1, &[]);
+        if self.record_drops_total.fetch_add(1, Ordering::Relaxed) == 0 {
+            warn!("The recording is falling behind; dropping frames so the stream keeps going");
+        }
+    }
+
     pub fn increment_bytes(&self, count: u64) {
         self.byte_counter.add(count, &[]);
         self.byte_count_total.fetch_add(count, Ordering::Relaxed);
@@ -66,3 +97,112 @@ impl Metrics {
         }
     }
 }
+
+/// Installs a Prometheus exporter as the global meter provider and serves it
+/// on `GET /metrics` at `addr` from a background thread. Each connection is
+/// handled on its own thread with I/O timeouts, so a client that stalls
+/// cannot hold up other scrapes.
+///
+/// Must be called before any `Metrics` is created, otherwise the counters are
+/// bound to the no-op provider and never show up in the scrape output.
+pub fn serve(addr: &str) -> Result<SocketAddr> {
+    let registry = Registry::new();
+    let exporter = opentelemetry_prometheus::exporter()
+        .with_registry(registry.clone())
+        .build()
+        .context("Failed to build Prometheus exporter")?;
+    let provider = SdkMeterProvider::builder().with_reader(exporter).build();
+    global::set_meter_provider(provider);
+
+    let listener = TcpListener::bind(addr)
+        .with_context(|| format!("Failed to bind metrics endpoint to {}", addr))?;
+    let local_addr = listener.local_addr()?;
+    info!("Serving metrics on http://{}/metrics", local_addr);
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let registry = registry.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = handle_connection(stream, &registry) {
+                            warn!("Metrics request failed: {}", e);
+                        }
+                    });
+                }
+                Err(e) => warn!("Metrics connection failed: {}", e),
+            }
+        }
+    });
+
+    Ok(local_addr)
+}
+
+fn handle_connection(mut stream: TcpStream, registry: &Registry) -> Result<()> {
+    stream.set_read_timeout(Some(CONNECTION_TIMEOUT))?;
+    stream.set_write_timeout(Some(CONNECTION_TIMEOUT))?;
+    let mut request_line = String::new();
+    BufReader::new((&stream).take(MAX_REQUEST_LINE)).read_line(&mut request_line)?;
+
+    let mut parts = request_line.split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let encoder = TextEncoder::new();
+            let mut body = Vec::new();
+            encoder.encode(&registry.gather(), &mut body)?;
+            ("200 OK", encoder.format_type().to_string(), body)
+        }
+        _ => (
+            "404 Not Found",
+            "text/plain".to_string(),
+            b"Not Found\n".to_vec(),
+        ),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(&body)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn get(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_serve_metrics_endpoint() {
+        let addr = serve("127.0.0.1:0").unwrap();
+
+        let metrics = Metrics::new();
+        metrics.increment_frames(3);
+        metrics.increment_bytes(1024);
+
+        let response = get(addr, "/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("video_frames_processed_total"));
+        assert!(response.contains("video_bytes_processed_total"));
+
+        let response = get(addr, "/other");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found"));
+
+        // A client that connects and sends nothing does not block others.
+        let _silent = TcpStream::connect(addr).unwrap();
+        let start = Instant::now();
+        assert!(get(addr, "/metrics").starts_with("HTTP/1.1 200 OK"));
+        assert!(start.elapsed() < CONNECTION_TIMEOUT);
+    }
+}
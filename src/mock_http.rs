@@ -0,0 +1,80 @@
+//! A tiny HTTP server for testing the Vault and AWS clients offline.
+
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug)]
+pub struct Request {
+    /// "METHOD /path".
+    pub line: String,
+    /// Lowercase names.
+    pub headers: HashMap<String, String>,
+    /// The JSON body, or `Null`.
+    pub body: Value,
+}
+
+/// Serves `routes`: "METHOD /path" answered with a status and a body, JSON
+/// or, for a string, plain text. Anything else gets a 404. Returns the base
+/// URL and the requests received so far.
+pub fn mock_http(routes: &[(&str, u16, Value)]) -> (String, Arc<Mutex<Vec<Request>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = format!("http://{}", listener.local_addr().unwrap());
+    let routes: Vec<(String, u16, Value)> = routes
+        .iter()
+        .map(|(route, status, body)| (route.to_string(), *status, body.clone()))
+        .collect();
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let seen = requests.clone();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let mut headers = HashMap::new();
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                let Some((name, value)) = header.trim_end().split_once(": ") else {
+                    break;
+                };
+                headers.insert(name.to_ascii_lowercase(), value.to_string());
+            }
+            let len = headers
+                .get("content-length")
+                .map_or(0, |len| len.parse().unwrap());
+            let mut body = vec![0u8; len];
+            reader.read_exact(&mut body).unwrap();
+
+            let route = line.rsplit_once(' ').unwrap().0.to_string();
+            let (status, response) = routes
+                .iter()
+                .find(|(r, _, _)| *r == route)
+                .map_or((404, json!({ "errors": [] })), |(_, status, body)| {
+                    (*status, body.clone())
+                });
+            seen.lock().unwrap().push(Request {
+                line: route,
+                headers,
+                body: serde_json::from_slice(&body).unwrap_or(Value::Null),
+            });
+            let (content_type, response) = match response {
+                Value::String(text) => ("text/plain", text),
+                json => ("application/json", json.to_string()),
+            };
+            write!(
+                stream,
+                "HTTP/1.1 {} Mock\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                content_type,
+                response.len(),
+                response
+            )
+            .unwrap();
+        }
+    });
+    (addr, requests)
+}
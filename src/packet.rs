@@ -0,0 +1,363 @@
+//! Per-packet AEAD framing for the RTP streaming path.
+//!
+//! Each RTP packet is sealed on its own with AES-GCM:
+//!
+//! ```text
+//! version u8 | session [u8; 16] | started u64 | counter u64 | ciphertext | tag [u8; 16]
+//! ```
+//!
+//! Every sender start picks a random 16-byte session ID, and the packets of
+//! that session are sealed with a key derived from the configured key and the
+//! session ID with HKDF-SHA256. The nonce is the 64-bit packet counter, which
+//! only has to be unique within the session since the key changes with it.
+//! The header is carried in the clear and authenticated as associated data.
+//! Packets are independent, so loss and reordering on the network do not
+//! affect decryption of the others, but the receiver remembers which
+//! counters it has seen and drops replays.
+//!
+//! `started` is the sender's start time in nanoseconds since the Unix epoch.
+//! The receiver follows one session at a time and only moves to a session
+//! that started later, so a replayed packet of an earlier run cannot take
+//! over from the live one. A session that has been silent for
+//! `SESSION_TIMEOUT` can be replaced by any other, so a sender whose clock
+//! went back across a restart is picked up again.
+
+use crate::container::Cipher;
+use anyhow::Result;
+use hkdf::Hkdf;
+use rand::RngCore;
+use sha2::Sha256;
+use std::time::{Duration, Instant, SystemTime};
+use zeroize::Zeroizing;
+
+pub const VERSION: u8 = 2;
+const SESSION_LEN: usize = 16;
+const HEADER_LEN: usize = 1 + SESSION_LEN + 8 + 8;
+const TAG_LEN: usize = 16;
+/// Bytes added to every packet by `Sealer::seal`.
+pub const OVERHEAD: usize = HEADER_LEN + TAG_LEN;
+const HKDF_INFO: &[u8] = b"eightyeightyeight packet key";
+/// How far behind the newest packet a packet may arrive and still be opened.
+const REPLAY_WINDOW: u64 = 128;
+/// How long a session may go without a packet before any other session can
+/// replace it.
+const SESSION_TIMEOUT: Duration = Duration::from_secs(10);
+
+type SessionId = [u8; SESSION_LEN];
+
+/// Packet key of a session, the same length as the configured key.
+fn session_cipher(key: &[u8], session: &SessionId) -> Result<Cipher> {
+    let mut session_key = Zeroizing::new(vec![0u8; key.len()]);
+    Hkdf::<Sha256>::new(Some(session), key)
+        .expand(HKDF_INFO, &mut session_key)
+        .expect("16 or 32 bytes is a valid HKDF-SHA256 output length");
+    Cipher::from_key(&session_key)
+}
+
+fn nonce(counter: u64) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(&counter.to_be_bytes());
+    nonce
+}
+
+/// Encrypts outgoing packets under a fresh session key.
+pub struct Sealer {
+    cipher: Cipher,
+    session: SessionId,
+    started: u64,
+    counter: u64,
+}
+
+impl Sealer {
+    pub fn new(key: &[u8]) -> Result<Self> {
+        let started = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |since| since.as_nanos() as u64);
+        Self::started_at(key, started)
+    }
+
+    fn started_at(key: &[u8], started: u64) -> Result<Self> {
+        let mut session = [0u8; SESSION_LEN];
+        rand::rng().fill_bytes(&mut session);
+        Ok(Self {
+            cipher: session_cipher(key, &session)?,
+            session,
+            started,
+            counter: 0,
+        })
+    }
+
+    pub fn seal(&mut self, payload: &[u8]) -> Vec<u8> {
+        let mut packet = Vec::with_capacity(OVERHEAD + payload.len());
+        packet.push(VERSION);
+        packet.extend_from_slice(&self.session);
+        packet.extend_from_slice(&self.started.to_be_bytes());
+        packet.extend_from_slice(&self.counter.to_be_bytes());
+
+        let mut sealed = payload.to_vec();
+        self.cipher
+            .seal(&nonce(self.counter), &packet[..HEADER_LEN], &mut sealed);
+        // 2^64 packets is out of reach for any real session.
+        self.counter += 1;
+
+        packet.extend_from_slice(&sealed);
+        packet
+    }
+}
+
+/// Counters seen in the last `REPLAY_WINDOW` packets of a session, as in
+/// the anti-replay window of IPsec (RFC 4303).
+#[derive(Default)]
+struct ReplayWindow {
+    highest: Option<u64>,
+    seen: u128,
+}
+
+impl ReplayWindow {
+    fn accepts(&self, counter: u64) -> bool {
+        match self.highest {
+            Some(highest) if counter <= highest => {
+                let age = highest - counter;
+                age < REPLAY_WINDOW && self.seen & (1 << age) == 0
+            }
+            _ => true,
+        }
+    }
+
+    fn mark(&mut self, counter: u64) {
+        match self.highest {
+            Some(highest) if counter <= highest => self.seen |= 1 << (highest - counter),
+            Some(highest) => {
+                let shift = counter - highest;
+                self.seen = if shift < REPLAY_WINDOW {
+                    self.seen << shift
+                } else {
+                    0
+                } | 1;
+                self.highest = Some(counter);
+            }
+            None => {
+                self.seen = 1;
+                self.highest = Some(counter);
+            }
+        }
+    }
+}
+
+struct Session {
+    id: SessionId,
+    started: u64,
+    cipher: Cipher,
+    window: ReplayWindow,
+    last_opened: Instant,
+}
+
+/// Authenticates and decrypts incoming packets, dropping replays.
+pub struct Opener {
+    key: Zeroizing<Vec<u8>>,
+    current: Option<Session>,
+}
+
+impl Opener {
+    pub fn new(key: &[u8]) -> Result<Self> {
+        Cipher::from_key(key)?;
+        Ok(Self {
+            key: Zeroizing::new(key.to_vec()),
+            current: None,
+        })
+    }
+
+    pub fn open(&mut self, packet: &[u8]) -> Result<Vec<u8>> {
+        if packet.len() < OVERHEAD {
+            anyhow::bail!("Packet too short: {} bytes", packet.len());
+        }
+        if packet[0] != VERSION {
+            anyhow::bail!("Unsupported packet version {}", packet[0]);
+        }
+        let (header, sealed) = packet.split_at(HEADER_LEN);
+        let id: SessionId = header[1..1 + SESSION_LEN].try_into().unwrap();
+        let started =
+            u64::from_be_bytes(header[1 + SESSION_LEN..HEADER_LEN - 8].try_into().unwrap());
+        let counter = u64::from_be_bytes(header[HEADER_LEN - 8..].try_into().unwrap());
+
+        // Another session only replaces the current one once one of its
+        // packets authenticates, so forged headers cannot reset the window,
+        // and only if it started later or the current one has gone quiet,
+        // so replayed packets of earlier runs cannot either.
+        let mut new_session = None;
+        let session = match &mut self.current {
+            Some(session) if session.id == id => session,
+            Some(session)
+                if started <= session.started
+                    && session.last_opened.elapsed() < SESSION_TIMEOUT =>
+            {
+                anyhow::bail!("Packet from an earlier session")
+            }
+            _ => new_session.insert(Session {
+                id,
+                started,
+                cipher: session_cipher(&self.key, &id)?,
+                window: ReplayWindow::default(),
+                last_opened: Instant::now(),
+            }),
+        };
+        if !session.window.accepts(counter) {
+            anyhow::bail!("Replayed packet {}", counter);
+        }
+        let mut buf = sealed.to_vec();
+        if !session.cipher.open(&nonce(counter), header, &mut buf) {
+            anyhow::bail!("Packet failed authentication");
+        }
+        session.window.mark(counter);
+        session.last_opened = Instant::now();
+
+        if let Some(session) = new_session {
+            self.current = Some(session);
+        }
+        Ok(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::parse_key;
+
+    const KEY: &str = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+
+    #[test]
+    fn test_roundtrip() {
+        let key = parse_key(KEY).unwrap();
+        let mut sealer = Sealer::new(&key).unwrap();
+        let mut opener = Opener::new(&key).unwrap();
+
+        let packet = sealer.seal(b"rtp packet");
+        assert_eq!(packet.len(), b"rtp packet".len() + OVERHEAD);
+        assert_eq!(opener.open(&packet).unwrap(), b"rtp packet");
+
+        let key = parse_key(&KEY[..32]).unwrap();
+        let mut sealer = Sealer::new(&key).unwrap();
+        let mut opener = Opener::new(&key).unwrap();
+        assert_eq!(opener.open(&sealer.seal(b"aes-128")).unwrap(), b"aes-128");
+    }
+
+    #[test]
+    fn test_nonce_differs_per_packet() {
+        let key = parse_key(KEY).unwrap();
+        let mut sealer = Sealer::new(&key).unwrap();
+        let a = sealer.seal(b"same");
+        let b = sealer.seal(b"same");
+        assert_ne!(a[..HEADER_LEN], b[..HEADER_LEN]);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn test_sessions_use_different_keys() {
+        let key = parse_key(KEY).unwrap();
+        let a = Sealer::new(&key).unwrap().seal(b"same");
+        let b = Sealer::new(&key).unwrap().seal(b"same");
+        // Same counter, different session: the ciphertexts must differ too.
+        assert_eq!(a[HEADER_LEN - 8..HEADER_LEN], b[HEADER_LEN - 8..HEADER_LEN]);
+        assert_ne!(a[1..1 + SESSION_LEN], b[1..1 + SESSION_LEN]);
+        assert_ne!(a[HEADER_LEN..], b[HEADER_LEN..]);
+    }
+
+    #[test]
+    fn test_packets_open_out_of_order() {
+        let key = parse_key(KEY).unwrap();
+        let mut sealer = Sealer::new(&key).unwrap();
+        let mut opener = Opener::new(&key).unwrap();
+        let first = sealer.seal(b"first");
+        let second = sealer.seal(b"second");
+        assert_eq!(opener.open(&second).unwrap(), b"second");
+        assert_eq!(opener.open(&first).unwrap(), b"first");
+    }
+
+    #[test]
+    fn test_rejects_replays() {
+        let key = parse_key(KEY).unwrap();
+        let mut sealer = Sealer::new(&key).unwrap();
+        let mut opener = Opener::new(&key).unwrap();
+
+        let first = sealer.seal(b"first");
+        opener.open(&first).unwrap();
+        assert!(opener.open(&first).is_err());
+
+        // Too far behind the newest packet to tell whether it was seen.
+        let packets: Vec<_> = (0..REPLAY_WINDOW + 1).map(|_| sealer.seal(b"p")).collect();
+        opener.open(packets.last().unwrap()).unwrap();
+        assert!(opener.open(&packets[0]).is_err());
+        opener.open(&packets[1]).unwrap();
+        assert!(opener.open(&packets[1]).is_err());
+
+        // Once the sender restarts, the old session is closed for good.
+        let mut restarted = Sealer::started_at(&key, sealer.started + 1).unwrap();
+        opener.open(&restarted.seal(b"new")).unwrap();
+        assert!(opener.open(&packets[2]).is_err());
+    }
+
+    #[test]
+    fn test_replayed_earlier_session_does_not_take_over() {
+        let key = parse_key(KEY).unwrap();
+        let mut earlier = Sealer::started_at(&key, 1).unwrap();
+        let mut live = Sealer::started_at(&key, 2).unwrap();
+        let old = earlier.seal(b"old");
+        let mut opener = Opener::new(&key).unwrap();
+
+        opener.open(&live.seal(b"live")).unwrap();
+        assert!(opener.open(&old).is_err());
+        assert_eq!(
+            opener.open(&live.seal(b"still live")).unwrap(),
+            b"still live"
+        );
+
+        // Replayed before the live session is seen, it is replaced at once.
+        let mut opener = Opener::new(&key).unwrap();
+        opener.open(&old).unwrap();
+        assert_eq!(opener.open(&live.seal(b"live")).unwrap(), b"live");
+        assert!(opener.open(&earlier.seal(b"old")).is_err());
+
+        // A silent session gives way, for senders whose clock went back.
+        opener.current.as_mut().unwrap().last_opened -= SESSION_TIMEOUT;
+        assert_eq!(opener.open(&earlier.seal(b"back")).unwrap(), b"back");
+    }
+
+    #[test]
+    fn test_forged_session_does_not_reset_the_window() {
+        let key = parse_key(KEY).unwrap();
+        let mut sealer = Sealer::new(&key).unwrap();
+        let mut opener = Opener::new(&key).unwrap();
+        let packet = sealer.seal(b"rtp packet");
+        opener.open(&packet).unwrap();
+
+        let mut forged = packet.clone();
+        forged[1] ^= 0x01;
+        assert!(opener.open(&forged).is_err());
+        assert!(opener.open(&packet).is_err());
+        opener.open(&sealer.seal(b"next")).unwrap();
+    }
+
+    #[test]
+    fn test_rejects_tampered_packet() {
+        let key = parse_key(KEY).unwrap();
+        let mut sealer = Sealer::new(&key).unwrap();
+        let mut opener = Opener::new(&key).unwrap();
+
+        let mut packet = sealer.seal(b"rtp packet");
+        packet[OVERHEAD] ^= 0x01;
+        assert!(opener.open(&packet).is_err());
+
+        let mut packet = sealer.seal(b"rtp packet");
+        packet[HEADER_LEN - 1] ^= 0x01; // counter
+        assert!(opener.open(&packet).is_err());
+
+        assert!(opener.open(&[VERSION; 10]).is_err());
+    }
+
+    #[test]
+    fn test_rejects_wrong_key() {
+        let mut sealer = Sealer::new(&parse_key(KEY).unwrap()).unwrap();
+        let mut opener = Opener::new(&parse_key(&"ff".repeat(32)).unwrap()).unwrap();
+        assert!(opener.open(&sealer.seal(b"rtp packet")).is_err());
+    }
+}
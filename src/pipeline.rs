@@ -1,21 +1,33 @@
-use crate::config::Config;
+use crate::cli::ExportFormat;
+use crate::config::{Config, DEFAULT_STREAM_PORT, Device, Transport};
+use crate::container::{self, Format};
+use crate::controls;
+use crate::manifest::{self, FragmentEvent};
+use crate::playlist::Playlist;
+use crate::segments::{self, SegmentNamer};
+use crate::srtp;
+use crate::verify::StreamStats;
 use anyhow::{Context, Result};
 use gstreamer as gst;
 use gstreamer::prelude::*;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, mpsc};
 use std::thread;
 use std::time::Duration;
 use tracing::{error, info, warn};
 
-pub fn build_record_pipeline(config: &Config) -> Result<String> {
+/// Name of the `h264parse` in export and verify pipelines.
+const PARSER: &str = "parse";
+
+/// Capture and encode chain shared by every live mode: source, caps,
+/// optional face detection and `x264enc`.
+fn build_capture_chain(config: &Config) -> Result<Vec<String>> {
     let mut parts = Vec::new();
 
-    let source = if config.device == "auto" {
-        "autovideosrc".to_string()
-    } else if config.device == "videotestsrc" {
-        "videotestsrc is-live=true".to_string()
-    } else {
-        format!("v4l2src device={}", config.device)
+    let source = match &config.device {
+        Device::Auto => "autovideosrc".to_string(),
+        Device::TestSource => "videotestsrc is-live=true".to_string(),
+        Device::V4l2(path) => format!("v4l2src device={}", gst_quote(&path.to_string_lossy())),
     };
     parts.push(source);
 
@@ -39,10 +51,82 @@ pub fn build_record_pipeline(config: &Config) -> Result<String> {
         "x264enc tune=zerolatency speed-preset=ultrafast bitrate={}",
         config.bitrate
     ));
-    parts.push("queue".to_string());
-    parts.push("h264parse".to_string());
 
-    if config.max_files.is_some() || config.max_file_size_mb.is_some() {
+    Ok(parts)
+}
+
+/// `tee` branches of the combined record and stream pipeline. Each branch is
+/// a bin so that a failed one can be told apart and cut off as a whole (see
+/// `handle_branch_error`).
+const RECORD_BRANCH: &str = "record";
+const STREAM_BRANCH: &str = "stream";
+
+/// Queues at the head of each `tee` branch. Both are leaky, so neither
+/// branch can stall the other: a disk stall longer than the record queue's
+/// 2 s (or 64 MB) drops the oldest frames of the recording, counted in the
+/// metrics, and a blocked socket drops frames of the stream.
+const RECORD_QUEUE: &str = "record_queue";
+const RECORD_BRANCH_QUEUE: &str = "queue name=record_queue leaky=downstream max-size-buffers=0 max-size-bytes=64000000 max-size-time=2000000000";
+const STREAM_BRANCH_QUEUE: &str =
+    "queue leaky=downstream max-size-buffers=0 max-size-bytes=0 max-size-time=500000000";
+
+pub fn build_record_pipeline(config: &Config) -> Result<String> {
+    if config.key.is_empty() && config.recipient.is_none() {
+        anyhow::bail!("Recording needs a key or a recipient");
+    }
+    let mut parts = build_capture_chain(config)?;
+
+    let Some(dest) = &config.stream_dest else {
+        parts.push("queue".to_string());
+        parts.extend(build_record_branch(config));
+        return Ok(parts.join(" ! "));
+    };
+
+    // Record and stream from the same capture and encoder.
+    let port = config.stream_port.unwrap_or(DEFAULT_STREAM_PORT);
+    let transport = config.stream_transport.unwrap_or(Transport::Aead);
+    parts.push("tee name=t allow-not-linked=true".to_string());
+
+    let mut record = vec![RECORD_BRANCH_QUEUE.to_string()];
+    record.extend(build_record_branch(config));
+    let mut stream = vec![STREAM_BRANCH_QUEUE.to_string()];
+    stream.extend(build_stream_branch(config, dest, port, transport)?);
+
+    Ok(format!(
+        "{} t. ! ( name={} {} ) t. ! ( name={} {} )",
+        parts.join(" ! "),
+        RECORD_BRANCH,
+        record.join(" ! "),
+        STREAM_BRANCH,
+        stream.join(" ! ")
+    ))
+}
+
+/// `aeadfilesink` with the recipient public key when set, so the recorder
+/// cannot decrypt its own files, or for the master key otherwise (set by
+/// `set_keys`).
+fn build_file_sink(config: &Config) -> String {
+    match &config.recipient {
+        Some(recipient) => format!("aeadfilesink recipient={}", recipient),
+        None => "aeadfilesink".to_string(),
+    }
+}
+
+/// Everything after the encoder that writes H.264 to encrypted files.
+fn build_record_branch(config: &Config) -> Vec<String> {
+    let mut parts = vec!["h264parse".to_string()];
+
+    if let Some(secs) = config.segment_duration_secs {
+        // File names come from the format-location-full handler connected in
+        // `setup_time_rotation`. Keyframe requests make splits land exactly on
+        // the boundary; they only work while max-size-bytes is 0.
+        parts.push(format!(
+            "splitmuxsink name=splitmux muxer=mpegtsmux sink=\"{}\" max-size-time={} max-size-bytes={} send-keyframe-requests=true async-finalize=true",
+            build_file_sink(config),
+            secs.get() * 1_000_000_000,
+            config.max_file_size_mb.unwrap_or(0) * 1_000_000
+        ));
+    } else if config.max_files.is_some() || config.max_file_size_mb.is_some() {
         // Use splitmuxsink for rotation
         let mut location = config.output_path.to_string_lossy().to_string();
         if !location.contains('%')
@@ -68,20 +152,15 @@ pub fn build_record_pipeline(config: &Config) -> Result<String> {
         let max_size_bytes = config.max_file_size_mb.unwrap_or(0) * 1_000_000;
         let max_files = config.max_files.unwrap_or(0);
 
-        // We use splitmuxsink with a custom sink bin that includes encryption.
+        // We use splitmuxsink with our encrypting sink element.
         // splitmuxsink manages the muxer (mpegtsmux) and resets it for each file.
-        // The sink property defines where the muxed stream goes.
-        // We use rndbuffersize to aggregate 4 TS packets (4 * 188 = 752 bytes).
-        // 752 is divisible by 16 (AES block size), ensuring alignment for most buffers.
-        // per-buffer-padding=false means only the final buffer (EOS) is padded if needed.
-        let sink_str = format!(
-            "rndbuffersize min=752 max=752 ! aesenc cipher=aes-256-cbc key={} serialize-iv=true per-buffer-padding=false ! filesink",
-            config.key
-        );
+        // aeadfilesink restarts for every fragment, so each file gets its own
+        // header and nonce prefix and is independently decryptable.
+        let sink_str = build_file_sink(config);
 
         parts.push(format!(
             "splitmuxsink location={} muxer=mpegtsmux sink=\"{}\" max-size-bytes={} max-files={} async-finalize=true",
-            location,
+            gst_quote(&location),
             sink_str,
             max_size_bytes,
             max_files
@@ -90,103 +169,205 @@ pub fn build_record_pipeline(config: &Config) -> Result<String> {
         // Standard single file recording
         parts.push("mpegtsmux".to_string());
         parts.push("queue".to_string());
-        parts.push("rndbuffersize min=752 max=752".to_string());
-        parts.push(format!(
-            "aesenc cipher=aes-256-cbc key={} serialize-iv=true per-buffer-padding=false",
-            config.key
-        ));
         parts.push(format!(
-            "filesink location={}",
-            config.output_path.to_string_lossy()
+            "{} location={}",
+            build_file_sink(config),
+            gst_quote(&config.output_path.to_string_lossy())
         ));
     }
 
-    Ok(parts.join(" ! "))
+    parts
+}
+
+/// Launch description for the RTSP media factory. `pay0` is the name
+/// rtsp-server looks for to find the stream's payloader.
+#[cfg_attr(not(feature = "rtsp"), allow(dead_code))]
+pub fn build_serve_pipeline(config: &Config) -> Result<String> {
+    let mut parts = build_capture_chain(config)?;
+    parts.push("rtph264pay name=pay0 pt=96 config-interval=1".to_string());
+    Ok(format!("( {} )", parts.join(" ! ")))
 }
 
-pub fn build_play_pipeline(config: &Config, input_file: &str) -> String {
-    let parts = [
-        format!("filesrc location={}", input_file),
-        format!(
-            "aesdec cipher=aes-256-cbc key={} serialize-iv=true per-buffer-padding=false",
-            config.key
-        ),
+/// Quotes a property value in a launch description, so that a path with
+/// spaces or `!` in it stays a single value.
+fn gst_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Launch syntax for an array property of strings. The array is quoted as
+/// a whole too, since the launch parser unescapes it once before the array
+/// itself is parsed.
+fn gst_array(items: &[String]) -> String {
+    let items: Vec<String> = items.iter().map(|item| gst_quote(item)).collect();
+    gst_quote(&format!("<{}>", items.join(",")))
+}
+
+/// Source elements that read and decrypt `inputs` into an MPEG-TS stream.
+/// Their keys are set by `set_keys`.
+fn build_decrypt_source(
+    config: &Config,
+    inputs: &[PathBuf],
+    format: Format,
+) -> Result<Vec<String>> {
+    if config.keys().is_empty() {
+        anyhow::bail!("No key or identity configured");
+    }
+    let aead_src = "aeadfilesrc";
+    Ok(match (format, inputs) {
+        (Format::Aead, [input]) => vec![format!(
+            "{} location={}",
+            aead_src,
+            gst_quote(&input.to_string_lossy())
+        )],
+        // Segments are read back to back as one stream by a single source.
+        (Format::Aead, inputs) => {
+            let locations: Vec<String> = inputs
+                .iter()
+                .map(|input| input.display().to_string())
+                .collect();
+            vec![format!("{} locations={}", aead_src, gst_array(&locations))]
+        }
+        // Legacy recordings have no integrity protection; kept for reading only.
+        (Format::LegacyCbc, [input]) => vec![
+            format!("filesrc location={}", gst_quote(&input.to_string_lossy())),
+            "aesdec cipher=aes-256-cbc serialize-iv=true per-buffer-padding=false".to_string(),
+        ],
+        (Format::LegacyCbc, _) => {
+            anyhow::bail!("Legacy recordings can only be played one file at a time")
+        }
+    })
+}
+
+pub fn build_play_pipeline(config: &Config, inputs: &[PathBuf], format: Format) -> Result<String> {
+    let mut parts = build_decrypt_source(config, inputs, format)?;
+    parts.extend([
         "tsdemux".to_string(),
         "h264parse".to_string(),
         "decodebin".to_string(),
         "autovideosink".to_string(),
-    ];
-    parts.join(" ! ")
+    ]);
+    Ok(parts.join(" ! "))
 }
 
-pub fn build_stream_pipeline(config: &Config, dest: &str, port: u16) -> Result<String> {
-    let mut parts = Vec::new();
-
-    let source = if config.device == "auto" {
-        "autovideosrc".to_string()
-    } else if config.device == "videotestsrc" {
-        "videotestsrc is-live=true".to_string()
-    } else {
-        format!("v4l2src device={}", config.device)
-    };
-    parts.push(source);
-
-    parts.push(format!(
-        "video/x-raw,width={},height={},framerate={}",
-        config.width, config.height, config.framerate
-    ));
+/// Remuxes the decrypted H.264 stream into `output` without re-encoding.
+/// The parser is named so trimming can hook onto its output.
+pub fn build_export_pipeline(
+    config: &Config,
+    inputs: &[PathBuf],
+    format: Format,
+    output: &Path,
+    export_format: ExportFormat,
+) -> Result<String> {
+    let mut parts = build_decrypt_source(config, inputs, format)?;
+    parts.extend([
+        "tsdemux".to_string(),
+        format!("h264parse name={}", PARSER),
+        match export_format {
+            // Moves the index to the front so players can start before the
+            // whole file is downloaded.
+            ExportFormat::Mp4 => "mp4mux faststart=true".to_string(),
+            ExportFormat::Mkv => "matroskamux".to_string(),
+        },
+        format!("filesink location={}", gst_quote(&output.to_string_lossy())),
+    ]);
+    Ok(parts.join(" ! "))
+}
 
-    parts.push("videoconvert".to_string());
+pub fn build_stream_pipeline(
+    config: &Config,
+    dest: &str,
+    port: u16,
+    transport: Transport,
+) -> Result<String> {
+    let mut parts = build_capture_chain(config)?;
+    parts.extend(build_stream_branch(config, dest, port, transport)?);
+    Ok(parts.join(" ! "))
+}
 
-    if config.cv_enabled {
-        check_element_exists("facedetect")?;
-        parts.push("videoconvert".to_string());
-        parts.push("facedetect".to_string());
-        parts.push("videoconvert".to_string());
+/// Everything after the encoder that sends encrypted RTP over UDP.
+fn build_stream_branch(
+    config: &Config,
+    dest: &str,
+    port: u16,
+    transport: Transport,
+) -> Result<Vec<String>> {
+    // A recipient public key only covers recordings.
+    if config.key.is_empty() {
+        anyhow::bail!("Streaming needs a key");
+    }
+    let mut parts = Vec::new();
+    match transport {
+        Transport::Aead => {
+            parts.push("rtph264pay config-interval=1 mtu=1400".to_string());
+            parts.push("queue".to_string());
+            parts.push("aeadpktenc".to_string());
+        }
+        Transport::Srtp => {
+            // The SSRC and key are per session; see `start_srtp_session`.
+            parts.push(format!(
+                "rtph264pay config-interval=1 mtu=1400 pt={}",
+                srtp::PAYLOAD_TYPE
+            ));
+            parts.push("queue".to_string());
+            parts.push(format!(
+                "srtpenc rtp-cipher={} rtp-auth={} rtcp-cipher={} rtcp-auth={}",
+                srtp::CIPHER,
+                srtp::AUTH,
+                srtp::CIPHER,
+                srtp::AUTH
+            ));
+        }
     }
-
-    parts.push("video/x-raw,format=I420".to_string());
-    parts.push("queue".to_string());
-    parts.push(format!(
-        "x264enc tune=zerolatency speed-preset=ultrafast bitrate={}",
-        config.bitrate
-    ));
-    parts.push("rtph264pay config-interval=1 mtu=1400".to_string());
-    parts.push("queue".to_string());
-    parts.push(format!(
-        "aesenc cipher=aes-256-cbc key={} iv={} per-buffer-padding=true serialize-iv=true",
-        config.key, config.key
-    ));
     parts.push(format!("udpsink host={} port={}", dest, port));
 
-    Ok(parts.join(" ! "))
+    Ok(parts)
 }
 
-pub fn build_receive_pipeline(config: &Config, listen: &str, port: u16) -> String {
-    let parts = [
-        format!("udpsrc address={} port={}", listen, port),
-        "application/x-rtp,media=(string)video,clock-rate=(int)90000,encoding-name=(string)H264"
-            .to_string(),
-        format!(
-            "aesdec cipher=aes-256-cbc key={} iv={} per-buffer-padding=true serialize-iv=true",
-            config.key, config.key
-        ),
-        "rtph264depay".to_string(),
-        "decodebin".to_string(),
-        "queue".to_string(),
-        "autovideosink sync=false".to_string(),
-    ];
-    parts.join(" ! ")
+pub fn build_receive_pipeline(listen: &str, port: u16, transport: Transport) -> Result<String> {
+    let mut parts = vec![format!("udpsrc address={} port={}", listen, port)];
+    match transport {
+        Transport::Aead => {
+            parts.push(
+                "application/x-rtp,media=(string)video,clock-rate=(int)90000,encoding-name=(string)H264"
+                    .to_string(),
+            );
+            parts.push("aeadpktdec".to_string());
+        }
+        Transport::Srtp => {
+            // srtpdec asks for the key of each SSRC it sees; see
+            // `connect_srtp_keys`.
+            parts.push(format!(
+                "application/x-srtp,media=(string)video,clock-rate=(int)90000,encoding-name=(string)H264,payload=(int){}",
+                srtp::PAYLOAD_TYPE
+            ));
+            parts.push("srtpdec".to_string());
+        }
+    }
+    parts.push("rtph264depay".to_string());
+    parts.push("decodebin".to_string());
+    parts.push("queue".to_string());
+    parts.push("autovideosink sync=false".to_string());
+    Ok(parts.join(" ! "))
 }
 
 pub fn run_record_pipeline(config: &Config) -> Result<()> {
-    gst::init().context("Failed to initialize GStreamer")?;
+    init()?;
+    if config.stream_dest.is_some() && config.stream_transport == Some(Transport::Srtp) {
+        check_element_exists("srtpenc")?;
+    }
     let pipeline_str = build_record_pipeline(config)?;
     info!("Pipeline: {}", pipeline_str);
-    let pipeline = gst::parse::launch(&pipeline_str)
-        .context("Failed to parse pipeline")?
-        .dynamic_cast::<gst::Pipeline>()
-        .map_err(|_| anyhow::anyhow!("Element is not a pipeline"))?;
+    let pipeline = launch(&pipeline_str, config)?;
+    if let Some(dest) = &config.stream_dest {
+        isolate_branches(&pipeline)?;
+        if config.stream_transport == Some(Transport::Srtp) {
+            let port = config.stream_port.unwrap_or(DEFAULT_STREAM_PORT);
+            start_srtp_session(&pipeline, config, srtp::Session::generate(), dest, port)?;
+        }
+    }
+    if let Some(secs) = config.segment_duration_secs {
+        setup_time_rotation(&pipeline, config, Duration::from_secs(secs.get()))?;
+    }
 
     // Disk space monitor thread
     if let Some(min_space_mb) = config.min_disk_space_mb {
@@ -217,40 +398,450 @@ pub fn run_record_pipeline(config: &Config) -> Result<()> {
         });
     }
 
-    run_pipeline_loop(&pipeline)
+    // Manifests are signed with the master key, which a recorder that only
+    // holds a recipient public key does not have.
+    let manifest = if uses_splitmux(config) && !config.key.is_empty() {
+        Some(start_manifest(&pipeline, config)?)
+    } else {
+        None
+    };
+
+    let result = run_pipeline_loop(&pipeline);
+
+    if let Some(writer) = manifest {
+        // Dropping the handler closes the channel so the writer finishes the
+        // last segment and exits.
+        if let Some(bus) = pipeline.bus() {
+            bus.unset_sync_handler();
+        }
+        if writer.join().is_err() {
+            error!("Manifest writer panicked");
+        }
+    }
+
+    result
 }
 
-pub fn run_play_pipeline(config: &Config, input_file: &str) -> Result<()> {
-    gst::init().context("Failed to initialize GStreamer")?;
-    let pipeline_str = build_play_pipeline(config, input_file);
+pub fn run_play_pipeline(config: &Config, playlist: &Playlist, seek_step: Duration) -> Result<()> {
+    init()?;
+    let format = playlist_format(config, playlist)?;
+    if playlist.files.len() > 1 {
+        info!("Playing {} segments as one recording", playlist.files.len());
+    }
+    let pipeline_str = build_play_pipeline(config, &playlist.files, format)?;
     info!("Pipeline: {}", pipeline_str);
-    let pipeline = gst::parse::launch(&pipeline_str)
-        .context("Failed to parse pipeline")?
-        .dynamic_cast::<gst::Pipeline>()
-        .map_err(|_| anyhow::anyhow!("Element is not a pipeline"))?;
-    run_pipeline_loop(&pipeline)
+    let pipeline = launch(&pipeline_str, config)?;
+
+    if let Some(offset) = playlist.start_offset {
+        // Seeking needs a prerolled pipeline.
+        pipeline
+            .set_state(gst::State::Paused)
+            .context("Failed to set pipeline to paused")?;
+        let (result, _, _) = pipeline.state(gst::ClockTime::from_seconds(10));
+        result.context("Failed to preroll pipeline")?;
+        info!(
+            "Starting {}s into {}",
+            offset.as_secs(),
+            playlist.files[0].display()
+        );
+        pipeline
+            .seek_simple(
+                gst::SeekFlags::FLUSH | gst::SeekFlags::KEY_UNIT,
+                gst::ClockTime::from_nseconds(offset.as_nanos() as u64),
+            )
+            .context("Failed to seek to start of time range")?;
+    }
+
+    let controls = controls::spawn(&pipeline, seek_step)?;
+    let result = run_pipeline_loop(&pipeline);
+    drop(controls);
+    result
 }
 
-pub fn run_stream_pipeline(config: &Config, dest: &str, port: u16) -> Result<()> {
-    gst::init().context("Failed to initialize GStreamer")?;
-    let pipeline_str = build_stream_pipeline(config, dest, port)?;
+/// Decrypts, demuxes and parses `input` as fast as possible, for `verify`.
+pub fn build_verify_pipeline(config: &Config, input: &Path, format: Format) -> Result<String> {
+    let mut parts = build_decrypt_source(config, &[input.to_path_buf()], format)?;
+    parts.extend([
+        "tsdemux".to_string(),
+        format!("h264parse name={}", PARSER),
+        "fakesink sync=false".to_string(),
+    ]);
+    Ok(parts.join(" ! "))
+}
+
+/// Runs the verify pipeline to the end and collects frame statistics. An
+/// error that stops the pipeline early is part of the result, not an `Err`.
+pub fn run_verify_pipeline(config: &Config, input: &Path, format: Format) -> Result<StreamStats> {
+    init()?;
+    check_element_exists("tsdemux")?;
+    check_element_exists("h264parse")?;
+    let pipeline_str = build_verify_pipeline(config, input, format)?;
+    let pipeline = launch(&pipeline_str, config)?;
+
+    let stats = Arc::new(std::sync::Mutex::new(StreamStats::default()));
+    let pad = pipeline
+        .by_name(PARSER)
+        .and_then(|parser| parser.static_pad("src"))
+        .context("Pipeline has no parser")?;
+    let probe_stats = stats.clone();
+    pad.add_probe(gst::PadProbeType::BUFFER, move |_pad, info| {
+        if let Some(gst::PadProbeData::Buffer(buffer)) = &info.data {
+            let mut stats = probe_stats.lock().unwrap();
+            stats.frames += 1;
+            if let Some(pts) = buffer.pts() {
+                let end = pts + buffer.duration().unwrap_or(gst::ClockTime::ZERO);
+                stats.first_pts = Some(stats.first_pts.map_or(pts, |first| first.min(pts)));
+                stats.end_pts = Some(stats.end_pts.map_or(end, |last| last.max(end)));
+                if !buffer.flags().contains(gst::BufferFlags::DELTA_UNIT) {
+                    stats.keyframes.push(pts);
+                }
+            }
+        }
+        gst::PadProbeReturn::Ok
+    });
+
+    pipeline
+        .set_state(gst::State::Playing)
+        .context("Failed to set pipeline to playing")?;
+    let guard = PipelineGuard(pipeline.clone());
+    let bus = pipeline.bus().context("Pipeline has no bus")?;
+    for msg in bus.iter_timed(gst::ClockTime::NONE) {
+        match msg.view() {
+            gst::MessageView::Eos(..) => break,
+            gst::MessageView::Error(err) => {
+                stats.lock().unwrap().error = Some(err.error().to_string());
+                break;
+            }
+            gst::MessageView::Warning(warning) => {
+                warn!("{}: {}", input.display(), warning.error());
+            }
+            _ => (),
+        }
+    }
+    // Stop the pipeline so its probe no longer updates the stats.
+    drop(guard);
+
+    let stats = std::mem::take(&mut *stats.lock().unwrap());
+    Ok(stats)
+}
+
+/// Detects the format of a single input; segment sets are always AEAD.
+/// Also checks that there is a key for every file, so a missing one is
+/// reported by key ID before anything is decrypted.
+fn playlist_format(config: &Config, playlist: &Playlist) -> Result<Format> {
+    let format = match playlist.files.as_slice() {
+        [input] => container::detect_format(input)?,
+        _ => Format::Aead,
+    };
+    if format == Format::LegacyCbc {
+        warn!(
+            "{} is a legacy CBC recording without integrity protection",
+            playlist.files[0].display()
+        );
+        return Ok(format);
+    }
+    let keys = config.keys();
+    for file in &playlist.files {
+        container::select_key(file, &keys)
+            .with_context(|| format!("Cannot decrypt {}", file.display()))?;
+    }
+    Ok(format)
+}
+
+pub fn run_export_pipeline(
+    config: &Config,
+    playlist: &Playlist,
+    output: &Path,
+    export_format: ExportFormat,
+    start: Option<Duration>,
+    end: Option<Duration>,
+) -> Result<()> {
+    init()?;
+    check_element_exists(match export_format {
+        ExportFormat::Mp4 => "mp4mux",
+        ExportFormat::Mkv => "matroskamux",
+    })?;
+    let format = playlist_format(config, playlist)?;
+    let pipeline_str =
+        build_export_pipeline(config, &playlist.files, format, output, export_format)?;
     info!("Pipeline: {}", pipeline_str);
-    let pipeline = gst::parse::launch(&pipeline_str)
-        .context("Failed to parse pipeline")?
-        .dynamic_cast::<gst::Pipeline>()
-        .map_err(|_| anyhow::anyhow!("Element is not a pipeline"))?;
+    let pipeline = launch(&pipeline_str, config)?;
+
+    let start = start.or(playlist.start_offset);
+    let end = end.or(playlist.end_offset);
+    if start.is_some() || end.is_some() {
+        setup_trim(&pipeline, start, end)?;
+    }
+
+    run_pipeline_loop(&pipeline)?;
+    info!("Exported to {}", output.display());
+    Ok(())
+}
+
+pub fn run_stream_pipeline(
+    config: &Config,
+    dest: &str,
+    port: u16,
+    transport: Transport,
+    sdp: Option<&Path>,
+) -> Result<()> {
+    init()?;
+    if transport == Transport::Srtp {
+        check_element_exists("srtpenc")?;
+    }
+    let pipeline_str = build_stream_pipeline(config, dest, port, transport)?;
+    info!("Pipeline: {}", pipeline_str);
+    let pipeline = launch(&pipeline_str, config)?;
+    if transport == Transport::Srtp {
+        let session = srtp::Session::generate();
+        if let Some(path) = sdp {
+            session.write_sdp(dest, port, path)?;
+        }
+        start_srtp_session(&pipeline, config, session, dest, port)?;
+    }
     run_pipeline_loop(&pipeline)
 }
 
-pub fn run_receive_pipeline(config: &Config, listen: &str, port: u16) -> Result<()> {
-    gst::init().context("Failed to initialize GStreamer")?;
-    let pipeline_str = build_receive_pipeline(config, listen, port);
+pub fn run_receive_pipeline(
+    config: &Config,
+    listen: &str,
+    port: u16,
+    transport: Transport,
+) -> Result<()> {
+    init()?;
+    if transport == Transport::Srtp {
+        check_element_exists("srtpdec")?;
+    }
+    let pipeline_str = build_receive_pipeline(listen, port, transport)?;
     info!("Pipeline: {}", pipeline_str);
-    let pipeline = gst::parse::launch(&pipeline_str)
+    let pipeline = launch(&pipeline_str, config)?;
+    if transport == Transport::Srtp {
+        let keys = srtp::KeyListener::bind(config.key.expose(), listen, port)?;
+        connect_srtp_keys(&pipeline, keys)?;
+    }
+    run_pipeline_loop(&pipeline)
+}
+
+/// How long `srtpdec` waits for the key of a new SSRC before dropping its
+/// packets; a few announcement intervals.
+const SRTP_KEY_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn find_element(pipeline: &gst::Pipeline, factory: &str) -> Result<gst::Element> {
+    pipeline
+        .iterate_recurse()
+        .into_iter()
+        .flatten()
+        .find(|element| factory_name(element) == factory)
+        .with_context(|| format!("No {} in pipeline", factory))
+}
+
+/// Keys the SRTP branch of `pipeline` with a fresh session and announces the
+/// key to the receiver at `dest` for as long as the pipeline is alive.
+fn start_srtp_session(
+    pipeline: &gst::Pipeline,
+    config: &Config,
+    session: srtp::Session,
+    dest: &str,
+    port: u16,
+) -> Result<()> {
+    find_element(pipeline, "rtph264pay")?.set_property("ssrc", session.ssrc);
+    let encoder = find_element(pipeline, "srtpenc")?;
+    encoder.set_property("key", gst::Buffer::from_slice(session.master.clone()));
+
+    let mut announcer = srtp::Announcer::new(config.key.expose(), dest, port)?;
+    let encoder = encoder.downgrade();
+    thread::spawn(move || {
+        while let Some(encoder) = encoder.upgrade() {
+            let roc = rollover_counter(&encoder, session.ssrc);
+            drop(encoder);
+            if let Err(e) = announcer.announce(&session, roc) {
+                warn!("{:#}", e);
+            }
+            thread::sleep(srtp::ANNOUNCE_INTERVAL);
+        }
+    });
+    Ok(())
+}
+
+/// Rollover counter of `ssrc` from the `srtpenc` stats, 0 until it has sent
+/// anything.
+fn rollover_counter(encoder: &gst::Element, ssrc: u32) -> u32 {
+    let stats = encoder.property::<gst::Structure>("stats");
+    let Ok(streams) = stats.get::<gst::Array>("streams") else {
+        return 0;
+    };
+    streams
+        .iter()
+        .filter_map(|stream| stream.get::<gst::Structure>().ok())
+        .find(|stream| stream.get::<u32>("ssrc") == Ok(ssrc))
+        .and_then(|stream| stream.get::<u32>("roc").ok())
+        .unwrap_or(0)
+}
+
+/// Hands `srtpdec` the announced key of each SSRC through its `request-key`
+/// signal, so the key never has to be part of the caps.
+fn connect_srtp_keys(pipeline: &gst::Pipeline, keys: srtp::KeyListener) -> Result<()> {
+    find_element(pipeline, "srtpdec")?.connect("request-key", false, move |args| {
+        let ssrc = args[1].get::<u32>().expect("request-key passes the SSRC");
+        let caps = keys.wait_for(ssrc, SRTP_KEY_TIMEOUT).map(|(roc, master)| {
+            gst::Caps::builder("application/x-srtp")
+                .field("srtp-key", gst::Buffer::from_slice(master))
+                .field("srtp-cipher", srtp::CIPHER)
+                .field("srtp-auth", srtp::AUTH)
+                .field("srtcp-cipher", srtp::CIPHER)
+                .field("srtcp-auth", srtp::AUTH)
+                .field("roc", roc)
+                .build()
+        });
+        if caps.is_none() {
+            warn!("No key announced for SSRC {:#010x}", ssrc);
+        }
+        Some(caps.to_value())
+    });
+    Ok(())
+}
+
+/// Dry run for `config check`: builds and parses every pipeline the
+/// configuration can run, without starting any of them, and returns what
+/// went wrong.
+pub fn check(config: &Config) -> Vec<String> {
+    if let Err(e) = init() {
+        return vec![format!("{:#}", e)];
+    }
+    let mut problems = Vec::new();
+    if let Device::V4l2(path) = &config.device
+        && !path.exists()
+    {
+        problems.push(format!("device: {} does not exist", path.display()));
+    }
+
+    let port = config.stream_port.unwrap_or(DEFAULT_STREAM_PORT);
+    let transport = config.stream_transport.unwrap_or(Transport::Aead);
+    let mut pipelines = vec![("record", build_record_pipeline(config))];
+    if !config.key.is_empty() {
+        let dest = config
+            .stream_dest
+            .as_deref()
+            .unwrap_or(crate::config::DEFAULT_STREAM_DEST);
+        let listen = config
+            .receive_listen
+            .as_deref()
+            .unwrap_or(crate::config::DEFAULT_LISTEN);
+        pipelines.push((
+            "stream",
+            build_stream_pipeline(config, dest, port, transport),
+        ));
+        pipelines.push(("receive", build_receive_pipeline(listen, port, transport)));
+    }
+    #[cfg(feature = "rtsp")]
+    pipelines.push(("serve", build_serve_pipeline(config)));
+
+    for (name, description) in pipelines {
+        let parsed = description.and_then(|description| {
+            gst::parse::launch(&description).context("Failed to parse pipeline")
+        });
+        if let Err(e) = parsed {
+            problems.push(format!("{} pipeline: {:#}", name, e));
+        }
+    }
+    problems
+}
+
+/// Parses a launch description and sets the keys on its elements.
+fn launch(description: &str, config: &Config) -> Result<gst::Pipeline> {
+    let pipeline = gst::parse::launch(description)
         .context("Failed to parse pipeline")?
         .dynamic_cast::<gst::Pipeline>()
         .map_err(|_| anyhow::anyhow!("Element is not a pipeline"))?;
-    run_pipeline_loop(&pipeline)
+    set_keys(&pipeline, config)?;
+    Ok(pipeline)
+}
+
+/// Sets the keys of the encrypting and decrypting elements in `pipeline`.
+///
+/// Launch descriptions never contain keys, so they can be logged, and turn
+/// up in GStreamer debug output, without leaking them. The element
+/// properties holding keys are write-only for the same reason.
+fn set_keys(pipeline: &gst::Pipeline, config: &Config) -> Result<()> {
+    for element in all_elements(pipeline)? {
+        match factory_name(&element).as_str() {
+            "aeadfilesink" if config.recipient.is_none() => {
+                element.set_property("key", config.key.expose());
+            }
+            "aeadpktenc" | "aeadpktdec" => element.set_property("key", config.key.expose()),
+            "aeadfilesrc" => {
+                let keys = config.keys();
+                let (key, keyring) = keys
+                    .split_first()
+                    .context("No key or identity configured")?;
+                element.set_property("key", key.expose());
+                element.set_property(
+                    "keyring",
+                    gst::Array::new(keyring.iter().map(|key| key.expose())),
+                );
+            }
+            // Legacy recordings only ever used the master key.
+            "aesdec" => {
+                let keys = config.keys();
+                let key = keys.first().context("No key configured")?;
+                element.set_property("key", key.expose());
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// Every element of `pipeline`, inside bins too.
+fn all_elements(pipeline: &gst::Pipeline) -> Result<Vec<gst::Element>> {
+    let mut elements = pipeline
+        .iterate_recurse()
+        .into_iter()
+        .collect::<Result<Vec<gst::Element>, _>>()
+        .context("Failed to list pipeline elements")?;
+    // splitmuxsink only adds its sink to itself once it starts.
+    let sinks: Vec<gst::Element> = elements
+        .iter()
+        .filter(|element| factory_name(element) == "splitmuxsink")
+        .filter_map(|element| element.property::<Option<gst::Element>>("sink"))
+        .collect();
+    elements.extend(sinks);
+    Ok(elements)
+}
+
+/// The sink elements whose input the metrics count. These are the actual
+/// sinks, also inside the `tee` branch bins and `splitmuxsink`, not the bins
+/// `iterate_sinks` returns, which have no sink pad of their own.
+fn metered_sinks(pipeline: &gst::Pipeline) -> Result<Vec<gst::Element>> {
+    Ok(all_elements(pipeline)?
+        .into_iter()
+        .filter(|element| {
+            element.element_flags().contains(gst::ElementFlags::SINK) && !element.is::<gst::Bin>()
+        })
+        .collect())
+}
+
+/// Counts the times the leaky record queue was full and dropped frames.
+fn count_record_drops(pipeline: &gst::Pipeline, metrics: Arc<Metrics>) {
+    if let Some(queue) = pipeline.by_name(RECORD_QUEUE) {
+        queue.connect("overrun", false, move |_| {
+            metrics.increment_record_drops();
+            None
+        });
+    }
+}
+
+fn factory_name(element: &gst::Element) -> String {
+    element
+        .factory()
+        .map(|factory| factory.name().to_string())
+        .unwrap_or_default()
+}
+
+fn init() -> Result<()> {
+    gst::init().context("Failed to initialize GStreamer")?;
+    crate::elements::register().context("Failed to register custom elements")?;
+    Ok(())
 }
 
 // RAII Guard for the pipeline
@@ -275,11 +866,265 @@ fn check_element_exists(element: &str) -> Result<()> {
     Ok(())
 }
 
+fn uses_splitmux(config: &Config) -> bool {
+    config.segment_duration_secs.is_some()
+        || config.max_files.is_some()
+        || config.max_file_size_mb.is_some()
+}
+
+/// Records every finished `splitmuxsink` fragment in the manifest.
+///
+/// Fragment messages are picked up by a bus sync handler, where the running
+/// time can still be mapped to wall-clock time accurately, and handed to a
+/// writer thread so hashing a finished segment never blocks streaming.
+fn start_manifest(pipeline: &gst::Pipeline, config: &Config) -> Result<thread::JoinHandle<()>> {
+    let mut recorder = manifest::Recorder::new(&config.output_path, config.key.expose())?;
+    let (tx, rx) = mpsc::channel::<FragmentEvent>();
+
+    let bus = pipeline.bus().context("Pipeline has no bus")?;
+    let pipeline_weak = pipeline.downgrade();
+    bus.set_sync_handler(move |_, msg| {
+        if let gst::MessageView::Element(elem_msg) = msg.view()
+            && let Some(structure) = elem_msg.structure()
+            && let (Ok(location), Ok(running_time)) = (
+                structure.get::<&str>("location"),
+                structure.get::<u64>("running-time"),
+            )
+        {
+            let time = pipeline_weak
+                .upgrade()
+                .map(|pipeline| utc_at(&pipeline, running_time))
+                .unwrap_or_else(chrono::Utc::now);
+            let location = PathBuf::from(location);
+            let event = match structure.name().as_str() {
+                "splitmuxsink-fragment-opened" => Some(FragmentEvent::Opened {
+                    location,
+                    running_time,
+                    time,
+                }),
+                "splitmuxsink-fragment-closed" => Some(FragmentEvent::Closed {
+                    location,
+                    running_time,
+                    time,
+                }),
+                _ => None,
+            };
+            if let Some(event) = event {
+                let _ = tx.send(event);
+            }
+        }
+        gst::BusSyncReply::Pass
+    });
+
+    Ok(thread::spawn(move || {
+        for event in rx {
+            if let Err(e) = recorder.handle(event) {
+                error!("Failed to update manifest: {}", e);
+            }
+        }
+    }))
+}
+
+/// Wall-clock time at which the pipeline of `element` was at `running_time`
+/// (in nanoseconds).
+fn utc_at(element: &impl IsA<gst::Element>, running_time: u64) -> chrono::DateTime<chrono::Utc> {
+    let now = element
+        .current_running_time()
+        .map(|t| t.nseconds())
+        .unwrap_or(running_time);
+    chrono::Utc::now() - chrono::Duration::nanoseconds(now.saturating_sub(running_time) as i64)
+}
+
+/// Running time of the first buffer of a fragment, from the sample
+/// `format-location-full` passes.
+fn sample_running_time(sample: &gst::Sample) -> Option<u64> {
+    let pts = sample.buffer()?.pts()?;
+    let segment = sample.segment()?.downcast_ref::<gst::ClockTime>()?;
+    segment.to_running_time(pts).map(|t| t.nseconds())
+}
+
+/// Names time-based segments by the capture time of their first frame and
+/// aligns the first split to the next wall-clock boundary; `max-size-time`
+/// keeps later splits on the boundaries from there.
+fn setup_time_rotation(pipeline: &gst::Pipeline, config: &Config, period: Duration) -> Result<()> {
+    let splitmux = pipeline
+        .by_name("splitmux")
+        .context("Pipeline has no splitmuxsink")?;
+
+    let namer = SegmentNamer::new(&config.output_path, config.max_files);
+    splitmux.connect("format-location-full", false, move |args| {
+        if let Err(e) = namer.enforce_retention() {
+            warn!("Failed to delete old segments: {}", e);
+        }
+        // The name is only asked for once the first buffer of the fragment
+        // is there, so name it after that buffer rather than the current time.
+        let splitmux = args[0]
+            .get::<gst::Element>()
+            .expect("signal is from splitmuxsink");
+        let start = args[2]
+            .get::<gst::Sample>()
+            .ok()
+            .and_then(|sample| sample_running_time(&sample))
+            .map(|running_time| utc_at(&splitmux, running_time))
+            .unwrap_or_else(chrono::Utc::now);
+        let path = namer.path_at(start);
+        Some(path.to_string_lossy().to_value())
+    });
+
+    // The running time is only known once the pipeline is playing.
+    let splitmux_weak = splitmux.downgrade();
+    thread::spawn(move || {
+        while let Some(splitmux) = splitmux_weak.upgrade() {
+            if let Some(running_time) = splitmux.current_running_time() {
+                let wait = segments::until_next_boundary(chrono::Utc::now(), period);
+                let split_at = running_time + gst::ClockTime::from_nseconds(wait.as_nanos() as u64);
+                splitmux.emit_by_name::<()>("split-at-running-time", &[&split_at.nseconds()]);
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    });
+
+    Ok(())
+}
+
+/// Trims an export to `[start, end)` in stream time by dropping parsed
+/// frames. Output starts at the first keyframe at or after `start`, since
+/// the stream is not re-encoded, and ends with EOS at `end`.
+fn setup_trim(
+    pipeline: &gst::Pipeline,
+    start: Option<Duration>,
+    end: Option<Duration>,
+) -> Result<()> {
+    let start = start
+        .map(|start| gst::ClockTime::try_from(start).unwrap_or(gst::ClockTime::MAX))
+        .unwrap_or(gst::ClockTime::ZERO);
+    let end = end.map(|end| gst::ClockTime::try_from(end).unwrap_or(gst::ClockTime::MAX));
+    let pad = pipeline
+        .by_name(PARSER)
+        .and_then(|parser| parser.static_pad("src"))
+        .context("Pipeline has no parser to trim at")?;
+
+    // Current segment and whether the first frame has been let through.
+    let state = std::sync::Mutex::new((gst::FormattedSegment::<gst::ClockTime>::new(), false));
+    pad.add_probe(
+        gst::PadProbeType::BUFFER | gst::PadProbeType::EVENT_DOWNSTREAM,
+        move |pad, info| {
+            match &info.data {
+                Some(gst::PadProbeData::Event(event)) => {
+                    if let gst::EventView::Segment(e) = event.view()
+                        && let Ok(new_segment) = e.segment().clone().downcast::<gst::ClockTime>()
+                    {
+                        state.lock().unwrap().0 = new_segment;
+                    }
+                }
+                Some(gst::PadProbeData::Buffer(buffer)) => {
+                    let mut state = state.lock().unwrap();
+                    let (segment, started) = &mut *state;
+                    let Some(position) = buffer.pts().and_then(|pts| segment.to_stream_time(pts))
+                    else {
+                        return gst::PadProbeReturn::Ok;
+                    };
+                    if end.is_some_and(|end| position >= end) {
+                        if let Some(peer) = pad.peer() {
+                            peer.send_event(gst::event::Eos::new());
+                        }
+                        // Stops the demuxer instead of reading to the end.
+                        info.data.take();
+                        info.flow_res = Err(gst::FlowError::Eos);
+                        return gst::PadProbeReturn::Handled;
+                    }
+                    if !*started {
+                        if position < start || buffer.flags().contains(gst::BufferFlags::DELTA_UNIT)
+                        {
+                            return gst::PadProbeReturn::Drop;
+                        }
+                        info!("Export starts at {:.3}", position);
+                        *started = true;
+                    }
+                }
+                _ => (),
+            }
+            gst::PadProbeReturn::Ok
+        },
+    );
+    Ok(())
+}
+
+/// Keeps a flow error in one `tee` branch from reaching the shared encoder.
+///
+/// `tee` returns the first error of any branch upstream, which would stop
+/// the source and every other branch with it. Buffers are therefore pushed
+/// into each branch by hand and the result is dropped; the failing element
+/// still posts its error and `handle_branch_error` removes the branch.
+fn isolate_branches(pipeline: &gst::Pipeline) -> Result<()> {
+    let tee = pipeline.by_name("t").context("Pipeline has no tee")?;
+    for pad in tee.src_pads() {
+        pad.add_probe(gst::PadProbeType::BUFFER, |pad, info| {
+            if let (Some(gst::PadProbeData::Buffer(buffer)), Some(peer)) =
+                (info.data.take(), pad.peer())
+            {
+                let _ = peer.chain(buffer);
+            }
+            gst::PadProbeReturn::Handled
+        });
+    }
+    Ok(())
+}
+
+/// Returns the `tee` branch bin that `obj` is part of, if any.
+fn branch_of(obj: &gst::Object) -> Option<gst::Bin> {
+    let mut current = Some(obj.clone());
+    while let Some(obj) = current {
+        if [RECORD_BRANCH, STREAM_BRANCH].contains(&obj.name().as_str())
+            && let Ok(bin) = obj.clone().downcast::<gst::Bin>()
+        {
+            return Some(bin);
+        }
+        current = obj.parent();
+    }
+    None
+}
+
+/// Cuts a failed branch off the `tee` so the rest of the pipeline keeps
+/// running. Returns `Ok(false)` if the error did not come from a branch, and
+/// an error once no branch is left.
+fn handle_branch_error(pipeline: &gst::Pipeline, src: &gst::Object) -> Result<bool> {
+    let Some(branch) = branch_of(src) else {
+        return Ok(false);
+    };
+
+    // Later errors from the same branch arrive after it was cut off.
+    if let Some(sink_pad) = branch.sink_pads().first()
+        && let Some(tee_pad) = sink_pad.peer()
+    {
+        tee_pad.unlink(sink_pad)?;
+        if let Some(tee) = tee_pad.parent_element() {
+            tee.release_request_pad(&tee_pad);
+        }
+        // The branch will never see the EOS sent on shutdown, so report it as
+        // done now; otherwise the pipeline would wait for it forever.
+        let _ = branch.post_message(gst::message::Eos::builder().src(&branch).build());
+        warn!(
+            "The {} branch failed and was stopped; the other output keeps running",
+            branch.name()
+        );
+    }
+
+    let tee = pipeline.by_name("t").context("Pipeline has no tee")?;
+    if tee.src_pads().is_empty() {
+        return Err(anyhow::anyhow!(
+            "Both the record and stream branches failed"
+        ));
+    }
+    Ok(true)
+}
+
 fn run_pipeline_loop(pipeline: &gst::Pipeline) -> Result<()> {
     // Setup Metrics
     let metrics = Arc::new(Metrics::new());
-    let mut iter = pipeline.iterate_sinks();
-    while let Ok(Some(elem)) = iter.next() {
+    count_record_drops(pipeline, metrics.clone());
+    for elem in metered_sinks(pipeline)? {
         if let Some(pad) = elem.static_pad("sink") {
             let metrics = metrics.clone();
             pad.add_probe(gst::PadProbeType::BUFFER, move |_pad, info| {
@@ -340,8 +1185,20 @@ fn run_pipeline_loop(pipeline: &gst::Pipeline) -> Result<()> {
                     err.error(),
                     err.debug()
                 );
+                if let Some(src) = msg.src()
+                    && handle_branch_error(pipeline, src)?
+                {
+                    continue;
+                }
                 return Err(anyhow::anyhow!("GStreamer error: {}", err.error()));
             }
+            MessageView::Warning(warning) => {
+                warn!(
+                    "Warning from {:?}: {}",
+                    msg.src().map(|s| s.path_string()),
+                    warning.error()
+                );
+            }
             // We can optionally listen for splitmuxsink messages here if needed (e.g. file-opened)
             // splitmuxsink emits element messages like 'splitmuxsink-fragment-opened'
             MessageView::Element(elem_msg) => {
@@ -364,25 +1221,64 @@ fn run_pipeline_loop(pipeline: &gst::Pipeline) -> Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::num::{NonZeroU32, NonZeroU64};
     use std::path::PathBuf;
 
-    #[test]
-    fn test_build_record_pipeline() {
-        let config = Config {
-            device: "/dev/video4".to_string(),
-            width: 640,
-            height: 480,
-            framerate: "30/1".to_string(),
-            bitrate: 1000,
-            key: "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff".to_string(),
+    /// Launches `description` with the keys of `config` and tells whether it
+    /// ran to the end without an error.
+    fn run_to_eos(description: &str, config: &Config) -> bool {
+        let pipeline = launch(description, config).unwrap();
+        // A source that cannot decrypt fails as it starts.
+        if pipeline.set_state(gst::State::Playing).is_err() {
+            pipeline.set_state(gst::State::Null).unwrap();
+            return false;
+        }
+        let msg = pipeline
+            .bus()
+            .unwrap()
+            .timed_pop_filtered(
+                gst::ClockTime::from_seconds(10),
+                &[gst::MessageType::Eos, gst::MessageType::Error],
+            )
+            .expect("pipeline stalled");
+        pipeline.set_state(gst::State::Null).unwrap();
+        matches!(msg.view(), gst::MessageView::Eos(..))
+    }
+
+    fn test_config() -> Config {
+        Config {
+            device: "/dev/video4".parse().unwrap(),
+            width: NonZeroU32::new(640).unwrap(),
+            height: NonZeroU32::new(480).unwrap(),
+            framerate: "30/1".parse().unwrap(),
+            bitrate: NonZeroU32::new(1000).unwrap(),
+            key: "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff".into(),
+            keyring: Vec::new(),
+            recipient: None,
+            identity: None,
             output_path: PathBuf::from("live.ts.enc"),
             cv_enabled: false,
             min_disk_space_mb: None,
             max_files: None,
             max_file_size_mb: None,
-        };
+            segment_duration_secs: None,
+            metrics_listen: None,
+            rtsp_username: None,
+            rtsp_password: None,
+            stream_dest: None,
+            stream_port: None,
+            stream_transport: None,
+            receive_listen: None,
+            rtsp_listen: None,
+            rtsp_port: None,
+        }
+    }
 
-        let expected = "v4l2src device=/dev/video4 ! video/x-raw,width=640,height=480,framerate=30/1 ! videoconvert ! video/x-raw,format=I420 ! queue ! x264enc tune=zerolatency speed-preset=ultrafast bitrate=1000 ! queue ! h264parse ! mpegtsmux ! queue ! rndbuffersize min=752 max=752 ! aesenc cipher=aes-256-cbc key=00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff serialize-iv=true per-buffer-padding=false ! filesink location=live.ts.enc";
+    #[test]
+    fn test_build_record_pipeline() {
+        let config = test_config();
+
+        let expected = "v4l2src device=\"/dev/video4\" ! video/x-raw,width=640,height=480,framerate=30/1 ! videoconvert ! video/x-raw,format=I420 ! queue ! x264enc tune=zerolatency speed-preset=ultrafast bitrate=1000 ! queue ! h264parse ! mpegtsmux ! queue ! aeadfilesink location=\"live.ts.enc\"";
 
         let actual = build_record_pipeline(&config).unwrap();
         assert_eq!(actual, expected);
@@ -391,20 +1287,11 @@ mod tests {
     #[test]
     fn test_build_record_pipeline_with_cv() {
         let config = Config {
-            device: "/dev/video4".to_string(),
-            width: 640,
-            height: 480,
-            framerate: "30/1".to_string(),
-            bitrate: 1000,
-            key: "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff".to_string(),
-            output_path: PathBuf::from("live.ts.enc"),
             cv_enabled: true,
-            min_disk_space_mb: None,
-            max_files: None,
-            max_file_size_mb: None,
+            ..test_config()
         };
 
-        let expected = "v4l2src device=/dev/video4 ! video/x-raw,width=640,height=480,framerate=30/1 ! videoconvert ! videoconvert ! facedetect ! videoconvert ! video/x-raw,format=I420 ! queue ! x264enc tune=zerolatency speed-preset=ultrafast bitrate=1000 ! queue ! h264parse ! mpegtsmux ! queue ! rndbuffersize min=752 max=752 ! aesenc cipher=aes-256-cbc key=00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff serialize-iv=true per-buffer-padding=false ! filesink location=live.ts.enc";
+        let expected = "v4l2src device=\"/dev/video4\" ! video/x-raw,width=640,height=480,framerate=30/1 ! videoconvert ! videoconvert ! facedetect ! videoconvert ! video/x-raw,format=I420 ! queue ! x264enc tune=zerolatency speed-preset=ultrafast bitrate=1000 ! queue ! h264parse ! mpegtsmux ! queue ! aeadfilesink location=\"live.ts.enc\"";
 
         gst::init().unwrap();
 
@@ -417,21 +1304,14 @@ mod tests {
     #[test]
     fn test_build_record_pipeline_with_rotation() {
         let config = Config {
-            device: "/dev/video4".to_string(),
-            width: 640,
-            height: 480,
-            framerate: "30/1".to_string(),
-            bitrate: 1000,
-            key: "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff".to_string(),
-            output_path: PathBuf::from("live.ts.enc"),
-            cv_enabled: false,
-            min_disk_space_mb: None,
             max_files: Some(10),
             max_file_size_mb: Some(100),
+            segment_duration_secs: None,
+            ..test_config()
         };
 
         // This expected string needs to match the splitmuxsink format we constructed above
-        let expected = "v4l2src device=/dev/video4 ! video/x-raw,width=640,height=480,framerate=30/1 ! videoconvert ! video/x-raw,format=I420 ! queue ! x264enc tune=zerolatency speed-preset=ultrafast bitrate=1000 ! queue ! h264parse ! splitmuxsink location=live.ts_%05d.enc muxer=mpegtsmux sink=\"rndbuffersize min=752 max=752 ! aesenc cipher=aes-256-cbc key=00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff serialize-iv=true per-buffer-padding=false ! filesink\" max-size-bytes=100000000 max-files=10 async-finalize=true";
+        let expected = "v4l2src device=\"/dev/video4\" ! video/x-raw,width=640,height=480,framerate=30/1 ! videoconvert ! video/x-raw,format=I420 ! queue ! x264enc tune=zerolatency speed-preset=ultrafast bitrate=1000 ! queue ! h264parse ! splitmuxsink location=\"live.ts_%05d.enc\" muxer=mpegtsmux sink=\"aeadfilesink\" max-size-bytes=100000000 max-files=10 async-finalize=true";
 
         let actual = build_record_pipeline(&config).unwrap();
         assert_eq!(actual, expected);
@@ -440,43 +1320,166 @@ mod tests {
     #[test]
     fn test_build_play_pipeline() {
         let config = Config {
-            device: "/dev/video0".to_string(),
-            width: 640,
-            height: 480,
-            framerate: "30/1".to_string(),
-            bitrate: 1000,
-            key: "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff".to_string(),
+            device: "/dev/video0".parse().unwrap(),
             output_path: PathBuf::from("unused.enc"),
-            cv_enabled: false,
-            min_disk_space_mb: None,
-            max_files: None,
-            max_file_size_mb: None,
+            ..test_config()
+        };
+
+        let input_file = [PathBuf::from("test_video.enc")];
+        let expected = "filesrc location=\"test_video.enc\" ! aesdec cipher=aes-256-cbc serialize-iv=true per-buffer-padding=false ! tsdemux ! h264parse ! decodebin ! autovideosink";
+
+        let actual = build_play_pipeline(&config, &input_file, Format::LegacyCbc).unwrap();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_build_play_pipeline_aead() {
+        let config = test_config();
+
+        let expected = "aeadfilesrc location=\"test_video.enc\" ! tsdemux ! h264parse ! decodebin ! autovideosink";
+
+        let actual =
+            build_play_pipeline(&config, &[PathBuf::from("test_video.enc")], Format::Aead).unwrap();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_build_play_pipeline_segments() {
+        let config = test_config();
+        let inputs = [
+            PathBuf::from("rec/cam0_20261018T120000Z.ts.enc"),
+            PathBuf::from("rec/cam0_20261018T120100Z.ts.enc"),
+        ];
+
+        let expected = r#"aeadfilesrc locations="<\"rec/cam0_20261018T120000Z.ts.enc\",\"rec/cam0_20261018T120100Z.ts.enc\">" ! tsdemux ! h264parse ! decodebin ! autovideosink"#;
+
+        let actual = build_play_pipeline(&config, &inputs, Format::Aead).unwrap();
+        assert_eq!(actual, expected);
+        assert!(build_play_pipeline(&config, &inputs, Format::LegacyCbc).is_err());
+    }
+
+    #[test]
+    fn test_play_pipeline_picks_keyring_key() {
+        use std::io::Write;
+
+        init().unwrap();
+        let old_key = "ffeeddccbbaa99887766554433221100";
+        let config = Config {
+            keyring: vec![old_key.into()],
+            ..test_config()
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let recording = dir.path().join("old.ts.enc");
+        let mut writer = container::Writer::new(
+            std::fs::File::create(&recording).unwrap(),
+            &container::parse_key(old_key).unwrap(),
+        )
+        .unwrap();
+        writer.write_all(&[0x47; 1880]).unwrap();
+        writer.finish().unwrap();
+
+        assert_eq!(
+            container::select_key(&recording, &config.keys()).unwrap(),
+            old_key
+        );
+
+        // The source of the play pipeline decrypts it with that key.
+        let play = build_play_pipeline(&config, &[recording], Format::Aead).unwrap();
+        let (source, _) = play.split_once(" ! tsdemux").unwrap();
+        let output = dir.path().join("old.ts");
+        assert!(run_to_eos(
+            &format!(
+                "{source} ! filesink location={}",
+                gst_quote(&output.to_string_lossy())
+            ),
+            &config
+        ));
+        assert_eq!(std::fs::read(&output).unwrap(), [0x47; 1880]);
+    }
+
+    #[test]
+    fn test_build_pipelines_with_recipient() {
+        let recipient = "9fe5b4c2a87a7d57a6a6e0f4c9c12a9dd2b2c2ba5d25e3bdbfb1c1c1dbb4b30a";
+        let identity = "a8abababababababababababababababababababababababababababababab6b";
+        let config = Config {
+            key: Default::default(),
+            recipient: Some(recipient.to_string()),
+            ..test_config()
+        };
+
+        let expected = format!(
+            "v4l2src device=\"/dev/video4\" ! video/x-raw,width=640,height=480,framerate=30/1 ! videoconvert ! video/x-raw,format=I420 ! queue ! x264enc tune=zerolatency speed-preset=ultrafast bitrate=1000 ! queue ! h264parse ! mpegtsmux ! queue ! aeadfilesink recipient={} location=\"live.ts.enc\"",
+            recipient
+        );
+        assert_eq!(build_record_pipeline(&config).unwrap(), expected);
+
+        // Without the master key there is nothing to stream with.
+        let streaming = Config {
+            stream_dest: Some("192.168.1.10".to_string()),
+            ..config
         };
+        assert!(build_record_pipeline(&streaming).is_err());
 
-        let input_file = "test_video.enc";
-        let expected = "filesrc location=test_video.enc ! aesdec cipher=aes-256-cbc key=00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff serialize-iv=true per-buffer-padding=false ! tsdemux ! h264parse ! decodebin ! autovideosink";
+        let reviewer = Config {
+            key: Default::default(),
+            identity: Some(identity.into()),
+            ..test_config()
+        };
+        let inputs = [PathBuf::from("live.ts.enc")];
+        let expected = "aeadfilesrc location=\"live.ts.enc\" ! tsdemux ! h264parse ! decodebin ! autovideosink";
+        assert_eq!(
+            build_play_pipeline(&reviewer, &inputs, Format::Aead).unwrap(),
+            expected
+        );
+        assert!(build_record_pipeline(&reviewer).is_err());
+    }
 
-        let actual = build_play_pipeline(&config, input_file);
+    #[test]
+    fn test_build_export_pipeline() {
+        let config = test_config();
+        let inputs = [PathBuf::from("live.ts.enc")];
+
+        let expected = "aeadfilesrc location=\"live.ts.enc\" ! tsdemux ! h264parse name=parse ! mp4mux faststart=true ! filesink location=\"clip.mp4\"";
+        let actual = build_export_pipeline(
+            &config,
+            &inputs,
+            Format::Aead,
+            Path::new("clip.mp4"),
+            ExportFormat::Mp4,
+        )
+        .unwrap();
+        assert_eq!(actual, expected);
+
+        let expected = "filesrc location=\"live.ts.enc\" ! aesdec cipher=aes-256-cbc serialize-iv=true per-buffer-padding=false ! tsdemux ! h264parse name=parse ! matroskamux ! filesink location=\"clip.mkv\"";
+        let actual = build_export_pipeline(
+            &config,
+            &inputs,
+            Format::LegacyCbc,
+            Path::new("clip.mkv"),
+            ExportFormat::Mkv,
+        )
+        .unwrap();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_build_verify_pipeline() {
+        let config = test_config();
+
+        let expected = "aeadfilesrc location=\"live.ts.enc\" ! tsdemux ! h264parse name=parse ! fakesink sync=false";
+        let actual =
+            build_verify_pipeline(&config, Path::new("live.ts.enc"), Format::Aead).unwrap();
         assert_eq!(actual, expected);
     }
 
     #[test]
     fn test_build_record_pipeline_auto() {
         let config = Config {
-            device: "auto".to_string(),
-            width: 640,
-            height: 480,
-            framerate: "30/1".to_string(),
-            bitrate: 1000,
-            key: "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff".to_string(),
-            output_path: PathBuf::from("live.ts.enc"),
-            cv_enabled: false,
-            min_disk_space_mb: None,
-            max_files: None,
-            max_file_size_mb: None,
+            device: "auto".parse().unwrap(),
+            ..test_config()
         };
 
-        let expected = "autovideosrc ! video/x-raw,width=640,height=480,framerate=30/1 ! videoconvert ! video/x-raw,format=I420 ! queue ! x264enc tune=zerolatency speed-preset=ultrafast bitrate=1000 ! queue ! h264parse ! mpegtsmux ! queue ! rndbuffersize min=752 max=752 ! aesenc cipher=aes-256-cbc key=00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff serialize-iv=true per-buffer-padding=false ! filesink location=live.ts.enc";
+        let expected = "autovideosrc ! video/x-raw,width=640,height=480,framerate=30/1 ! videoconvert ! video/x-raw,format=I420 ! queue ! x264enc tune=zerolatency speed-preset=ultrafast bitrate=1000 ! queue ! h264parse ! mpegtsmux ! queue ! aeadfilesink location=\"live.ts.enc\"";
 
         let actual = build_record_pipeline(&config).unwrap();
         assert_eq!(actual, expected);
@@ -485,51 +1488,315 @@ mod tests {
     #[test]
     fn test_build_stream_pipeline() {
         let config = Config {
-            device: "/dev/video4".to_string(),
-            width: 640,
-            height: 480,
-            framerate: "30/1".to_string(),
-            bitrate: 1000,
-            key: "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff".to_string(),
             output_path: PathBuf::from("unused.enc"),
-            cv_enabled: false,
-            min_disk_space_mb: None,
-            max_files: None,
-            max_file_size_mb: None,
+            ..test_config()
         };
 
         let dest = "127.0.0.1";
         let port = 8088;
-        let expected = "v4l2src device=/dev/video4 ! video/x-raw,width=640,height=480,framerate=30/1 ! videoconvert ! video/x-raw,format=I420 ! queue ! x264enc tune=zerolatency speed-preset=ultrafast bitrate=1000 ! rtph264pay config-interval=1 mtu=1400 ! queue ! aesenc cipher=aes-256-cbc key=00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff iv=00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff per-buffer-padding=true serialize-iv=true ! udpsink host=127.0.0.1 port=8088";
+        let expected = "v4l2src device=\"/dev/video4\" ! video/x-raw,width=640,height=480,framerate=30/1 ! videoconvert ! video/x-raw,format=I420 ! queue ! x264enc tune=zerolatency speed-preset=ultrafast bitrate=1000 ! rtph264pay config-interval=1 mtu=1400 ! queue ! aeadpktenc ! udpsink host=127.0.0.1 port=8088";
 
-        let actual = build_stream_pipeline(&config, dest, port).unwrap();
+        let actual = build_stream_pipeline(&config, dest, port, Transport::Aead).unwrap();
         assert_eq!(actual, expected);
     }
 
     #[test]
     fn test_build_receive_pipeline() {
+        let listen = "0.0.0.0";
+        let port = 8088;
+        let expected = "udpsrc address=0.0.0.0 port=8088 ! application/x-rtp,media=(string)video,clock-rate=(int)90000,encoding-name=(string)H264 ! aeadpktdec ! rtph264depay ! decodebin ! queue ! autovideosink sync=false";
+
+        let actual = build_receive_pipeline(listen, port, Transport::Aead).unwrap();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_build_record_pipeline_time_rotation() {
         let config = Config {
-            device: "auto".to_string(),
-            width: 640,
-            height: 480,
-            framerate: "30/1".to_string(),
-            bitrate: 1000,
-            key: "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff".to_string(),
-            output_path: PathBuf::from("unused.enc"),
-            cv_enabled: false,
-            min_disk_space_mb: None,
-            max_files: None,
-            max_file_size_mb: None,
+            output_path: PathBuf::from("cam0.ts.enc"),
+            segment_duration_secs: NonZeroU64::new(60),
+            max_files: Some(1440),
+            ..test_config()
         };
 
-        let listen = "0.0.0.0";
-        let port = 8088;
-        let expected = "udpsrc address=0.0.0.0 port=8088 ! application/x-rtp,media=(string)video,clock-rate=(int)90000,encoding-name=(string)H264 ! aesdec cipher=aes-256-cbc key=00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff iv=00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff per-buffer-padding=true serialize-iv=true ! rtph264depay ! decodebin ! queue ! autovideosink sync=false";
+        let expected = "v4l2src device=\"/dev/video4\" ! video/x-raw,width=640,height=480,framerate=30/1 ! videoconvert ! video/x-raw,format=I420 ! queue ! x264enc tune=zerolatency speed-preset=ultrafast bitrate=1000 ! queue ! h264parse ! splitmuxsink name=splitmux muxer=mpegtsmux sink=\"aeadfilesink\" max-size-time=60000000000 max-size-bytes=0 send-keyframe-requests=true async-finalize=true";
 
-        let actual = build_receive_pipeline(&config, listen, port);
+        let actual = build_record_pipeline(&config).unwrap();
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn test_build_record_and_stream_pipeline() {
+        let config = Config {
+            stream_dest: Some("192.168.1.10".to_string()),
+            ..test_config()
+        };
+
+        let expected = "v4l2src device=\"/dev/video4\" ! video/x-raw,width=640,height=480,framerate=30/1 ! videoconvert ! video/x-raw,format=I420 ! queue ! x264enc tune=zerolatency speed-preset=ultrafast bitrate=1000 ! tee name=t allow-not-linked=true \
+            t. ! ( name=record queue name=record_queue leaky=downstream max-size-buffers=0 max-size-bytes=64000000 max-size-time=2000000000 ! h264parse ! mpegtsmux ! queue ! aeadfilesink location=\"live.ts.enc\" ) \
+            t. ! ( name=stream queue leaky=downstream max-size-buffers=0 max-size-bytes=0 max-size-time=500000000 ! rtph264pay config-interval=1 mtu=1400 ! queue ! aeadpktenc ! udpsink host=192.168.1.10 port=8088 )";
+
+        let actual = build_record_pipeline(&config).unwrap();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_stalled_recording_does_not_stall_the_stream() {
+        gst::init().unwrap();
+        // The record branch takes a second per 1 MB buffer.
+        let pipeline = gst::parse::launch(&format!(
+            "fakesrc num-buffers=200 sizetype=fixed sizemax=1000000 ! tee name=t \
+             t. ! ( name={RECORD_BRANCH} {RECORD_BRANCH_QUEUE} ! identity sleep-time=1000000 ! fakesink name=record_sink ) \
+             t. ! ( name={STREAM_BRANCH} {STREAM_BRANCH_QUEUE} ! fakesink name=stream_sink )"
+        ))
+        .unwrap()
+        .downcast::<gst::Pipeline>()
+        .unwrap();
+
+        let mut names: Vec<_> = metered_sinks(&pipeline)
+            .unwrap()
+            .iter()
+            .map(|sink| sink.name().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["record_sink", "stream_sink"]);
+
+        let metrics = Arc::new(Metrics::new());
+        count_record_drops(&pipeline, metrics.clone());
+        let received = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = received.clone();
+        pipeline
+            .by_name("stream_sink")
+            .unwrap()
+            .static_pad("sink")
+            .unwrap()
+            .add_probe(gst::PadProbeType::BUFFER, move |_, _| {
+                counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                gst::PadProbeReturn::Ok
+            });
+
+        pipeline.set_state(gst::State::Playing).unwrap();
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        while received.load(std::sync::atomic::Ordering::SeqCst) < 200 {
+            assert!(
+                std::time::Instant::now() < deadline,
+                "the stream stalled behind the recording"
+            );
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        pipeline.set_state(gst::State::Null).unwrap();
+        assert!(
+            metrics
+                .record_drops_total
+                .load(std::sync::atomic::Ordering::Relaxed)
+                > 0
+        );
+    }
+
+    #[test]
+    fn test_failed_branch_does_not_stop_the_other() {
+        gst::init().unwrap();
+
+        for failing in [RECORD_BRANCH, STREAM_BRANCH] {
+            let branch = |name: &str| {
+                let fail = if name == failing {
+                    "identity error-after=10 ! "
+                } else {
+                    ""
+                };
+                format!("( name={name} queue ! {fail}fakesink name={name}_sink )")
+            };
+            let pipeline = gst::parse::launch(&format!(
+                "fakesrc num-buffers=200 ! tee name=t allow-not-linked=true t. ! {} t. ! {}",
+                branch(RECORD_BRANCH),
+                branch(STREAM_BRANCH)
+            ))
+            .unwrap()
+            .downcast::<gst::Pipeline>()
+            .unwrap();
+            isolate_branches(&pipeline).unwrap();
+
+            let other = if failing == RECORD_BRANCH {
+                STREAM_BRANCH
+            } else {
+                RECORD_BRANCH
+            };
+            let received = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+            let counter = received.clone();
+            pipeline
+                .by_name(&format!("{other}_sink"))
+                .unwrap()
+                .static_pad("sink")
+                .unwrap()
+                .add_probe(gst::PadProbeType::BUFFER, move |_, _| {
+                    counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    gst::PadProbeReturn::Ok
+                });
+
+            pipeline.set_state(gst::State::Playing).unwrap();
+            let bus = pipeline.bus().unwrap();
+            loop {
+                let msg = bus
+                    .timed_pop(gst::ClockTime::from_seconds(10))
+                    .unwrap_or_else(|| {
+                        panic!("pipeline stalled after the {failing} branch failed")
+                    });
+                match msg.view() {
+                    gst::MessageView::Eos(..) => break,
+                    gst::MessageView::Error(_) => {
+                        assert!(handle_branch_error(&pipeline, msg.src().unwrap()).unwrap());
+                    }
+                    _ => (),
+                }
+            }
+            pipeline.set_state(gst::State::Null).unwrap();
+
+            assert_eq!(received.load(std::sync::atomic::Ordering::SeqCst), 200);
+            let tee = pipeline.by_name("t").unwrap();
+            assert_eq!(tee.src_pads().len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_build_serve_pipeline() {
+        let config = Config {
+            device: "videotestsrc".parse().unwrap(),
+            ..test_config()
+        };
+
+        let expected = "( videotestsrc is-live=true ! video/x-raw,width=640,height=480,framerate=30/1 ! videoconvert ! video/x-raw,format=I420 ! queue ! x264enc tune=zerolatency speed-preset=ultrafast bitrate=1000 ! rtph264pay name=pay0 pt=96 config-interval=1 )";
+
+        let actual = build_serve_pipeline(&config).unwrap();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_build_srtp_pipelines() {
+        let config = test_config();
+
+        let stream = build_stream_pipeline(&config, "127.0.0.1", 8088, Transport::Srtp).unwrap();
+        assert!(stream.ends_with(
+            "rtph264pay config-interval=1 mtu=1400 pt=96 ! queue ! srtpenc rtp-cipher=aes-128-icm rtp-auth=hmac-sha1-80 rtcp-cipher=aes-128-icm rtcp-auth=hmac-sha1-80 ! udpsink host=127.0.0.1 port=8088"
+        ));
+
+        let receive = build_receive_pipeline("0.0.0.0", 8088, Transport::Srtp).unwrap();
+        let expected = "udpsrc address=0.0.0.0 port=8088 ! application/x-srtp,media=(string)video,clock-rate=(int)90000,encoding-name=(string)H264,payload=(int)96 ! srtpdec ! rtph264depay ! decodebin ! queue ! autovideosink sync=false";
+        assert_eq!(receive, expected);
+    }
+
+    #[test]
+    fn test_set_keys() {
+        init().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let recording = dir.path().join("in.enc");
+        let recording = gst_quote(&recording.to_string_lossy());
+        let output = dir.path().join("out.ts");
+        let sink = format!("filesink location={}", gst_quote(&output.to_string_lossy()));
+        let source = "fakesrc num-buffers=3 sizetype=fixed sizemax=1000 filltype=pattern ! application/octet-stream";
+        let plaintext: Vec<u8> = (0..3).flat_map(|_| (0..1000).map(|i| i as u8)).collect();
+        let config = test_config();
+
+        // Both packet elements get the master key.
+        assert!(run_to_eos(
+            &format!("{source} ! aeadpktenc ! aeadpktdec ! {sink}"),
+            &config
+        ));
+        assert_eq!(std::fs::read(&output).unwrap(), plaintext);
+
+        // A recording written under the master key reads back when that key
+        // is only in the keyring, and not at all without it.
+        assert!(run_to_eos(
+            &format!("{source} ! aeadfilesink location={recording}"),
+            &config
+        ));
+        let rotated = Config {
+            key: "ff".repeat(32).into(),
+            keyring: vec![config.key.clone()],
+            ..test_config()
+        };
+        std::fs::remove_file(&output).unwrap();
+        assert!(run_to_eos(
+            &format!("aeadfilesrc location={recording} ! {sink}"),
+            &rotated
+        ));
+        assert_eq!(std::fs::read(&output).unwrap(), plaintext);
+        let stranger = Config {
+            key: "ee".repeat(32).into(),
+            ..test_config()
+        };
+        assert!(!run_to_eos(
+            &format!("aeadfilesrc location={recording} ! {sink}"),
+            &stranger
+        ));
+
+        // The key properties cannot be read back.
+        let pipeline = launch(
+            &format!("aeadfilesrc location={recording} ! fakesink"),
+            &config,
+        )
+        .unwrap();
+        let src = pipeline
+            .iterate_elements()
+            .into_iter()
+            .flatten()
+            .find(|element| factory_name(element) == "aeadfilesrc")
+            .unwrap();
+        assert!(
+            !src.find_property("key")
+                .unwrap()
+                .flags()
+                .contains(gst::glib::ParamFlags::READABLE)
+        );
+    }
+
+    #[test]
+    fn test_paths_are_quoted() {
+        init().unwrap();
+        let path = r#"/tmp/my recordings/cam ! "0" \ x.mp4"#;
+        let description = format!("fakesrc ! filesink location={}", gst_quote(path));
+        let pipeline = gst::parse::launch(&description)
+            .unwrap()
+            .dynamic_cast::<gst::Pipeline>()
+            .unwrap();
+        let sink = find_element(&pipeline, "filesink").unwrap();
+        assert_eq!(
+            sink.property::<Option<String>>("location").as_deref(),
+            Some(path)
+        );
+
+        // Segment lists are quoted twice over.
+        let paths = vec![path.to_string(), "/tmp/b.enc".to_string()];
+        let description = format!("aeadfilesrc locations={} ! fakesink", gst_array(&paths));
+        let pipeline = gst::parse::launch(&description)
+            .unwrap()
+            .dynamic_cast::<gst::Pipeline>()
+            .unwrap();
+        let locations: Vec<String> = find_element(&pipeline, "aeadfilesrc")
+            .unwrap()
+            .property::<gst::Array>("locations")
+            .iter()
+            .map(|location| location.get::<String>().unwrap())
+            .collect();
+        assert_eq!(locations, paths);
+    }
+
+    #[test]
+    fn test_check_reports_every_problem() {
+        let config = Config {
+            device: "/nonexistent/video0".parse().unwrap(),
+            key: "".into(),
+            ..test_config()
+        };
+        let problems = check(&config);
+        assert_eq!(problems[0], "device: /nonexistent/video0 does not exist");
+        assert_eq!(
+            problems[1],
+            "record pipeline: Recording needs a key or a recipient"
+        );
+        // Without a key there is nothing to stream or receive.
+        assert!(!problems.iter().any(|p| p.starts_with("stream")));
+    }
+
     #[test]
     fn test_pipeline_parse() {
         gst::init().unwrap();
@@ -0,0 +1,863 @@
+//! PKCS#11 key unwrapping for the `pkcs11:` key source.
+//!
+//! The recording key is kept only in wrapped form. At startup it is decrypted
+//! inside a token (an HSM, a smart card, or SoftHSM2 for testing) with a key
+//! object that never leaves it, so no file on the recorder holds the master
+//! key. The vendor module is loaded at run time and driven through the few
+//! raw PKCS#11 calls needed; nothing links against it at build time.
+
+use crate::secrets::get_resolver;
+use anyhow::{Context, Result};
+use rand::RngCore;
+use std::ffi::c_void;
+use std::path::PathBuf;
+
+/// The subset of the PKCS#11 v2.40 C interface used here.
+#[allow(non_camel_case_types)]
+mod ffi {
+    use std::ffi::{c_uchar, c_ulong, c_void};
+
+    pub type CK_ULONG = c_ulong;
+    pub type CK_RV = CK_ULONG;
+    pub type CK_BBOOL = c_uchar;
+
+    pub const CK_TRUE: CK_BBOOL = 1;
+
+    pub const CKR_OK: CK_RV = 0;
+    pub const CKR_USER_ALREADY_LOGGED_IN: CK_RV = 0x100;
+    pub const CKR_CRYPTOKI_ALREADY_INITIALIZED: CK_RV = 0x191;
+
+    pub const CKF_RW_SESSION: CK_ULONG = 0x2;
+    pub const CKF_SERIAL_SESSION: CK_ULONG = 0x4;
+    pub const CKU_USER: CK_ULONG = 1;
+
+    pub const CKA_CLASS: CK_ULONG = 0x0;
+    pub const CKA_LABEL: CK_ULONG = 0x3;
+    pub const CKO_PUBLIC_KEY: CK_ULONG = 0x2;
+    pub const CKO_PRIVATE_KEY: CK_ULONG = 0x3;
+    pub const CKO_SECRET_KEY: CK_ULONG = 0x4;
+
+    pub const CKM_RSA_PKCS_OAEP: CK_ULONG = 0x9;
+    pub const CKM_SHA256: CK_ULONG = 0x250;
+    pub const CKM_AES_GCM: CK_ULONG = 0x1087;
+    pub const CKG_MGF1_SHA256: CK_ULONG = 0x2;
+    pub const CKZ_DATA_SPECIFIED: CK_ULONG = 0x1;
+
+    #[cfg(test)]
+    pub const CKA_TOKEN: CK_ULONG = 0x1;
+    #[cfg(test)]
+    pub const CKA_VALUE: CK_ULONG = 0x11;
+    #[cfg(test)]
+    pub const CKA_KEY_TYPE: CK_ULONG = 0x100;
+    #[cfg(test)]
+    pub const CKA_ENCRYPT: CK_ULONG = 0x104;
+    #[cfg(test)]
+    pub const CKA_DECRYPT: CK_ULONG = 0x105;
+    #[cfg(test)]
+    pub const CKK_AES: CK_ULONG = 0x1f;
+
+    #[repr(C)]
+    pub struct CK_VERSION {
+        pub major: c_uchar,
+        pub minor: c_uchar,
+    }
+
+    #[repr(C)]
+    pub struct CK_TOKEN_INFO {
+        pub label: [c_uchar; 32],
+        pub manufacturer_id: [c_uchar; 32],
+        pub model: [c_uchar; 16],
+        pub serial_number: [c_uchar; 16],
+        pub flags: CK_ULONG,
+        pub counters: [CK_ULONG; 10],
+        pub hardware_version: CK_VERSION,
+        pub firmware_version: CK_VERSION,
+        pub utc_time: [c_uchar; 16],
+    }
+
+    #[repr(C)]
+    pub struct CK_ATTRIBUTE {
+        pub kind: CK_ULONG,
+        pub value: *mut c_void,
+        pub len: CK_ULONG,
+    }
+
+    #[repr(C)]
+    pub struct CK_MECHANISM {
+        pub mechanism: CK_ULONG,
+        pub parameter: *mut c_void,
+        pub parameter_len: CK_ULONG,
+    }
+
+    #[repr(C)]
+    pub struct CK_GCM_PARAMS {
+        pub iv: *mut c_uchar,
+        pub iv_len: CK_ULONG,
+        pub iv_bits: CK_ULONG,
+        pub aad: *mut c_uchar,
+        pub aad_len: CK_ULONG,
+        pub tag_bits: CK_ULONG,
+    }
+
+    #[repr(C)]
+    pub struct CK_RSA_PKCS_OAEP_PARAMS {
+        pub hash_alg: CK_ULONG,
+        pub mgf: CK_ULONG,
+        pub source: CK_ULONG,
+        pub source_data: *mut c_void,
+        pub source_data_len: CK_ULONG,
+    }
+
+    type Unused = *const c_void;
+
+    /// `CK_FUNCTION_LIST` up to `C_Decrypt`; the entries are in the order the
+    /// standard fixes, and later ones are never read.
+    #[repr(C)]
+    pub struct CK_FUNCTION_LIST {
+        pub version: CK_VERSION,
+        pub initialize: Option<unsafe extern "C" fn(*mut c_void) -> CK_RV>,
+        pub finalize: Option<unsafe extern "C" fn(*mut c_void) -> CK_RV>,
+        _get_info: Unused,
+        _get_function_list: Unused,
+        pub get_slot_list:
+            Option<unsafe extern "C" fn(CK_BBOOL, *mut CK_ULONG, *mut CK_ULONG) -> CK_RV>,
+        _get_slot_info: Unused,
+        pub get_token_info: Option<unsafe extern "C" fn(CK_ULONG, *mut CK_TOKEN_INFO) -> CK_RV>,
+        _get_mechanism_list: Unused,
+        _get_mechanism_info: Unused,
+        _init_token: Unused,
+        _init_pin: Unused,
+        _set_pin: Unused,
+        pub open_session: Option<
+            unsafe extern "C" fn(CK_ULONG, CK_ULONG, *mut c_void, Unused, *mut CK_ULONG) -> CK_RV,
+        >,
+        pub close_session: Option<unsafe extern "C" fn(CK_ULONG) -> CK_RV>,
+        _close_all_sessions: Unused,
+        _get_session_info: Unused,
+        _get_operation_state: Unused,
+        _set_operation_state: Unused,
+        pub login:
+            Option<unsafe extern "C" fn(CK_ULONG, CK_ULONG, *const c_uchar, CK_ULONG) -> CK_RV>,
+        _logout: Unused,
+        #[cfg_attr(not(test), allow(dead_code))]
+        pub create_object: Option<
+            unsafe extern "C" fn(CK_ULONG, *const CK_ATTRIBUTE, CK_ULONG, *mut CK_ULONG) -> CK_RV,
+        >,
+        _copy_object: Unused,
+        #[cfg_attr(not(test), allow(dead_code))]
+        pub destroy_object: Option<unsafe extern "C" fn(CK_ULONG, CK_ULONG) -> CK_RV>,
+        _get_object_size: Unused,
+        _get_attribute_value: Unused,
+        _set_attribute_value: Unused,
+        pub find_objects_init:
+            Option<unsafe extern "C" fn(CK_ULONG, *const CK_ATTRIBUTE, CK_ULONG) -> CK_RV>,
+        pub find_objects:
+            Option<unsafe extern "C" fn(CK_ULONG, *mut CK_ULONG, CK_ULONG, *mut CK_ULONG) -> CK_RV>,
+        pub find_objects_final: Option<unsafe extern "C" fn(CK_ULONG) -> CK_RV>,
+        pub encrypt_init:
+            Option<unsafe extern "C" fn(CK_ULONG, *const CK_MECHANISM, CK_ULONG) -> CK_RV>,
+        pub encrypt: Option<
+            unsafe extern "C" fn(
+                CK_ULONG,
+                *const c_uchar,
+                CK_ULONG,
+                *mut c_uchar,
+                *mut CK_ULONG,
+            ) -> CK_RV,
+        >,
+        _encrypt_update: Unused,
+        _encrypt_final: Unused,
+        pub decrypt_init:
+            Option<unsafe extern "C" fn(CK_ULONG, *const CK_MECHANISM, CK_ULONG) -> CK_RV>,
+        pub decrypt: Option<
+            unsafe extern "C" fn(
+                CK_ULONG,
+                *const c_uchar,
+                CK_ULONG,
+                *mut c_uchar,
+                *mut CK_ULONG,
+            ) -> CK_RV,
+        >,
+    }
+
+    pub type GetFunctionList = unsafe extern "C" fn(*mut *const CK_FUNCTION_LIST) -> CK_RV;
+}
+
+use ffi::CK_ULONG;
+
+const GCM_IV_LEN: usize = 12;
+const GCM_TAG_BITS: CK_ULONG = 128;
+
+/// How the wrapped key was encrypted with the token key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mechanism {
+    /// AES-GCM under a secret key; the wrapped key is IV || ciphertext || tag.
+    AesGcm,
+    /// RSA-OAEP with SHA-256 under a key pair, so keys can be wrapped offline
+    /// with just the public key.
+    RsaOaep,
+}
+
+impl Mechanism {
+    /// The object classes used to decrypt and to encrypt.
+    fn classes(self) -> (CK_ULONG, CK_ULONG) {
+        match self {
+            Mechanism::AesGcm => (ffi::CKO_SECRET_KEY, ffi::CKO_SECRET_KEY),
+            Mechanism::RsaOaep => (ffi::CKO_PRIVATE_KEY, ffi::CKO_PUBLIC_KEY),
+        }
+    }
+}
+
+/// A `pkcs11:` key source:
+/// "module=PATH,token=LABEL|slot=ID,key=LABEL,pin=SOURCE,wrapped=SOURCE[,mechanism=aes-gcm|rsa-oaep]"
+///
+/// `pin` and `wrapped` are themselves key sources (e.g. `env:HSM_PIN`,
+/// `file:/etc/eightyeightyeight/key.wrapped`); `wrapped` resolves to hex.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Spec {
+    module: PathBuf,
+    token: Option<String>,
+    slot: Option<CK_ULONG>,
+    key: String,
+    pin: String,
+    wrapped: Option<String>,
+    mechanism: Mechanism,
+}
+
+impl Spec {
+    pub fn parse(spec: &str) -> Result<Self> {
+        let (mut module, mut token, mut slot, mut key, mut pin, mut wrapped) =
+            (None, None, None, None, None, None);
+        let mut mechanism = Mechanism::AesGcm;
+        for field in spec.split(',').filter(|f| !f.is_empty()) {
+            let (name, value) = field
+                .split_once('=')
+                .with_context(|| format!("Invalid PKCS#11 parameter: {}", field))?;
+            match name {
+                "module" => module = Some(PathBuf::from(value)),
+                "token" => token = Some(value.to_string()),
+                "slot" => {
+                    slot = Some(
+                        value
+                            .parse()
+                            .with_context(|| format!("Invalid PKCS#11 slot: {}", value))?,
+                    )
+                }
+                "key" => key = Some(value.to_string()),
+                "pin" => pin = Some(value.to_string()),
+                "wrapped" => wrapped = Some(value.to_string()),
+                "mechanism" => {
+                    mechanism = match value {
+                        "aes-gcm" => Mechanism::AesGcm,
+                        "rsa-oaep" => Mechanism::RsaOaep,
+                        _ => anyhow::bail!(
+                            "Unknown PKCS#11 mechanism {} (use aes-gcm or rsa-oaep)",
+                            value
+                        ),
+                    }
+                }
+                _ => anyhow::bail!("Unknown PKCS#11 parameter: {}", name),
+            }
+        }
+        if token.is_some() == slot.is_some() {
+            anyhow::bail!("PKCS#11 keys need exactly one of token=LABEL or slot=ID");
+        }
+        Ok(Self {
+            module: module.context("PKCS#11 keys need the module path (module=PATH)")?,
+            token,
+            slot,
+            key: key.context("PKCS#11 keys need the key object label (key=LABEL)")?,
+            pin: pin.context("PKCS#11 keys need a PIN source (pin=SOURCE)")?,
+            wrapped,
+            mechanism,
+        })
+    }
+}
+
+/// A loaded module, initialized for as long as this lives.
+struct Module {
+    functions: *const ffi::CK_FUNCTION_LIST,
+    /// Whether we initialized the library, and so must finalize it.
+    initialized: bool,
+    _library: libloading::Library,
+}
+
+impl Module {
+    fn load(path: &std::path::Path) -> Result<Self> {
+        // SAFETY: loading a PKCS#11 module runs its initializers; the path is
+        // the operator's choice, like any other shared library.
+        let library = unsafe { libloading::Library::new(path) }
+            .with_context(|| format!("Failed to load PKCS#11 module {}", path.display()))?;
+        let mut functions = std::ptr::null();
+        // SAFETY: C_GetFunctionList has this signature in every PKCS#11 module.
+        unsafe {
+            let get = library
+                .get::<ffi::GetFunctionList>(b"C_GetFunctionList\0")
+                .with_context(|| format!("{} is not a PKCS#11 module", path.display()))?;
+            check(get(&mut functions), "C_GetFunctionList")?;
+        }
+        anyhow::ensure!(!functions.is_null(), "C_GetFunctionList returned nothing");
+        let mut module = Self {
+            functions,
+            initialized: false,
+            _library: library,
+        };
+        let initialize = module.function(module.list().initialize, "C_Initialize")?;
+        // SAFETY: no arguments means the library may use OS locking or none.
+        match unsafe { initialize(std::ptr::null_mut()) } {
+            ffi::CKR_CRYPTOKI_ALREADY_INITIALIZED => {}
+            rv => {
+                check(rv, "C_Initialize")?;
+                module.initialized = true;
+            }
+        }
+        Ok(module)
+    }
+
+    fn list(&self) -> &ffi::CK_FUNCTION_LIST {
+        // SAFETY: checked non-null in `load`; the list lives as long as the library.
+        unsafe { &*self.functions }
+    }
+
+    fn function<F>(&self, function: Option<F>, name: &str) -> Result<F> {
+        function.with_context(|| format!("The PKCS#11 module does not implement {}", name))
+    }
+
+    /// The slot holding the token labelled `label`.
+    fn find_token(&self, label: &str) -> Result<CK_ULONG> {
+        let get_slot_list = self.function(self.list().get_slot_list, "C_GetSlotList")?;
+        let get_token_info = self.function(self.list().get_token_info, "C_GetTokenInfo")?;
+        let mut count = 0;
+        // SAFETY: a null list asks for the count only.
+        check(
+            unsafe { get_slot_list(ffi::CK_TRUE, std::ptr::null_mut(), &mut count) },
+            "C_GetSlotList",
+        )?;
+        let mut slots = vec![0; count as usize];
+        // SAFETY: `slots` holds `count` entries.
+        check(
+            unsafe { get_slot_list(ffi::CK_TRUE, slots.as_mut_ptr(), &mut count) },
+            "C_GetSlotList",
+        )?;
+        slots.truncate(count as usize);
+
+        let mut labels = Vec::new();
+        for slot in slots {
+            // SAFETY: CK_TOKEN_INFO is plain data, filled in by the call.
+            let mut info: ffi::CK_TOKEN_INFO = unsafe { std::mem::zeroed() };
+            check(unsafe { get_token_info(slot, &mut info) }, "C_GetTokenInfo")?;
+            let token = String::from_utf8_lossy(&info.label).trim_end().to_string();
+            if token == label {
+                return Ok(slot);
+            }
+            labels.push(token);
+        }
+        anyhow::bail!(
+            "No PKCS#11 token labelled {} (found: {})",
+            label,
+            if labels.is_empty() {
+                "none".to_string()
+            } else {
+                labels.join(", ")
+            }
+        )
+    }
+
+    /// A logged-in read-write session on the token `spec` names.
+    fn session(&self, spec: &Spec) -> Result<Session<'_>> {
+        let slot = match (&spec.token, spec.slot) {
+            (Some(label), _) => self.find_token(label)?,
+            (None, Some(slot)) => slot,
+            (None, None) => unreachable!("checked by Spec::parse"),
+        };
+        let open_session = self.function(self.list().open_session, "C_OpenSession")?;
+        let mut handle = 0;
+        // SAFETY: no application data and no notification callback.
+        check(
+            unsafe {
+                open_session(
+                    slot,
+                    ffi::CKF_SERIAL_SESSION | ffi::CKF_RW_SESSION,
+                    std::ptr::null_mut(),
+                    std::ptr::null(),
+                    &mut handle,
+                )
+            },
+            "C_OpenSession",
+        )?;
+        let session = Session {
+            module: self,
+            handle,
+        };
+
+        let pin = get_resolver(&spec.pin)
+            .resolve()
+            .context("Failed to resolve the PKCS#11 PIN")?;
+        let login = self.function(self.list().login, "C_Login")?;
+        // SAFETY: the PIN is passed by pointer and length.
+        match unsafe {
+            login(
+                handle,
+                ffi::CKU_USER,
+                pin.expose().as_ptr(),
+                pin.expose().len() as CK_ULONG,
+            )
+        } {
+            ffi::CKR_USER_ALREADY_LOGGED_IN => {}
+            rv => check(rv, "C_Login")?,
+        }
+        Ok(session)
+    }
+}
+
+impl Drop for Module {
+    fn drop(&mut self) {
+        if self.initialized
+            && let Some(finalize) = self.list().finalize
+        {
+            // SAFETY: all sessions borrowed from this module are closed by now.
+            unsafe { finalize(std::ptr::null_mut()) };
+        }
+    }
+}
+
+struct Session<'a> {
+    module: &'a Module,
+    handle: CK_ULONG,
+}
+
+impl Session<'_> {
+    /// The one object of `class` labelled `label`.
+    fn find_key(&self, label: &str, class: CK_ULONG) -> Result<CK_ULONG> {
+        let list = self.module.list();
+        let init = self
+            .module
+            .function(list.find_objects_init, "C_FindObjectsInit")?;
+        let find = self.module.function(list.find_objects, "C_FindObjects")?;
+        let finish = self
+            .module
+            .function(list.find_objects_final, "C_FindObjectsFinal")?;
+
+        let mut class = class;
+        let template = [
+            attribute(ffi::CKA_CLASS, &mut class),
+            ffi::CK_ATTRIBUTE {
+                kind: ffi::CKA_LABEL,
+                value: label.as_ptr() as *mut c_void,
+                len: label.len() as CK_ULONG,
+            },
+        ];
+        let mut objects = [0; 2];
+        let mut count = 0;
+        // SAFETY: the template outlives the search, which is always finished.
+        unsafe {
+            check(
+                init(self.handle, template.as_ptr(), template.len() as CK_ULONG),
+                "C_FindObjectsInit",
+            )?;
+            let rv = find(
+                self.handle,
+                objects.as_mut_ptr(),
+                objects.len() as CK_ULONG,
+                &mut count,
+            );
+            finish(self.handle);
+            check(rv, "C_FindObjects")?;
+        }
+        match count {
+            0 => anyhow::bail!("No PKCS#11 key labelled {} on the token", label),
+            1 => Ok(objects[0]),
+            _ => anyhow::bail!("More than one PKCS#11 key is labelled {}", label),
+        }
+    }
+
+    fn decrypt(&self, mechanism: Mechanism, key: CK_ULONG, data: &[u8]) -> Result<Vec<u8>> {
+        let list = self.module.list();
+        let init = self.module.function(list.decrypt_init, "C_DecryptInit")?;
+        let decrypt = self.module.function(list.decrypt, "C_Decrypt")?;
+        let (mut iv, ciphertext) = match mechanism {
+            Mechanism::AesGcm => {
+                anyhow::ensure!(data.len() > GCM_IV_LEN, "The wrapped key is too short");
+                (data[..GCM_IV_LEN].to_vec(), &data[GCM_IV_LEN..])
+            }
+            Mechanism::RsaOaep => (Vec::new(), data),
+        };
+        let mut params = Params::new(mechanism, &mut iv);
+        // The plaintext is never longer than the ciphertext for either mechanism.
+        let mut plaintext = vec![0u8; ciphertext.len()];
+        let mut len = plaintext.len() as CK_ULONG;
+        // SAFETY: the mechanism parameters and buffers outlive both calls.
+        unsafe {
+            check(init(self.handle, &params.mechanism(), key), "C_DecryptInit")?;
+            check(
+                decrypt(
+                    self.handle,
+                    ciphertext.as_ptr(),
+                    ciphertext.len() as CK_ULONG,
+                    plaintext.as_mut_ptr(),
+                    &mut len,
+                ),
+                "C_Decrypt",
+            )?;
+        }
+        plaintext.truncate(len as usize);
+        Ok(plaintext)
+    }
+
+    fn encrypt(&self, mechanism: Mechanism, key: CK_ULONG, data: &[u8]) -> Result<Vec<u8>> {
+        let list = self.module.list();
+        let init = self.module.function(list.encrypt_init, "C_EncryptInit")?;
+        let encrypt = self.module.function(list.encrypt, "C_Encrypt")?;
+        let mut iv = match mechanism {
+            Mechanism::AesGcm => {
+                let mut iv = vec![0u8; GCM_IV_LEN];
+                rand::rng().fill_bytes(&mut iv);
+                iv
+            }
+            Mechanism::RsaOaep => Vec::new(),
+        };
+        let mut params = Params::new(mechanism, &mut iv);
+        let mut len = 0;
+        // SAFETY: the mechanism parameters and buffers outlive the calls; a
+        // null output asks for the length without ending the operation.
+        let mut ciphertext = unsafe {
+            check(init(self.handle, &params.mechanism(), key), "C_EncryptInit")?;
+            check(
+                encrypt(
+                    self.handle,
+                    data.as_ptr(),
+                    data.len() as CK_ULONG,
+                    std::ptr::null_mut(),
+                    &mut len,
+                ),
+                "C_Encrypt",
+            )?;
+            let mut ciphertext = vec![0u8; len as usize];
+            check(
+                encrypt(
+                    self.handle,
+                    data.as_ptr(),
+                    data.len() as CK_ULONG,
+                    ciphertext.as_mut_ptr(),
+                    &mut len,
+                ),
+                "C_Encrypt",
+            )?;
+            ciphertext
+        };
+        ciphertext.truncate(len as usize);
+        iv.extend_from_slice(&ciphertext);
+        Ok(iv)
+    }
+}
+
+impl Drop for Session<'_> {
+    fn drop(&mut self) {
+        // Closing the last session also logs out.
+        if let Some(close_session) = self.module.list().close_session {
+            // SAFETY: the handle came from C_OpenSession and is closed once.
+            unsafe { close_session(self.handle) };
+        }
+    }
+}
+
+/// Mechanism parameters, kept alive for the duration of an operation.
+enum Params {
+    Gcm(ffi::CK_GCM_PARAMS),
+    Oaep(ffi::CK_RSA_PKCS_OAEP_PARAMS),
+}
+
+impl Params {
+    fn new(mechanism: Mechanism, iv: &mut [u8]) -> Self {
+        match mechanism {
+            Mechanism::AesGcm => Params::Gcm(ffi::CK_GCM_PARAMS {
+                iv: iv.as_mut_ptr(),
+                iv_len: iv.len() as CK_ULONG,
+                iv_bits: (iv.len() * 8) as CK_ULONG,
+                aad: std::ptr::null_mut(),
+                aad_len: 0,
+                tag_bits: GCM_TAG_BITS,
+            }),
+            Mechanism::RsaOaep => Params::Oaep(ffi::CK_RSA_PKCS_OAEP_PARAMS {
+                hash_alg: ffi::CKM_SHA256,
+                mgf: ffi::CKG_MGF1_SHA256,
+                source: ffi::CKZ_DATA_SPECIFIED,
+                source_data: std::ptr::null_mut(),
+                source_data_len: 0,
+            }),
+        }
+    }
+
+    fn mechanism(&mut self) -> ffi::CK_MECHANISM {
+        let (mechanism, parameter) = match self {
+            Params::Gcm(params) => (ffi::CKM_AES_GCM, attribute(0, params)),
+            Params::Oaep(params) => (ffi::CKM_RSA_PKCS_OAEP, attribute(0, params)),
+        };
+        ffi::CK_MECHANISM {
+            mechanism,
+            parameter: parameter.value,
+            parameter_len: parameter.len,
+        }
+    }
+}
+
+fn attribute<T>(kind: CK_ULONG, value: &mut T) -> ffi::CK_ATTRIBUTE {
+    ffi::CK_ATTRIBUTE {
+        kind,
+        value: value as *mut T as *mut c_void,
+        len: std::mem::size_of::<T>() as CK_ULONG,
+    }
+}
+
+fn check(rv: ffi::CK_RV, call: &str) -> Result<()> {
+    if rv == ffi::CKR_OK {
+        return Ok(());
+    }
+    let (name, hint) = match rv {
+        0x05 => ("CKR_GENERAL_ERROR", ""),
+        0x30 => ("CKR_DEVICE_ERROR", ""),
+        0x40 | 0x41 => (
+            "CKR_ENCRYPTED_DATA_INVALID",
+            "; the wrapped key does not match this token key or mechanism",
+        ),
+        0x63 => (
+            "CKR_KEY_TYPE_INCONSISTENT",
+            "; aes-gcm needs an AES key and rsa-oaep an RSA key pair",
+        ),
+        0x68 => (
+            "CKR_KEY_FUNCTION_NOT_PERMITTED",
+            "; the key object must allow CKA_DECRYPT (or CKA_ENCRYPT to wrap)",
+        ),
+        0x70 => (
+            "CKR_MECHANISM_INVALID",
+            "; the token does not support this mechanism",
+        ),
+        0xa0 => ("CKR_PIN_INCORRECT", "; check the PIN"),
+        0xa4 => ("CKR_PIN_LOCKED", "; the token must be unlocked by its SO"),
+        0xe0 => ("CKR_TOKEN_NOT_PRESENT", ""),
+        0x3 => ("CKR_SLOT_ID_INVALID", ""),
+        _ => ("an error", ""),
+    };
+    anyhow::bail!("{} failed with {} (0x{:x}){}", call, name, rv, hint)
+}
+
+/// Unwraps the key a `pkcs11:` source describes and returns it as hex.
+pub fn unwrap(spec: &Spec) -> Result<String> {
+    let wrapped = spec
+        .wrapped
+        .as_deref()
+        .context("PKCS#11 keys need the wrapped key (wrapped=SOURCE)")?;
+    let wrapped = get_resolver(wrapped)
+        .resolve()
+        .context("Failed to resolve the wrapped key")?;
+    let wrapped = hex::decode(wrapped.expose().trim()).context("The wrapped key is not hex")?;
+
+    let module = Module::load(&spec.module)?;
+    let session = module.session(spec)?;
+    let key = session.find_key(&spec.key, spec.mechanism.classes().0)?;
+    let unwrapped = session.decrypt(spec.mechanism, key, &wrapped)?;
+    anyhow::ensure!(
+        matches!(unwrapped.len(), 16 | 32),
+        "The unwrapped key is {} bytes, not an AES-128 or AES-256 key",
+        unwrapped.len()
+    );
+    Ok(hex::encode(unwrapped))
+}
+
+/// Wraps `key` (hex) with the token key `spec` names and returns the
+/// wrapped key as hex, for the `wrapped` parameter.
+pub fn wrap(spec: &Spec, key: &str) -> Result<String> {
+    let key = hex::decode(key.trim()).context("The key is not hex")?;
+    let module = Module::load(&spec.module)?;
+    let session = module.session(spec)?;
+    let token_key = session.find_key(&spec.key, spec.mechanism.classes().1)?;
+    Ok(hex::encode(session.encrypt(
+        spec.mechanism,
+        token_key,
+        &key,
+    )?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aes_gcm::aead::{Aead, KeyInit};
+
+    const KEY: &str = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+
+    impl Session<'_> {
+        /// Imports an AES key as a token object, for tests.
+        fn create_aes_key(&self, label: &str, value: &[u8]) -> Result<CK_ULONG> {
+            let create = self
+                .module
+                .function(self.module.list().create_object, "C_CreateObject")?;
+            let (mut class, mut key_type, mut yes) =
+                (ffi::CKO_SECRET_KEY, ffi::CKK_AES, ffi::CK_TRUE);
+            let template = [
+                attribute(ffi::CKA_CLASS, &mut class),
+                attribute(ffi::CKA_KEY_TYPE, &mut key_type),
+                attribute(ffi::CKA_TOKEN, &mut yes),
+                attribute(ffi::CKA_ENCRYPT, &mut yes),
+                attribute(ffi::CKA_DECRYPT, &mut yes),
+                ffi::CK_ATTRIBUTE {
+                    kind: ffi::CKA_LABEL,
+                    value: label.as_ptr() as *mut c_void,
+                    len: label.len() as CK_ULONG,
+                },
+                ffi::CK_ATTRIBUTE {
+                    kind: ffi::CKA_VALUE,
+                    value: value.as_ptr() as *mut c_void,
+                    len: value.len() as CK_ULONG,
+                },
+            ];
+            let mut object = 0;
+            // SAFETY: the template outlives the call.
+            check(
+                unsafe {
+                    create(
+                        self.handle,
+                        template.as_ptr(),
+                        template.len() as CK_ULONG,
+                        &mut object,
+                    )
+                },
+                "C_CreateObject",
+            )?;
+            Ok(object)
+        }
+    }
+
+    #[test]
+    fn test_parse_spec() {
+        let spec = Spec::parse(
+            "module=/usr/lib/softhsm/libsofthsm2.so,token=cams,key=recording-kek,pin=env:HSM_PIN,wrapped=file:/etc/key.wrapped",
+        )
+        .unwrap();
+        assert_eq!(
+            spec.module,
+            PathBuf::from("/usr/lib/softhsm/libsofthsm2.so")
+        );
+        assert_eq!(spec.token.as_deref(), Some("cams"));
+        assert_eq!(spec.slot, None);
+        assert_eq!(spec.key, "recording-kek");
+        assert_eq!(spec.pin, "env:HSM_PIN");
+        assert_eq!(spec.wrapped.as_deref(), Some("file:/etc/key.wrapped"));
+        assert_eq!(spec.mechanism, Mechanism::AesGcm);
+
+        let spec = Spec::parse("module=m.so,slot=3,key=k,pin=1234,mechanism=rsa-oaep").unwrap();
+        assert_eq!(spec.slot, Some(3));
+        assert_eq!(spec.wrapped, None);
+        assert_eq!(spec.mechanism, Mechanism::RsaOaep);
+
+        for bad in [
+            "token=t,key=k,pin=1",
+            "module=m.so,key=k,pin=1",
+            "module=m.so,token=t,slot=0,key=k,pin=1",
+            "module=m.so,token=t,pin=1",
+            "module=m.so,token=t,key=k",
+            "module=m.so,slot=x,key=k,pin=1",
+            "module=m.so,token=t,key=k,pin=1,mechanism=des",
+            "module=m.so,token=t,key=k,pin=1,colour=red",
+        ] {
+            assert!(Spec::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn test_ffi_layout() {
+        use std::mem::{offset_of, size_of};
+        // C_Decrypt is entry 34 after the two-byte version (padded to 8).
+        assert_eq!(offset_of!(ffi::CK_FUNCTION_LIST, decrypt), 8 + 34 * 8);
+        assert_eq!(offset_of!(ffi::CK_FUNCTION_LIST, login), 8 + 18 * 8);
+        assert_eq!(size_of::<ffi::CK_TOKEN_INFO>(), 208);
+        assert_eq!(size_of::<ffi::CK_GCM_PARAMS>(), 48);
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = check(0xa0, "C_Login").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "C_Login failed with CKR_PIN_INCORRECT (0xa0); check the PIN"
+        );
+        let err = check(0x63, "C_DecryptInit").unwrap_err();
+        assert!(err.to_string().contains("CKR_KEY_TYPE_INCONSISTENT (0x63)"));
+        let err = check(0x68, "C_DecryptInit").unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("CKR_KEY_FUNCTION_NOT_PERMITTED (0x68)")
+        );
+
+        let spec =
+            Spec::parse("module=/nonexistent/libpkcs11.so,slot=0,key=k,pin=1,wrapped=00").unwrap();
+        let err = unwrap(&spec).unwrap_err();
+        assert!(
+            err.to_string().starts_with("Failed to load PKCS#11 module"),
+            "{}",
+            err
+        );
+    }
+
+    /// Runs against SoftHSM2 with `cargo test -- --ignored` when
+    /// `SOFTHSM2_MODULE` points at `libsofthsm2.so` and `softhsm2-util` is
+    /// installed.
+    #[test]
+    #[ignore = "needs SoftHSM2 (set SOFTHSM2_MODULE)"]
+    fn test_softhsm_roundtrip() {
+        let module = std::env::var("SOFTHSM2_MODULE").expect("SOFTHSM2_MODULE is not set");
+        let dir = tempfile::tempdir().unwrap();
+        let conf = dir.path().join("softhsm2.conf");
+        std::fs::write(
+            &conf,
+            format!("directories.tokendir = {}\n", dir.path().display()),
+        )
+        .unwrap();
+        let status = std::process::Command::new("softhsm2-util")
+            .args(["--init-token", "--free", "--label", "eightyeightyeight"])
+            .args(["--pin", "1234", "--so-pin", "5678"])
+            .env("SOFTHSM2_CONF", &conf)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        // SAFETY: no other test reads SOFTHSM2_CONF.
+        unsafe { std::env::set_var("SOFTHSM2_CONF", &conf) };
+
+        let kek = [7u8; 32];
+        let source = format!("module={},token=eightyeightyeight,key=kek,pin=1234", module);
+        let spec = Spec::parse(&source).unwrap();
+        {
+            let module = Module::load(&spec.module).unwrap();
+            module
+                .session(&spec)
+                .unwrap()
+                .create_aes_key("kek", &kek)
+                .unwrap();
+        }
+
+        let wrapped = wrap(&spec, KEY).unwrap();
+        let bytes = hex::decode(&wrapped).unwrap();
+        let (nonce, ciphertext) = bytes.split_at(GCM_IV_LEN);
+        let plain = aes_gcm::Aes256Gcm::new_from_slice(&kek)
+            .unwrap()
+            .decrypt(nonce.into(), ciphertext)
+            .unwrap();
+        assert_eq!(hex::encode(plain), KEY);
+
+        let spec = Spec::parse(&format!("{},wrapped={}", source, wrapped)).unwrap();
+        assert_eq!(unwrap(&spec).unwrap(), KEY);
+
+        let wrong_pin = Spec::parse(&format!(
+            "module={},token=eightyeightyeight,key=kek,pin=0000,wrapped={}",
+            spec.module.display(),
+            wrapped
+        ))
+        .unwrap();
+        assert!(
+            unwrap(&wrong_pin)
+                .unwrap_err()
+                .to_string()
+                .contains("CKR_PIN_INCORRECT")
+        );
+    }
+}
@@ -0,0 +1,307 @@
+//! Resolves the `--input` of `play` and `export` to recordings, in order.
+//!
+//! The input is a single file, a directory of rotated segments or a glob.
+//! Segments are ordered by start time, taken from the manifest when one
+//! authenticates, else from the timestamp in a time-rotated name, else from
+//! the file's modification time (index names wrap once `max_files` is
+//! reached, so their order says nothing). `--from`/`--to` keep only the
+//! segments overlapping that UTC range.
+
+use crate::container::{self, Format};
+use crate::manifest::Manifest;
+use crate::segments;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{debug, warn};
+
+const MANIFEST_SUFFIX: &str = ".manifest.json";
+
+pub struct Playlist {
+    pub files: Vec<PathBuf>,
+    /// Position of `--from` within the first file.
+    pub start_offset: Option<Duration>,
+    /// Position of `--to` from the start of the first file, assuming the
+    /// segments follow each other without gaps.
+    pub end_offset: Option<Duration>,
+}
+
+struct Entry {
+    path: PathBuf,
+    start: DateTime<Utc>,
+    end: Option<DateTime<Utc>>,
+}
+
+/// `keys` are tried in order on manifests, as any of them may have signed
+/// older ones.
+pub fn resolve<K: AsRef<str>>(
+    input: &str,
+    keys: &[K],
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+) -> Result<Playlist> {
+    let input_path = Path::new(input);
+    if !input_path.is_dir() && !is_glob(input) && from.is_none() && to.is_none() {
+        // A single file plays as before, legacy recordings included.
+        return Ok(Playlist {
+            files: vec![input_path.to_path_buf()],
+            start_offset: None,
+            end_offset: None,
+        });
+    }
+
+    let mut recordings = Vec::new();
+    for path in expand(input)? {
+        match container::detect_format(&path)? {
+            Format::Aead => recordings.push(path),
+            Format::LegacyCbc => warn!(
+                "Skipping {}: legacy recordings can only be played one file at a time",
+                path.display()
+            ),
+        }
+    }
+
+    let spans = load_manifests(&recordings, keys);
+    let mut entries = Vec::with_capacity(recordings.len());
+    for path in recordings {
+        let (start, end) = match spans.get(&path) {
+            Some(&(start, end)) => (start, Some(end)),
+            None => {
+                let start = match segments::parse_start_time(&path) {
+                    Some(start) => start,
+                    None => std::fs::metadata(&path)?.modified()?.into(),
+                };
+                (start, None)
+            }
+        };
+        entries.push(Entry { path, start, end });
+    }
+    entries.sort_by(|a, b| (a.start, &a.path).cmp(&(b.start, &b.path)));
+
+    // Without a manifest a segment is taken to last until the next one.
+    for i in 1..entries.len() {
+        if entries[i - 1].end.is_none() {
+            entries[i - 1].end = Some(entries[i].start);
+        }
+    }
+
+    entries.retain(|entry| {
+        to.is_none_or(|to| entry.start < to)
+            && from.is_none_or(|from| entry.end.is_none_or(|end| end > from))
+    });
+    let Some(first) = entries.first() else {
+        anyhow::bail!("No recordings found for {}", input);
+    };
+
+    let start_offset = from
+        .and_then(|from| (from - first.start).to_std().ok())
+        .filter(|offset| !offset.is_zero());
+    let end_offset = to.and_then(|to| (to - first.start).to_std().ok());
+    Ok(Playlist {
+        start_offset,
+        end_offset,
+        files: entries.into_iter().map(|entry| entry.path).collect(),
+    })
+}
+
+fn is_glob(input: &str) -> bool {
+    input.contains(['*', '?', '['])
+}
+
+/// Files named by `input`: the file itself, the files in a directory or the
+/// matches of a glob, sorted by path and without manifests.
+pub fn expand(input: &str) -> Result<Vec<PathBuf>> {
+    let input_path = Path::new(input);
+    let mut files = if input_path.is_dir() {
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(input_path)
+            .with_context(|| format!("Failed to read directory: {}", input))?
+        {
+            files.push(entry?.path());
+        }
+        files
+    } else if is_glob(input) {
+        glob::glob(input)
+            .context("Invalid glob pattern")?
+            .filter_map(|path| path.ok())
+            .collect()
+    } else {
+        return Ok(vec![input_path.to_path_buf()]);
+    };
+    files.retain(|path| path.is_file() && !is_sidecar(path));
+    files.sort();
+    Ok(files)
+}
+
+/// Manifests, and temporary files left behind by an interrupted `rekey` or
+/// manifest update.
+fn is_sidecar(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy())
+        .unwrap_or_default();
+    name.contains(MANIFEST_SUFFIX) || name.ends_with(".tmp")
+}
+
+/// Wall-clock span of every segment listed in an authentic manifest next to
+/// the recordings.
+fn load_manifests<K: AsRef<str>>(
+    recordings: &[PathBuf],
+    keys: &[K],
+) -> HashMap<PathBuf, (DateTime<Utc>, DateTime<Utc>)> {
+    let dirs: BTreeSet<&Path> = recordings.iter().filter_map(|p| p.parent()).collect();
+    let mut spans = HashMap::new();
+    for dir in dirs {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            continue;
+        };
+        for path in entries.filter_map(|e| e.ok().map(|e| e.path())) {
+            if !path.to_string_lossy().ends_with(MANIFEST_SUFFIX) {
+                continue;
+            }
+            let mut manifest = Manifest::load(&path, keys[0].as_ref());
+            for key in &keys[1..] {
+                if manifest.is_err() {
+                    manifest = Manifest::load(&path, key.as_ref());
+                }
+            }
+            match manifest {
+                Ok(manifest) => {
+                    debug!("Using manifest {}", path.display());
+                    for segment in manifest.segments {
+                        spans.insert(
+                            dir.join(&segment.path),
+                            (segment.start_time, segment.end_time),
+                        );
+                    }
+                }
+                Err(e) => warn!("Ignoring manifest {}: {}", path.display(), e),
+            }
+        }
+    }
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::Segment;
+    use chrono::TimeZone;
+    use std::io::Write;
+
+    const KEY: &str = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+
+    fn write_segment(path: &Path) {
+        let key = container::parse_key(KEY).unwrap();
+        let mut writer =
+            container::Writer::new(std::fs::File::create(path).unwrap(), &key).unwrap();
+        writer.write_all(&[0x47u8; 188]).unwrap();
+        writer.finish().unwrap();
+    }
+
+    fn at(minute: u32, second: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 10, 18, 12, minute, second)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_resolve_single_file() {
+        let playlist = resolve("legacy.ts.enc", &[KEY.to_string()], None, None).unwrap();
+        assert_eq!(playlist.files, [PathBuf::from("legacy.ts.enc")]);
+        assert_eq!(playlist.start_offset, None);
+    }
+
+    #[test]
+    fn test_resolve_directory_in_time_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let namer = segments::SegmentNamer::new(&dir.path().join("cam0.ts.enc"), None);
+        let paths: Vec<PathBuf> = [2, 0, 1]
+            .into_iter()
+            .map(|minute| namer.path_for(at(minute, 0)))
+            .collect();
+        for path in &paths {
+            write_segment(path);
+        }
+        std::fs::write(dir.path().join("notes.txt"), b"not a recording").unwrap();
+        Manifest::default()
+            .save(&dir.path().join("cam0.manifest.json"), KEY)
+            .unwrap();
+
+        let playlist =
+            resolve(dir.path().to_str().unwrap(), &[KEY.to_string()], None, None).unwrap();
+        assert_eq!(
+            playlist.files,
+            [paths[1].clone(), paths[2].clone(), paths[0].clone()]
+        );
+    }
+
+    #[test]
+    fn test_resolve_glob_with_time_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let namer = segments::SegmentNamer::new(&dir.path().join("cam0.ts.enc"), None);
+        let paths: Vec<PathBuf> = (0..4).map(|minute| namer.path_for(at(minute, 0))).collect();
+        for path in &paths {
+            write_segment(path);
+        }
+
+        let pattern = format!("{}/cam0_*.ts.enc", dir.path().display());
+        let playlist = resolve(
+            &pattern,
+            &[KEY.to_string()],
+            Some(at(1, 30)),
+            Some(at(2, 45)),
+        )
+        .unwrap();
+        assert_eq!(playlist.files, paths[1..3]);
+        assert_eq!(playlist.start_offset, Some(Duration::from_secs(30)));
+        assert_eq!(playlist.end_offset, Some(Duration::from_secs(105)));
+
+        let playlist = resolve(&pattern, &[KEY.to_string()], Some(at(3, 0)), None).unwrap();
+        assert_eq!(playlist.files, paths[3..]);
+        assert_eq!(playlist.start_offset, None);
+
+        assert!(resolve(&pattern, &[KEY.to_string()], None, Some(at(0, 0))).is_err());
+    }
+
+    #[test]
+    fn test_resolve_uses_manifest_for_index_names() {
+        let dir = tempfile::tempdir().unwrap();
+        // After wrapping around, 00000 is the newest segment.
+        let newest = dir.path().join("cam0_00000.ts.enc");
+        let oldest = dir.path().join("cam0_00001.ts.enc");
+        write_segment(&oldest);
+        write_segment(&newest);
+
+        let segment = |path: &Path, minute: u32| Segment {
+            path: path.file_name().unwrap().to_string_lossy().to_string(),
+            start_pts: 0,
+            end_pts: 0,
+            start_time: at(minute, 0),
+            end_time: at(minute + 1, 0),
+            bytes: 0,
+            sha256: String::new(),
+        };
+        let mut manifest = Manifest::default();
+        manifest.upsert(segment(&oldest, 0));
+        manifest.upsert(segment(&newest, 1));
+        manifest
+            .save(&dir.path().join("cam0.manifest.json"), KEY)
+            .unwrap();
+
+        let playlist =
+            resolve(dir.path().to_str().unwrap(), &[KEY.to_string()], None, None).unwrap();
+        assert_eq!(playlist.files, [oldest.clone(), newest.clone()]);
+
+        let playlist = resolve(
+            dir.path().to_str().unwrap(),
+            &[KEY.to_string()],
+            Some(at(1, 10)),
+            None,
+        )
+        .unwrap();
+        assert_eq!(playlist.files, [newest]);
+        assert_eq!(playlist.start_offset, Some(Duration::from_secs(10)));
+    }
+}
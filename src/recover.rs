@@ -0,0 +1,508 @@
+//! `recover`: salvages recordings cut off by a power loss.
+//!
+//! An interrupted recording ends in a partial chunk (or, for legacy files, a
+//! partial cipher block) that cannot be decrypted, and the PES packet being
+//! written at the time is incomplete. Recovery keeps the plaintext up to the
+//! last chunk that authenticates, cuts the MPEG-TS stream before the last PES
+//! packet, and writes the rest to a new, properly finished recording. The
+//! original file is left untouched.
+
+use crate::config::Config;
+use crate::container::{self, ChunkReader, Format, Record};
+use crate::verify::{TS_PACKET_LEN, TS_SYNC_BYTE};
+use aes::Aes256;
+use anyhow::{Context, Result};
+use cbc::cipher::generic_array::GenericArray;
+use cbc::cipher::{BlockDecryptMut, KeyIvInit};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const CBC_BLOCK_LEN: usize = 16;
+
+/// MPEG-TS timestamps count a 90 kHz clock in 33 bits.
+const PTS_HZ: u64 = 90_000;
+const PTS_MASK: u64 = (1 << 33) - 1;
+
+/// Outcome of salvaging one recording.
+#[derive(Debug)]
+pub struct Recovery {
+    /// Why the readable part of the recording ends.
+    pub reason: String,
+    /// Plaintext bytes written to the recovered file.
+    pub kept_bytes: u64,
+    /// Unreadable tail plus the discarded incomplete PES packet.
+    pub lost_bytes: u64,
+    pub kept_duration: Option<Duration>,
+    /// Estimated from the bitrate of the kept part.
+    pub lost_duration: Option<Duration>,
+}
+
+/// Where the readable part of a recording ends.
+pub(crate) struct Tail {
+    /// Bytes of the file after the last readable chunk or block.
+    pub unreadable: u64,
+    /// Why reading stopped, or `None` if the recording ended properly.
+    pub reason: Option<String>,
+}
+
+/// Feeds the readable plaintext of `path` to `f`, in order.
+pub(crate) fn read_plaintext(
+    path: &Path,
+    format: Format,
+    key: &[u8],
+    f: impl FnMut(&[u8]) -> Result<()>,
+) -> Result<Tail> {
+    match format {
+        Format::Aead => read_aead(path, key, f),
+        Format::LegacyCbc => read_legacy(path, key, f),
+    }
+}
+
+fn read_aead(path: &Path, key: &[u8], mut f: impl FnMut(&[u8]) -> Result<()>) -> Result<Tail> {
+    let file_len = std::fs::metadata(path)?.len();
+    let reader = ChunkReader::new(BufReader::new(File::open(path)?), key)?;
+    let record_size = reader.header().record_size();
+    let mut consumed = reader.header().encoded_len();
+
+    for record in reader {
+        match record? {
+            Record::Chunk {
+                plaintext, last, ..
+            } => {
+                f(&plaintext)?;
+                if last {
+                    break;
+                }
+                consumed += record_size;
+            }
+            // Whatever follows a damaged chunk would not continue the
+            // stream cleanly, so the salvaged part ends here.
+            Record::Corrupt { index } => {
+                return Ok(Tail {
+                    unreadable: file_len - consumed,
+                    reason: Some(format!("chunk {} failed authentication", index)),
+                });
+            }
+            Record::Truncated { index, len } => {
+                let reason = if len == 0 {
+                    "the final chunk is missing".to_string()
+                } else {
+                    format!("chunk {} is incomplete ({} bytes)", index, len)
+                };
+                return Ok(Tail {
+                    unreadable: len as u64,
+                    reason: Some(reason),
+                });
+            }
+        }
+    }
+    Ok(Tail {
+        unreadable: 0,
+        reason: None,
+    })
+}
+
+/// Decrypts the complete blocks of an `aesenc serialize-iv=true` stream: the
+/// IV, then AES-256-CBC with PKCS#7 padding at the very end.
+fn read_legacy(path: &Path, key: &[u8], mut f: impl FnMut(&[u8]) -> Result<()>) -> Result<Tail> {
+    let file_len = std::fs::metadata(path)?.len();
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut iv = [0u8; CBC_BLOCK_LEN];
+    reader
+        .read_exact(&mut iv)
+        .context("Recording is too short to contain an IV")?;
+    let mut decryptor = cbc::Decryptor::<Aes256>::new_from_slices(key, &iv)
+        .map_err(|_| anyhow::anyhow!("Legacy recordings need a 32-byte key"))?;
+
+    let body = file_len - CBC_BLOCK_LEN as u64;
+    let partial = body % CBC_BLOCK_LEN as u64;
+    let mut remaining = body - partial;
+    let mut buf = vec![0u8; container::DEFAULT_CHUNK_SIZE as usize];
+    while remaining > 0 {
+        let len = remaining.min(buf.len() as u64) as usize;
+        reader.read_exact(&mut buf[..len])?;
+        remaining -= len as u64;
+        for block in buf[..len].chunks_exact_mut(CBC_BLOCK_LEN) {
+            decryptor.decrypt_block_mut(GenericArray::from_mut_slice(block));
+        }
+        // Valid padding on the final block means the file was finished.
+        if remaining == 0
+            && partial == 0
+            && let Some(padding) = pkcs7_padding(&buf[..len])
+        {
+            f(&buf[..len - padding])?;
+            return Ok(Tail {
+                unreadable: 0,
+                reason: None,
+            });
+        }
+        f(&buf[..len])?;
+    }
+
+    let reason = if partial > 0 {
+        format!("the last cipher block is incomplete ({} bytes)", partial)
+    } else {
+        "the stream ends without padding".to_string()
+    };
+    Ok(Tail {
+        unreadable: partial,
+        reason: Some(reason),
+    })
+}
+
+fn pkcs7_padding(data: &[u8]) -> Option<usize> {
+    let padding = *data.last()? as usize;
+    if padding == 0 || padding > CBC_BLOCK_LEN || padding > data.len() {
+        return None;
+    }
+    data[data.len() - padding..]
+        .iter()
+        .all(|&b| b as usize == padding)
+        .then_some(padding)
+}
+
+/// Finds where the last PES packet starts, and the PTS range before it.
+#[derive(Default)]
+struct TsScan {
+    total: u64,
+    /// Length of the leading run of complete, in-sync packets.
+    packets_end: u64,
+    last_pes_start: Option<u64>,
+    first_pts: Option<u64>,
+    last_pts: Option<u64>,
+    pending: Vec<u8>,
+    lost_sync: bool,
+}
+
+impl TsScan {
+    fn feed(&mut self, data: &[u8]) {
+        self.total += data.len() as u64;
+        if self.lost_sync {
+            return;
+        }
+        self.pending.extend_from_slice(data);
+        let mut pos = 0;
+        while pos + TS_PACKET_LEN <= self.pending.len() {
+            let packet = &self.pending[pos..pos + TS_PACKET_LEN];
+            if packet[0] != TS_SYNC_BYTE {
+                self.lost_sync = true;
+                break;
+            }
+            if let Some(pes) = pes_start(packet) {
+                self.last_pes_start = Some(self.packets_end);
+                if let Some(pts) = pes_pts(pes) {
+                    self.first_pts.get_or_insert(pts);
+                    self.last_pts = Some(pts);
+                }
+            }
+            self.packets_end += TS_PACKET_LEN as u64;
+            pos += TS_PACKET_LEN;
+        }
+        self.pending.drain(..pos);
+    }
+
+    /// Time from the first PES packet to the start of the last one, which is
+    /// what remains once the last one is cut off.
+    fn kept_duration(&self) -> Option<Duration> {
+        let ticks = self.last_pts?.wrapping_sub(self.first_pts?) & PTS_MASK;
+        Some(Duration::from_nanos(ticks * 1_000_000_000 / PTS_HZ))
+    }
+}
+
+/// The payload of `packet` if a PES packet starts in it.
+fn pes_start(packet: &[u8]) -> Option<&[u8]> {
+    let unit_start = packet[1] & 0x40 != 0;
+    let payload_start = match (packet[3] >> 4) & 0x03 {
+        0b01 => 4,
+        0b11 => 5 + packet[4] as usize,
+        _ => return None,
+    };
+    let payload = packet.get(payload_start..)?;
+    (unit_start && payload.starts_with(&[0, 0, 1])).then_some(payload)
+}
+
+fn pes_pts(pes: &[u8]) -> Option<u64> {
+    let has_pts = *pes.get(7)? & 0x80 != 0;
+    let b = pes.get(9..14)?;
+    has_pts.then(|| {
+        ((b[0] as u64 >> 1) & 0x07) << 30
+            | (b[1] as u64) << 22
+            | (b[2] as u64 >> 1) << 15
+            | (b[3] as u64) << 7
+            | b[4] as u64 >> 1
+    })
+}
+
+/// Salvages `path` into a new recording at `output`. Returns `None`, and
+/// writes nothing, if the recording is intact.
+pub fn recover(path: &Path, output: &Path, key: &[u8]) -> Result<Option<Recovery>> {
+    let format = container::detect_format(path)?;
+    let mut scan = TsScan::default();
+    let tail = read_plaintext(path, format, key, |data| {
+        scan.feed(data);
+        Ok(())
+    })?;
+    let Some(reason) = tail.reason else {
+        return Ok(None);
+    };
+    let Some(cut) = scan.last_pes_start.filter(|&cut| cut > 0) else {
+        anyhow::bail!(
+            "Nothing to recover from {} ({}): no complete video before the damage. Is the key right?",
+            path.display(),
+            reason
+        );
+    };
+
+    let file = File::create_new(output)
+        .with_context(|| format!("Failed to create {}", output.display()))?;
+    let result = write_recovered(path, format, key, file, cut);
+    if result.is_err() {
+        let _ = std::fs::remove_file(output);
+    }
+    result?;
+
+    let kept_duration = scan.kept_duration();
+    let lost_bytes = scan.total - cut + tail.unreadable;
+    let lost_duration = kept_duration
+        .filter(|kept| !kept.is_zero())
+        .map(|kept| kept.mul_f64(lost_bytes as f64 / cut as f64));
+    Ok(Some(Recovery {
+        reason,
+        kept_bytes: cut,
+        lost_bytes,
+        kept_duration,
+        lost_duration,
+    }))
+}
+
+/// Re-encrypts the first `len` plaintext bytes of `path` into `file`.
+fn write_recovered(path: &Path, format: Format, key: &[u8], file: File, len: u64) -> Result<()> {
+    let mut writer = container::Writer::new(BufWriter::new(file), key)?;
+    let mut remaining = len;
+    read_plaintext(path, format, key, |data| {
+        let take = remaining.min(data.len() as u64) as usize;
+        std::io::Write::write_all(&mut writer, &data[..take])?;
+        remaining -= take as u64;
+        Ok(())
+    })?;
+    let file = writer.finish()?.into_inner().map_err(|e| e.into_error())?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// `cam0.ts.enc` becomes `cam0.recovered.ts.enc`.
+pub fn default_output(input: &Path) -> PathBuf {
+    let name = input
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let name = match name.split_once('.') {
+        Some((stem, ext)) => format!("{}.recovered.{}", stem, ext),
+        None => format!("{}.recovered", name),
+    };
+    input.with_file_name(name)
+}
+
+pub fn run(config: &Config, input: &Path, output: Option<&Path>) -> Result<()> {
+    let output = output
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| default_output(input));
+    let keys = config.keys();
+    let key = container::parse_key(container::select_key(input, &keys)?)?;
+
+    let Some(recovery) = recover(input, &output, &key)? else {
+        println!("{}: intact, nothing to recover", input.display());
+        return Ok(());
+    };
+    let seconds = |d: Option<Duration>| {
+        d.map(|d| format!("{:.1} s", d.as_secs_f64()))
+            .unwrap_or_else(|| "unknown duration".to_string())
+    };
+    println!("{}: {}", input.display(), recovery.reason);
+    println!(
+        "  kept {} bytes ({}) in {}",
+        recovery.kept_bytes,
+        seconds(recovery.kept_duration),
+        output.display()
+    );
+    println!(
+        "  lost {} bytes (about {})",
+        recovery.lost_bytes,
+        seconds(recovery.lost_duration)
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cbc::cipher::BlockEncryptMut;
+    use cbc::cipher::block_padding::Pkcs7;
+    use std::io::Write;
+
+    const KEY: &str = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+
+    /// `frames` PES packets of three TS packets each, 3000 ticks apart.
+    fn ts_stream(frames: u64) -> Vec<u8> {
+        let mut data = Vec::new();
+        for frame in 0..frames {
+            let pts = frame * 3000;
+            for i in 0..3 {
+                let mut packet = [0xffu8; TS_PACKET_LEN];
+                packet[..4].copy_from_slice(&[TS_SYNC_BYTE, 0x01, 0x00, 0x10]);
+                if i == 0 {
+                    packet[1] |= 0x40;
+                    packet[4..18].copy_from_slice(&[
+                        0,
+                        0,
+                        1,
+                        0xe0,
+                        0,
+                        0,
+                        0x80,
+                        0x80,
+                        5,
+                        0x21 | ((pts >> 29) & 0x0e) as u8,
+                        (pts >> 22) as u8,
+                        ((pts >> 14) as u8 & 0xfe) | 1,
+                        (pts >> 7) as u8,
+                        (pts << 1) as u8 | 1,
+                    ]);
+                }
+                data.extend_from_slice(&packet);
+            }
+        }
+        data
+    }
+
+    fn read_back(path: &Path, key: &[u8]) -> Vec<u8> {
+        let mut plaintext = Vec::new();
+        let reader = ChunkReader::new(File::open(path).unwrap(), key).unwrap();
+        let mut finished = false;
+        for record in reader {
+            match record.unwrap() {
+                Record::Chunk {
+                    plaintext: data,
+                    last,
+                    ..
+                } => {
+                    plaintext.extend(data);
+                    finished = last;
+                }
+                other => panic!("Unexpected record {:?}", other),
+            }
+        }
+        assert!(finished);
+        plaintext
+    }
+
+    #[test]
+    fn test_default_output() {
+        assert_eq!(
+            default_output(Path::new("/rec/cam0_00001.ts.enc")),
+            Path::new("/rec/cam0_00001.recovered.ts.enc")
+        );
+        assert_eq!(
+            default_output(Path::new("recording")),
+            Path::new("recording.recovered")
+        );
+    }
+
+    #[test]
+    fn test_pes_pts() {
+        let data = ts_stream(2);
+        let pes = pes_start(&data[3 * TS_PACKET_LEN..]).unwrap();
+        assert_eq!(pes_pts(pes), Some(3000));
+        assert!(pes_start(&data[TS_PACKET_LEN..]).is_none());
+    }
+
+    #[test]
+    fn test_recover_truncated_recording() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cam0.ts.enc");
+        let output = default_output(&path);
+        let key = container::parse_key(KEY).unwrap();
+        let ts = ts_stream(100);
+
+        // Power loss: full chunks are on disk, the last one only in part.
+        let mut writer =
+            container::Writer::with_chunk_size(File::create(&path).unwrap(), &key, 4096).unwrap();
+        writer.write_all(&ts).unwrap();
+        drop(writer);
+        File::options()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(&[0u8; 752])
+            .unwrap();
+
+        let recovery = recover(&path, &output, &key).unwrap().unwrap();
+        assert_eq!(recovery.reason, "chunk 13 is incomplete (752 bytes)");
+        // 13 chunks hold 283 whole packets; the PES starting at packet 282
+        // is incomplete.
+        assert_eq!(recovery.kept_bytes, 282 * TS_PACKET_LEN as u64);
+        assert_eq!(
+            recovery.kept_duration,
+            Some(Duration::from_nanos(3_133_333_333))
+        );
+        let lost = recovery.lost_duration.unwrap();
+        assert!(lost > Duration::ZERO && lost < Duration::from_secs(1));
+
+        assert_eq!(read_back(&output, &key), ts[..282 * TS_PACKET_LEN]);
+        // The result is a finished recording, and the output is not reused.
+        assert!(
+            recover(&output, &dir.path().join("again"), &key)
+                .unwrap()
+                .is_none()
+        );
+        assert!(recover(&path, &output, &key).is_err());
+    }
+
+    #[test]
+    fn test_recover_legacy_recording() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("legacy.ts.enc");
+        let output = dir.path().join("out.ts.enc");
+        let key = container::parse_key(KEY).unwrap();
+        let iv = [7u8; CBC_BLOCK_LEN];
+        let ts = ts_stream(10);
+
+        let mut buf = ts.clone();
+        buf.resize(ts.len() + CBC_BLOCK_LEN, 0);
+        let ciphertext = cbc::Encryptor::<Aes256>::new_from_slices(&key, &iv)
+            .unwrap()
+            .encrypt_padded_mut::<Pkcs7>(&mut buf, ts.len())
+            .unwrap();
+        let file = [&iv[..], ciphertext].concat();
+
+        std::fs::write(&path, &file).unwrap();
+        assert!(recover(&path, &output, &key).unwrap().is_none());
+
+        // Cut mid-way through packet 20, the third of frame 6.
+        std::fs::write(&path, &file[..CBC_BLOCK_LEN + 20 * TS_PACKET_LEN + 5]).unwrap();
+        let recovery = recover(&path, &output, &key).unwrap().unwrap();
+        assert_eq!(
+            recovery.reason,
+            "the last cipher block is incomplete (5 bytes)"
+        );
+        assert_eq!(recovery.kept_bytes, 18 * TS_PACKET_LEN as u64);
+        assert_eq!(read_back(&output, &key), ts[..18 * TS_PACKET_LEN]);
+    }
+
+    #[test]
+    fn test_recover_with_wrong_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cam0.ts.enc");
+        let key = container::parse_key(KEY).unwrap();
+        let mut writer = container::Writer::new(File::create(&path).unwrap(), &key).unwrap();
+        writer.write_all(&ts_stream(10)).unwrap();
+        writer.finish().unwrap();
+
+        let wrong = [0u8; 32];
+        let output = default_output(&path);
+        assert!(recover(&path, &output, &wrong).is_err());
+        assert!(!output.exists());
+    }
+}
@@ -0,0 +1,435 @@
+//! `rekey`: moves recordings to a new master key.
+//!
+//! Recordings only need their data key rewrapped: they are copied with the
+//! new key block in the header and the chunks as they are, without
+//! decrypting anything. Legacy CBC recordings are encrypted with the master
+//! key itself, so they are decrypted with the old key and encrypted with the
+//! new one in a single streaming pass, with plaintext only ever in memory,
+//! one chunk at a time. Either way the result goes to a temporary file next
+//! to the original, is synced and read back, and only then replaces the
+//! original with a rename. The key block holds the only copy of the data
+//! key, so it is never overwritten in place, where a crash halfway through
+//! would lose the recording. Manifests next to the recordings are updated
+//! and re-signed with the new key. Files already under the new key are
+//! skipped, so an interrupted run can simply be repeated.
+
+use crate::container::{self, ChunkReader, Format, Header, KeyId, Record};
+use crate::manifest::{self, Manifest};
+use crate::playlist;
+use crate::recover;
+use crate::secrets::{self, Secret};
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// Resolves a key source (`env:`, `file:`, a literal, ...) to its hex string.
+fn resolve_key(source: &str, name: &str) -> Result<Secret> {
+    let key = secrets::get_resolver(source)
+        .resolve()
+        .with_context(|| format!("Failed to resolve the {} key", name))?;
+    container::parse_key(key.expose()).with_context(|| format!("Invalid {} key", name))?;
+    Ok(key)
+}
+
+/// Whether `path` already has its data key wrapped by `key`.
+fn is_encrypted_with(path: &Path, key: &[u8]) -> Result<bool> {
+    if container::detect_format(path)? != Format::Aead {
+        return Ok(false);
+    }
+    let header = Header::read_from(&mut BufReader::new(File::open(path)?))?;
+    Ok(header.key_id == KeyId::of(key) && header.data_key(key).is_ok())
+}
+
+/// SHA-256 of the plaintext of a container that must be complete and
+/// authentic under `key`.
+fn plaintext_digest(path: &Path, key: &[u8]) -> Result<Vec<u8>> {
+    let reader = ChunkReader::new(BufReader::new(File::open(path)?), key)?;
+    let mut hasher = Sha256::new();
+    let mut finished = false;
+    for record in reader {
+        match record? {
+            Record::Chunk {
+                plaintext, last, ..
+            } => {
+                hasher.update(&plaintext);
+                finished = last;
+            }
+            Record::Corrupt { index } | Record::Truncated { index, .. } => {
+                anyhow::bail!("chunk {} does not authenticate", index);
+            }
+        }
+    }
+    if !finished {
+        anyhow::bail!("the final chunk is missing");
+    }
+    Ok(hasher.finalize().to_vec())
+}
+
+/// Moves one recording to `new_key`: rewraps its data key, or re-encrypts
+/// a legacy recording into the current format.
+pub fn rekey_file(path: &Path, old_key: &[u8], new_key: &[u8]) -> Result<()> {
+    match container::detect_format(path)? {
+        Format::Aead => {
+            let header = Header::read_from(&mut BufReader::new(File::open(path)?))?;
+            rewrap_file(path, header, old_key, new_key)
+        }
+        Format::LegacyCbc => replace_file(path, |file, tmp| {
+            write_rekeyed(path, file, tmp, old_key, new_key)
+        }),
+    }
+}
+
+/// Replaces `path` with the file `write` produces at a temporary path next
+/// to it, by a rename made durable with a sync of the directory. `write`
+/// must sync the file and check it. On failure the original is untouched
+/// and the temporary file is removed.
+fn replace_file(path: &Path, write: impl FnOnce(File, &Path) -> Result<()>) -> Result<()> {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let tmp = path.with_file_name(format!("{}.rekey.tmp", name));
+    let file =
+        File::create_new(&tmp).with_context(|| format!("Failed to create {}", tmp.display()))?;
+    file.set_permissions(std::fs::metadata(path)?.permissions())?;
+
+    let result = write(file, &tmp)
+        .and_then(|()| Ok(std::fs::rename(&tmp, path)?))
+        .and_then(|()| sync_parent(path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    result
+}
+
+/// Replaces a recording with a copy whose data key is wrapped by
+/// `new_key`. Only the key block changes; the chunks are copied as they are.
+fn rewrap_file(path: &Path, mut header: Header, old_key: &[u8], new_key: &[u8]) -> Result<()> {
+    let data_key = header
+        .data_key(old_key)
+        .with_context(|| format!("{}: wrong old key", path.display()))?;
+    let len = header.encoded_len();
+    header.rewrap(old_key, new_key)?;
+    let bytes = header.to_bytes();
+    if bytes.len() as u64 != len {
+        anyhow::bail!("{}: rewrapped header changed size", path.display());
+    }
+
+    replace_file(path, |file, tmp| {
+        let mut original =
+            File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        original.seek(SeekFrom::Start(len))?;
+        let mut writer = BufWriter::new(file);
+        writer.write_all(&bytes)?;
+        std::io::copy(&mut original, &mut writer)?;
+        writer
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()?;
+
+        let written = Header::read_from(&mut BufReader::new(File::open(tmp)?))?;
+        if written.data_key(new_key)? != data_key {
+            anyhow::bail!("Rewrapped header of {} does not match", path.display());
+        }
+        if std::fs::metadata(tmp)?.len() != original.metadata()?.len() {
+            anyhow::bail!("Rewrapped copy of {} is incomplete", path.display());
+        }
+        Ok(())
+    })
+}
+
+/// Writes the re-encrypted copy of a legacy recording to `tmp` and checks
+/// it decrypts to the same plaintext.
+fn write_rekeyed(
+    path: &Path,
+    file: File,
+    tmp: &Path,
+    old_key: &[u8],
+    new_key: &[u8],
+) -> Result<()> {
+    let mut writer = container::Writer::new(BufWriter::new(file), new_key)?;
+    let mut hasher = Sha256::new();
+    let tail = recover::read_plaintext(path, Format::LegacyCbc, old_key, |data| {
+        hasher.update(data);
+        writer.write_all(data)?;
+        Ok(())
+    })?;
+    if let Some(reason) = tail.reason {
+        anyhow::bail!(
+            "{}: {}. Wrong old key, or run `recover` first",
+            path.display(),
+            reason
+        );
+    }
+    let file = writer.finish()?.into_inner().map_err(|e| e.into_error())?;
+    file.sync_all()?;
+
+    let written = plaintext_digest(tmp, new_key)
+        .with_context(|| format!("Re-encrypted copy of {} is unreadable", path.display()))?;
+    if written != hasher.finalize().as_slice() {
+        anyhow::bail!(
+            "Re-encrypted copy of {} does not match the original",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Makes a rename in the directory of `path` durable.
+fn sync_parent(path: &Path) -> Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+/// Re-signs the manifests in `dirs` with the new key and updates the size
+/// and hash of every segment that was re-encrypted.
+fn update_manifests(dirs: &BTreeSet<PathBuf>, rekeyed: &[PathBuf], old: &str, new: &str) {
+    for dir in dirs {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            continue;
+        };
+        for path in entries.filter_map(|e| e.ok().map(|e| e.path())) {
+            if !path.to_string_lossy().ends_with(".manifest.json") {
+                continue;
+            }
+            // A manifest already signed with the new key is from an earlier,
+            // interrupted run.
+            let manifest = Manifest::load(&path, old).or_else(|_| Manifest::load(&path, new));
+            let result = manifest.and_then(|mut manifest| {
+                for segment in &mut manifest.segments {
+                    let segment_path = dir.join(&segment.path);
+                    if rekeyed.contains(&segment_path) {
+                        (segment.bytes, segment.sha256) = manifest::hash_file(&segment_path)?;
+                    }
+                }
+                manifest.save(&path, new)
+            });
+            match result {
+                Ok(()) => info!("Re-signed {}", path.display()),
+                Err(e) => warn!("Could not update manifest {}: {:#}", path.display(), e),
+            }
+        }
+    }
+}
+
+pub fn run(input: &str, old_key: &str, new_key: &str) -> Result<()> {
+    let old_hex = resolve_key(old_key, "old")?;
+    let new_hex = resolve_key(new_key, "new")?;
+    let old = container::parse_key(old_hex.expose())?;
+    let new = container::parse_key(new_hex.expose())?;
+    if old == new {
+        anyhow::bail!("The old and new keys are the same");
+    }
+
+    let files = playlist::expand(input)?;
+    if files.is_empty() {
+        anyhow::bail!("No recordings found for {}", input);
+    }
+
+    let mut rekeyed = Vec::new();
+    let mut failed = 0;
+    for path in &files {
+        match is_encrypted_with(path, &new) {
+            Ok(true) => {
+                info!("{} is already under the new key", path.display());
+                rekeyed.push(path.clone());
+                continue;
+            }
+            Ok(false) => (),
+            Err(e) => {
+                warn!("Skipping {}: {:#}", path.display(), e);
+                failed += 1;
+                continue;
+            }
+        }
+        match rekey_file(path, &old, &new) {
+            Ok(()) => {
+                info!("Re-encrypted {}", path.display());
+                rekeyed.push(path.clone());
+            }
+            Err(e) => {
+                warn!("Failed to re-encrypt {}: {:#}", path.display(), e);
+                failed += 1;
+            }
+        }
+    }
+
+    let dirs: BTreeSet<PathBuf> = files
+        .iter()
+        .filter_map(|p| p.parent().map(Path::to_path_buf))
+        .collect();
+    update_manifests(&dirs, &rekeyed, old_hex.expose(), new_hex.expose());
+
+    if failed > 0 {
+        anyhow::bail!(
+            "{} of {} recording(s) could not be re-encrypted",
+            failed,
+            files.len()
+        );
+    }
+    info!("Re-encrypted {} recording(s)", rekeyed.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::Segment;
+    use cbc::cipher::block_padding::Pkcs7;
+    use cbc::cipher::{BlockEncryptMut, KeyIvInit};
+    use chrono::Utc;
+
+    const OLD: &str = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+    const NEW: &str = "ffeeddccbbaa99887766554433221100ffeeddccbbaa99887766554433221100";
+
+    fn write_recording(path: &Path, key: &str, data: &[u8]) {
+        let key = container::parse_key(key).unwrap();
+        let mut writer =
+            container::Writer::with_chunk_size(File::create(path).unwrap(), &key, 1024).unwrap();
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap();
+    }
+
+    /// A legacy recording: AES-256-CBC under the master key itself.
+    fn write_legacy_recording(path: &Path, key: &str, data: &[u8]) {
+        let key = container::parse_key(key).unwrap();
+        let iv = [7u8; 16];
+        let mut buf = data.to_vec();
+        buf.resize(data.len() + 16, 0);
+        let ciphertext = cbc::Encryptor::<aes::Aes256>::new_from_slices(&key, &iv)
+            .unwrap()
+            .encrypt_padded_mut::<Pkcs7>(&mut buf, data.len())
+            .unwrap();
+        std::fs::write(path, [&iv[..], ciphertext].concat()).unwrap();
+    }
+
+    fn digest(data: &[u8]) -> Vec<u8> {
+        Sha256::digest(data).to_vec()
+    }
+
+    #[test]
+    fn test_rekey_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("cam0_00000.ts.enc");
+        let second = dir.path().join("cam0_00001.ts.enc");
+        let data: Vec<u8> = (0..5000u32).map(|i| i as u8).collect();
+        write_recording(&first, OLD, &data);
+        write_legacy_recording(&second, OLD, &data[..100]);
+        let original = std::fs::read(&first).unwrap();
+
+        let mut manifest = Manifest::default();
+        manifest.upsert(Segment {
+            path: "cam0_00000.ts.enc".to_string(),
+            start_pts: 0,
+            end_pts: 0,
+            start_time: Utc::now(),
+            end_time: Utc::now(),
+            bytes: 0,
+            sha256: String::new(),
+        });
+        let manifest_path = dir.path().join("cam0.manifest.json");
+        manifest.save(&manifest_path, OLD).unwrap();
+
+        unsafe {
+            std::env::set_var("REKEY_TEST_NEW_KEY", NEW);
+        }
+        run(dir.path().to_str().unwrap(), OLD, "env:REKEY_TEST_NEW_KEY").unwrap();
+        unsafe {
+            std::env::remove_var("REKEY_TEST_NEW_KEY");
+        }
+
+        let new = container::parse_key(NEW).unwrap();
+        assert_eq!(plaintext_digest(&first, &new).unwrap(), digest(&data));
+        assert_eq!(
+            plaintext_digest(&second, &new).unwrap(),
+            digest(&data[..100])
+        );
+        let old = container::parse_key(OLD).unwrap();
+        assert!(plaintext_digest(&first, &old).is_err());
+        assert!(plaintext_digest(&second, &old).is_err());
+
+        // The current recording only had its key block rewritten; the older
+        // one was re-encrypted into the current format.
+        let rekeyed = std::fs::read(&first).unwrap();
+        let header = Header::read_from(&mut &rekeyed[..]).unwrap();
+        let header_len = header.encoded_len() as usize;
+        assert_eq!(rekeyed.len(), original.len());
+        assert_eq!(rekeyed[header_len..], original[header_len..]);
+        assert!(is_encrypted_with(&second, &new).unwrap());
+
+        assert!(Manifest::load(&manifest_path, OLD).is_err());
+        let manifest = Manifest::load(&manifest_path, NEW).unwrap();
+        let (bytes, sha256) = manifest::hash_file(&first).unwrap();
+        assert_eq!(manifest.segments[0].bytes, bytes);
+        assert_eq!(manifest.segments[0].sha256, sha256);
+
+        // Nothing but the recordings and the manifest is left behind, and a
+        // second run finds everything already done.
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 3);
+        run(dir.path().to_str().unwrap(), OLD, NEW).unwrap();
+        assert_eq!(plaintext_digest(&first, &new).unwrap(), digest(&data));
+    }
+
+    #[test]
+    fn test_rekey_keeps_damaged_original() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cam0.ts.enc");
+        write_legacy_recording(&path, OLD, &[0x47u8; 5000]);
+        let original = std::fs::read(&path).unwrap();
+        let truncated = &original[..original.len() - 10];
+        std::fs::write(&path, truncated).unwrap();
+
+        let old = container::parse_key(OLD).unwrap();
+        let new = container::parse_key(NEW).unwrap();
+        let err = rekey_file(&path, &old, &new).unwrap_err();
+        assert!(err.to_string().contains("run `recover` first"));
+        assert_eq!(std::fs::read(&path).unwrap(), truncated);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        // So does a wrong old key, in either format.
+        write_legacy_recording(&path, OLD, &[0x47u8; 5000]);
+        let original = std::fs::read(&path).unwrap();
+        assert!(rekey_file(&path, &new, &old).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), original);
+        write_recording(&path, OLD, &[0x47u8; 5000]);
+        let original = std::fs::read(&path).unwrap();
+        assert!(rekey_file(&path, &new, &old).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), original);
+    }
+
+    #[test]
+    fn test_rewrap_replaces_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cam0.ts.enc");
+        let data = [0x47u8; 5000];
+        write_recording(&path, OLD, &data);
+        // A second link to the original sees what happens to its contents.
+        let link = dir.path().join("link.enc");
+        std::fs::hard_link(&path, &link).unwrap();
+        let original = std::fs::read(&path).unwrap();
+
+        let old = container::parse_key(OLD).unwrap();
+        let new = container::parse_key(NEW).unwrap();
+        rekey_file(&path, &old, &new).unwrap();
+
+        // The original was never written to, only replaced.
+        assert_eq!(std::fs::read(&link).unwrap(), original);
+        assert_eq!(plaintext_digest(&path, &new).unwrap(), digest(&data));
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_rekey_rejects_bad_keys() {
+        assert!(run("missing", OLD, OLD).is_err());
+        assert!(run("missing", OLD, "not-hex").is_err());
+        assert!(run("missing", "env:REKEY_TEST_UNSET", NEW).is_err());
+    }
+}
@@ -0,0 +1,84 @@
+//! `serve`: exposes the live camera as an RTSP mount point.
+//!
+//! Built only with the `rtsp` cargo feature, since it needs the
+//! gst-rtsp-server development libraries.
+
+use crate::cli::RtspAuth;
+use crate::config::Config;
+use crate::pipeline;
+use anyhow::{Context, Result};
+use gstreamer_rtsp_server::prelude::*;
+use gstreamer_rtsp_server::{
+    RTSP_PERM_MEDIA_FACTORY_ACCESS, RTSP_PERM_MEDIA_FACTORY_CONSTRUCT,
+    RTSP_TOKEN_MEDIA_FACTORY_ROLE, RTSPAuth, RTSPMediaFactory, RTSPServer, RTSPToken, glib, gst,
+    gst_rtsp,
+};
+use tracing::{info, warn};
+
+pub fn run_serve(
+    config: &Config,
+    listen: &str,
+    port: u16,
+    mount: &str,
+    auth_method: RtspAuth,
+) -> Result<()> {
+    gst::init().context("Failed to initialize GStreamer")?;
+
+    let launch = pipeline::build_serve_pipeline(config)?;
+    info!("Media pipeline: {}", launch);
+
+    let factory = RTSPMediaFactory::new();
+    factory.set_launch(&launch);
+    // All clients share one capture pipeline; a V4L2 device can only be
+    // opened once anyway.
+    factory.set_shared(true);
+
+    let server = RTSPServer::new();
+    server.set_address(listen);
+    server.set_service(&port.to_string());
+
+    if let (Some(user), Some(password)) = (&config.rtsp_username, &config.rtsp_password) {
+        let password = password.expose();
+        let auth = RTSPAuth::new();
+        let token = RTSPToken::builder()
+            .field(RTSP_TOKEN_MEDIA_FACTORY_ROLE, user.as_str())
+            .build();
+        match auth_method {
+            RtspAuth::Basic => auth.add_basic(&RTSPAuth::make_basic(user, password), &token),
+            RtspAuth::Digest => {
+                auth.add_digest(user, password, &token);
+                auth.set_supported_methods(gst_rtsp::RTSPAuthMethod::Digest);
+            }
+        }
+        server.set_auth(Some(&auth));
+        factory.add_role_from_structure(
+            &gst::Structure::builder(user.as_str())
+                .field(RTSP_PERM_MEDIA_FACTORY_ACCESS, true)
+                .field(RTSP_PERM_MEDIA_FACTORY_CONSTRUCT, true)
+                .build(),
+        );
+    } else {
+        warn!(
+            "No rtsp_username/rtsp_password configured; anyone who can reach {}:{} can watch",
+            listen, port
+        );
+    }
+
+    server
+        .mount_points()
+        .context("RTSP server has no mount points")?
+        .add_factory(mount, factory);
+
+    let main_loop = glib::MainLoop::new(None, false);
+    let _source = server.attach(None).context("Failed to start RTSP server")?;
+    info!("Serving rtsp://{}:{}{}", listen, port, mount);
+
+    let main_loop_clone = main_loop.clone();
+    ctrlc::set_handler(move || {
+        info!("Ctrl+C received, stopping RTSP server...");
+        main_loop_clone.quit();
+    })?;
+    main_loop.run();
+
+    Ok(())
+}
@@ -1,11 +1,83 @@
 use anyhow::{Context, Result};
+use rand::RngCore;
 use std::fs;
+use std::io::IsTerminal;
 use std::path::Path;
+use zeroize::Zeroizing;
+
+/// A resolved secret, such as a hex key or a password. It is wiped from
+/// memory when dropped and prints as `[REDACTED]` in `Debug` output, so a
+/// logged `Config` does not leak it. It deliberately does not implement
+/// `Serialize`. Use `expose` where the value is needed.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Secret(Zeroizing<String>);
+
+impl Secret {
+    pub fn new(value: impl Into<String>) -> Self {
+        Self(Zeroizing::new(value.into()))
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Self::new(value)
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(value: &str) -> Self {
+        Self::new(value)
+    }
+}
+
+impl AsRef<str> for Secret {
+    fn as_ref(&self) -> &str {
+        self.expose()
+    }
+}
+
+impl PartialEq<str> for Secret {
+    fn eq(&self, other: &str) -> bool {
+        self.expose() == other
+    }
+}
+
+impl PartialEq<&str> for Secret {
+    fn eq(&self, other: &&str) -> bool {
+        self.expose() == *other
+    }
+}
+
+impl PartialEq<String> for Secret {
+    fn eq(&self, other: &String) -> bool {
+        self.expose() == other
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("[REDACTED]")
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Secret {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self::from)
+    }
+}
 
 /// A trait for resolving sensitive configuration values.
 pub trait SecretResolver {
-    /// Resolves the secret to a string.
-    fn resolve(&self) -> Result<String>;
+    /// Resolves the secret.
+    fn resolve(&self) -> Result<Secret>;
 }
 
 /// Resolves a secret from a literal string.
@@ -23,12 +95,10 @@ impl LiteralResolver {
 }
 
 impl SecretResolver for LiteralResolver {
-    fn resolve(&self) -> Result<String> {
-        Ok(self
-            .value
-            .strip_prefix("literal:")
-            .unwrap_or(&self.value)
-            .to_string())
+    fn resolve(&self) -> Result<Secret> {
+        Ok(Secret::from(
+            self.value.strip_prefix("literal:").unwrap_or(&self.value),
+        ))
     }
 }
 
@@ -47,8 +117,9 @@ impl EnvVarResolver {
 }
 
 impl SecretResolver for EnvVarResolver {
-    fn resolve(&self) -> Result<String> {
+    fn resolve(&self) -> Result<Secret> {
         std::env::var(&self.var_name)
+            .map(Secret::from)
             .with_context(|| format!("Failed to read environment variable: {}", self.var_name))
     }
 }
@@ -66,65 +137,459 @@ impl FileResolver {
 }
 
 impl SecretResolver for FileResolver {
-    fn resolve(&self) -> Result<String> {
+    fn resolve(&self) -> Result<Secret> {
         let path = Path::new(&self.path);
         fs::read_to_string(path)
+            .map(Zeroizing::new)
             .with_context(|| format!("Failed to read secret file: {:?}", path))
-            .map(|s| s.trim().to_string())
+            .map(|s| Secret::from(s.trim()))
+    }
+}
+
+/// Argon2id parameters used for new passphrase keys: 64 MiB, 3 passes.
+const DEFAULT_KDF_MEMORY_KIB: u32 = 64 * 1024;
+const DEFAULT_KDF_ITERATIONS: u32 = 3;
+const DEFAULT_KDF_PARALLELISM: u32 = 1;
+const KDF_SALT_LEN: usize = 16;
+const DERIVED_KEY_LEN: usize = 32;
+
+/// Derives an AES-256 key from a passphrase with Argon2id.
+/// Format: "passphrase:salt=HEX,m=KIB,t=PASSES,p=LANES[,fd=N]"
+///
+/// The salt and cost parameters live in the source string, so the config
+/// holds everything but the passphrase. It is prompted for on the terminal,
+/// or read from file descriptor `fd` when set (for scripts and services).
+/// The descriptor must be 3 or above; it is read through a duplicate and
+/// left open.
+pub struct PassphraseResolver {
+    spec: String,
+}
+
+impl PassphraseResolver {
+    pub fn new(spec: impl Into<String>) -> Self {
+        Self { spec: spec.into() }
+    }
+
+    /// Source string for a new passphrase key with a random salt and the
+    /// default parameters.
+    pub fn new_source() -> String {
+        let mut salt = [0u8; KDF_SALT_LEN];
+        rand::rng().fill_bytes(&mut salt);
+        format!(
+            "passphrase:salt={},m={},t={},p={}",
+            hex::encode(salt),
+            DEFAULT_KDF_MEMORY_KIB,
+            DEFAULT_KDF_ITERATIONS,
+            DEFAULT_KDF_PARALLELISM
+        )
+    }
+
+    /// Parses the spec into Argon2 parameters, the salt and the optional fd.
+    fn parse(&self) -> Result<(argon2::Params, Vec<u8>, Option<i32>)> {
+        let mut salt = None;
+        let mut memory = DEFAULT_KDF_MEMORY_KIB;
+        let mut iterations = DEFAULT_KDF_ITERATIONS;
+        let mut parallelism = DEFAULT_KDF_PARALLELISM;
+        let mut fd = None;
+        for field in self.spec.split(',').filter(|f| !f.is_empty()) {
+            let (name, value) = field
+                .split_once('=')
+                .with_context(|| format!("Invalid passphrase parameter: {}", field))?;
+            let number = || {
+                value
+                    .parse::<u32>()
+                    .with_context(|| format!("Invalid passphrase parameter: {}", field))
+            };
+            match name {
+                "salt" => salt = Some(hex::decode(value).context("Passphrase salt is not hex")?),
+                "m" => memory = number()?,
+                "t" => iterations = number()?,
+                "p" => parallelism = number()?,
+                "fd" => fd = Some(number()? as i32),
+                _ => anyhow::bail!("Unknown passphrase parameter: {}", name),
+            }
+        }
+        let salt = salt.context("Passphrase keys need a salt (salt=HEX)")?;
+        let params = argon2::Params::new(memory, iterations, parallelism, Some(DERIVED_KEY_LEN))
+            .map_err(|e| anyhow::anyhow!("Invalid Argon2 parameters: {}", e))?;
+        Ok((params, salt, fd))
+    }
+
+    fn read_passphrase(fd: Option<i32>) -> Result<Secret> {
+        if let Some(fd) = fd {
+            use std::io::Read;
+            use std::os::fd::BorrowedFd;
+            if fd <= 2 {
+                anyhow::bail!(
+                    "fd={} is a standard stream; pass the passphrase on fd 3 or above",
+                    fd
+                );
+            }
+            // SAFETY: F_GETFD has no effect beyond checking that `fd` is open.
+            if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+                anyhow::bail!("fd={} is not an open file descriptor", fd);
+            }
+            // The descriptor is only borrowed: reading from a duplicate
+            // leaves it open for whoever else holds it.
+            // SAFETY: `fd` was just checked to be open, and nothing here
+            // closes it while the borrow lasts.
+            let borrowed = unsafe { BorrowedFd::borrow_raw(fd) };
+            let mut file = fs::File::from(
+                borrowed
+                    .try_clone_to_owned()
+                    .with_context(|| format!("Failed to duplicate fd {}", fd))?,
+            );
+            let mut passphrase = Zeroizing::new(String::new());
+            file.read_to_string(&mut passphrase)
+                .with_context(|| format!("Failed to read passphrase from fd {}", fd))?;
+            return Ok(Secret::from(passphrase.trim_end_matches(['\r', '\n'])));
+        }
+        if !std::io::stdin().is_terminal() {
+            anyhow::bail!("No terminal to prompt for the passphrase; pass it with fd=N");
+        }
+        inquire::Password::new("Passphrase:")
+            .without_confirmation()
+            .prompt()
+            .map(Secret::from)
+            .context("Failed to read passphrase")
+    }
+
+    /// Derives the hex key for `passphrase` with the spec's salt and cost.
+    pub fn derive(&self, passphrase: &str) -> Result<Secret> {
+        let (params, salt, _) = self.parse()?;
+        if passphrase.is_empty() {
+            anyhow::bail!("Empty passphrase");
+        }
+        let mut key = Zeroizing::new([0u8; DERIVED_KEY_LEN]);
+        argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), &salt, key.as_mut())
+            .map_err(|e| anyhow::anyhow!("Failed to derive key from passphrase: {}", e))?;
+        Ok(Secret::new(hex::encode(key.as_ref())))
+    }
+}
+
+impl SecretResolver for PassphraseResolver {
+    fn resolve(&self) -> Result<Secret> {
+        let (_, _, fd) = self.parse()?;
+        self.derive(Self::read_passphrase(fd)?.expose())
+    }
+}
+
+/// Resolves an X25519 private key (an identity) for reading recordings
+/// sealed to its public key. The source takes any of the forms above
+/// (`env:`, `file:`, ...) and must hold 64 hex characters.
+pub struct PrivateKeyResolver {
+    source: String,
+}
+
+impl PrivateKeyResolver {
+    pub fn new(source: impl Into<String>) -> Self {
+        Self {
+            source: source.into(),
+        }
+    }
+
+    /// A new random identity, hex-encoded.
+    pub fn generate() -> String {
+        let mut identity = [0u8; crate::container::X25519_KEY_LEN];
+        rand::rng().fill_bytes(&mut identity);
+        hex::encode(identity)
+    }
+
+    /// The public key (hex) recordings are sealed to for `identity`.
+    pub fn recipient(identity: &str) -> Result<String> {
+        let identity = crate::container::parse_x25519_key(identity)?;
+        Ok(hex::encode(crate::container::recipient_of(&identity)))
+    }
+}
+
+impl SecretResolver for PrivateKeyResolver {
+    fn resolve(&self) -> Result<Secret> {
+        let identity = get_resolver(&self.source).resolve()?;
+        crate::container::parse_x25519_key(identity.expose()).context("Invalid identity")?;
+        Ok(Secret::new(identity.expose().to_ascii_lowercase()))
     }
 }
 
 /// Resolves a secret from AWS Secrets Manager.
-/// Format: "aws:REGION:SECRET_ID"
+/// Format: "aws:REGION:SECRET_ID[#FIELD]" or "aws:SECRET_ARN[#FIELD]"
+///
+/// `FIELD` picks a string field of a JSON secret. Credentials, the default
+/// region and an endpoint override (e.g. LocalStack) come from the usual
+/// `AWS_*` environment variables, profiles or the instance role; see
+/// `aws::Settings`.
 pub struct AwsSecretManagerResolver {
-    region: String,
-    secret_id: String,
+    source: String,
 }
 
 impl AwsSecretManagerResolver {
     pub fn new(source: &str) -> Self {
-        let parts: Vec<&str> = source.splitn(2, ':').collect();
-        let region = parts.first().unwrap_or(&"us-east-1").to_string();
-        let secret_id = parts.get(1).unwrap_or(&"").to_string();
-        Self { region, secret_id }
+        Self {
+            source: source.to_string(),
+        }
     }
 }
 
 impl SecretResolver for AwsSecretManagerResolver {
-    fn resolve(&self) -> Result<String> {
-        // TODO: Implement AWS Secrets Manager integration using aws-sdk-secretsmanager
-        Err(anyhow::anyhow!(
-            "AWS Secrets Manager resolution not yet implemented. Region: {}, Secret: {}",
-            self.region,
-            self.secret_id
-        ))
+    fn resolve(&self) -> Result<Secret> {
+        crate::aws::resolve(&self.source)
+            .map(Secret::from)
+            .with_context(|| format!("Failed to resolve aws:{}", self.source))
+    }
+}
+
+/// Unwraps a key inside a PKCS#11 token (an HSM, or SoftHSM2 for testing).
+/// Format: "pkcs11:module=PATH,token=LABEL|slot=ID,key=LABEL,pin=SOURCE,wrapped=SOURCE[,mechanism=aes-gcm|rsa-oaep]"
+///
+/// Only the wrapped key is stored on the recorder; see `pkcs11::Spec`.
+pub struct Pkcs11Resolver {
+    spec: String,
+}
+
+impl Pkcs11Resolver {
+    pub fn new(spec: &str) -> Self {
+        Self {
+            spec: spec.to_string(),
+        }
     }
 }
 
-/// Resolves a secret from HashiCorp Vault.
-/// Format: "vault:PATH:KEY"
+impl SecretResolver for Pkcs11Resolver {
+    fn resolve(&self) -> Result<Secret> {
+        // The spec may hold a literal PIN, so it is not repeated in errors.
+        crate::pkcs11::Spec::parse(&self.spec)
+            .and_then(|spec| crate::pkcs11::unwrap(&spec))
+            .map(Secret::from)
+            .context("Failed to resolve pkcs11 key")
+    }
+}
+
+/// Resolves a key from HashiCorp Vault's KV version 2 engine, optionally
+/// unwrapped by the Transit engine.
+/// Format: "vault:PATH:FIELD[,mount=MOUNT][,transit=KEY][,transit_mount=MOUNT]"
+///
+/// The address, CA and credentials (token, token file or AppRole) come from
+/// the usual `VAULT_*` environment variables; see `vault::Settings`.
 pub struct VaultResolver {
-    path: String,
-    key: String,
+    source: String,
 }
 
 impl VaultResolver {
     pub fn new(source: &str) -> Self {
-        let parts: Vec<&str> = source.splitn(2, ':').collect();
-        let path = parts.first().unwrap_or(&"").to_string();
-        let key = parts.get(1).unwrap_or(&"").to_string();
-        Self { path, key }
+        Self {
+            source: source.to_string(),
+        }
     }
 }
 
 impl SecretResolver for VaultResolver {
-    fn resolve(&self) -> Result<String> {
-        // TODO: Implement HashiCorp Vault integration
-        Err(anyhow::anyhow!(
-            "HashiCorp Vault resolution not yet implemented. Path: {}, Key: {}",
-            self.path,
-            self.key
-        ))
+    fn resolve(&self) -> Result<Secret> {
+        crate::vault::resolve(&self.source)
+            .map(Secret::from)
+            .with_context(|| format!("Failed to resolve vault:{}", self.source))
+    }
+}
+
+/// Reads a key from the Linux kernel keyring, so it never touches the disk.
+/// Format: "keyring:[@s|@u|@us|@p|@t:]DESCRIPTION"
+///
+/// Without a keyring the key is looked up like `keyctl request user DESC`,
+/// which searches the thread, process and session keyrings. The payload of
+/// the `user` key is either hex text or the raw 16 or 32 key bytes.
+pub struct KernelKeyringResolver {
+    source: String,
+}
+
+impl KernelKeyringResolver {
+    pub fn new(source: &str) -> Self {
+        Self {
+            source: source.to_string(),
+        }
+    }
+
+    /// Splits the source into the special keyring ID, if any, and the key
+    /// description.
+    fn parse(&self) -> Result<(Option<i32>, &str)> {
+        let (keyring, description) = match self.source.split_once(':') {
+            Some((keyring, description)) if keyring.starts_with('@') => {
+                let id = match keyring {
+                    "@t" => libc::KEY_SPEC_THREAD_KEYRING,
+                    "@p" => libc::KEY_SPEC_PROCESS_KEYRING,
+                    "@s" => libc::KEY_SPEC_SESSION_KEYRING,
+                    "@u" => libc::KEY_SPEC_USER_KEYRING,
+                    "@us" => libc::KEY_SPEC_USER_SESSION_KEYRING,
+                    _ => anyhow::bail!(
+                        "Unknown keyring '{}' (expected @s, @u, @us, @p or @t)",
+                        keyring
+                    ),
+                };
+                (Some(id), description)
+            }
+            _ => (None, self.source.as_str()),
+        };
+        if description.is_empty() {
+            anyhow::bail!("Missing key description");
+        }
+        Ok((keyring, description))
+    }
+
+    #[cfg(target_os = "linux")]
+    fn read(keyring: Option<i32>, description: &str) -> Result<Zeroizing<Vec<u8>>> {
+        use std::ffi::CString;
+
+        let description = CString::new(description).context("Invalid key description")?;
+        let id = unsafe {
+            match keyring {
+                Some(keyring) => libc::syscall(
+                    libc::SYS_keyctl,
+                    libc::KEYCTL_SEARCH,
+                    keyring,
+                    c"user".as_ptr(),
+                    description.as_ptr(),
+                    0,
+                ),
+                None => libc::syscall(
+                    libc::SYS_request_key,
+                    c"user".as_ptr(),
+                    description.as_ptr(),
+                    std::ptr::null::<libc::c_char>(),
+                    0,
+                ),
+            }
+        };
+        if id < 0 {
+            let err = std::io::Error::last_os_error();
+            let hint = match err.raw_os_error() {
+                Some(libc::ENOKEY) => {
+                    " (add it with `keyctl padd user DESCRIPTION @s`, and check that the service shares the session keyring)"
+                }
+                Some(libc::EKEYEXPIRED) | Some(libc::EKEYREVOKED) => " (add the key again)",
+                Some(libc::EACCES) => " (the key is not readable by this user)",
+                Some(libc::ENOSYS) | Some(libc::EPERM) => {
+                    " (the keyring is not available here; a container may block keyctl)"
+                }
+                _ => "",
+            };
+            anyhow::bail!("Failed to find key: {}{}", err, hint);
+        }
+
+        // KEYCTL_READ returns the payload size, which may exceed the buffer.
+        let mut payload = Zeroizing::new(vec![0u8; 64]);
+        loop {
+            let len = unsafe {
+                libc::syscall(
+                    libc::SYS_keyctl,
+                    libc::KEYCTL_READ,
+                    id,
+                    payload.as_mut_ptr(),
+                    payload.len(),
+                )
+            };
+            if len < 0 {
+                return Err(std::io::Error::last_os_error()).context("Failed to read key");
+            }
+            let len = len as usize;
+            if len <= payload.len() {
+                payload.truncate(len);
+                return Ok(payload);
+            }
+            payload = Zeroizing::new(vec![0u8; len]);
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn read(_keyring: Option<i32>, _description: &str) -> Result<Zeroizing<Vec<u8>>> {
+        anyhow::bail!("The kernel keyring is only available on Linux")
+    }
+}
+
+impl SecretResolver for KernelKeyringResolver {
+    fn resolve(&self) -> Result<Secret> {
+        let (keyring, description) = self.parse()?;
+        let payload = Self::read(keyring, description)
+            .with_context(|| format!("Failed to resolve keyring:{}", self.source))?;
+        decode_payload(&payload).with_context(|| format!("Key keyring:{} is unusable", self.source))
+    }
+}
+
+/// Reads a systemd credential, as passed to the service with
+/// `LoadCredential=`, `LoadCredentialEncrypted=` or `SetCredentialEncrypted=`.
+/// Format: "credential:NAME"
+///
+/// systemd decrypts encrypted credentials (with the TPM or the host key) into
+/// a private, non-swappable `$CREDENTIALS_DIRECTORY` that only the service
+/// can read. Like keyring keys, a credential is hex text or the raw key bytes.
+pub struct CredentialResolver {
+    name: String,
+}
+
+impl CredentialResolver {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+        }
+    }
+}
+
+impl SecretResolver for CredentialResolver {
+    fn resolve(&self) -> Result<Secret> {
+        if self.name.is_empty() || self.name.contains('/') || self.name == "." || self.name == ".."
+        {
+            anyhow::bail!("Invalid credential name '{}'", self.name);
+        }
+        let dir = std::env::var_os("CREDENTIALS_DIRECTORY").with_context(|| {
+            format!(
+                "Failed to resolve credential:{}: CREDENTIALS_DIRECTORY is not set (run under systemd with LoadCredentialEncrypted={}:PATH)",
+                self.name, self.name
+            )
+        })?;
+        let path = Path::new(&dir).join(&self.name);
+        let payload = fs::read(&path).map(Zeroizing::new).with_context(|| {
+            format!(
+                "Failed to read credential {:?} (is it listed in the unit's LoadCredential*= settings?)",
+                path
+            )
+        })?;
+        decode_payload(&payload).with_context(|| format!("Credential {:?} is unusable", path))
+    }
+}
+
+/// Decodes a secret stored as bytes: trimmed text (a hex key or a password),
+/// or a raw 16 or 32 byte key, which becomes hex.
+fn decode_payload(payload: &[u8]) -> Result<Secret> {
+    let text = std::str::from_utf8(payload)
+        .ok()
+        .map(str::trim)
+        .filter(|text| !text.is_empty() && !text.chars().any(char::is_control));
+    match text {
+        Some(text) => Ok(Secret::from(text)),
+        None if payload.len() == 16 || payload.len() == 32 => {
+            Ok(Secret::from(hex::encode(payload)))
+        }
+        None => anyhow::bail!("Neither text nor a 16 or 32 byte key"),
+    }
+}
+
+/// Hides the secret part of a key source for display. References such as
+/// `env:VAR` or `file:PATH` are kept, since they help find where a key
+/// comes from; literal values are replaced.
+pub fn redact(source: &str) -> String {
+    const REFERENCES: &[&str] = &[
+        "env:",
+        "file:",
+        "passphrase:",
+        "aws:",
+        "vault:",
+        "keyring:",
+        "credential:",
+    ];
+    if REFERENCES.iter().any(|prefix| source.starts_with(prefix)) {
+        source.to_string()
+    } else if source.starts_with("pkcs11:") {
+        // The spec may hold a literal PIN.
+        "pkcs11:[REDACTED]".to_string()
+    } else {
+        "[REDACTED]".to_string()
     }
 }
 
@@ -134,10 +599,18 @@ pub fn get_resolver(source: &str) -> Box<dyn SecretResolver> {
         Box::new(EnvVarResolver::new(var_name))
     } else if let Some(path) = source.strip_prefix("file:") {
         Box::new(FileResolver::new(path))
+    } else if let Some(spec) = source.strip_prefix("passphrase:") {
+        Box::new(PassphraseResolver::new(spec))
     } else if let Some(aws_src) = source.strip_prefix("aws:") {
         Box::new(AwsSecretManagerResolver::new(aws_src))
     } else if let Some(vault_src) = source.strip_prefix("vault:") {
         Box::new(VaultResolver::new(vault_src))
+    } else if let Some(spec) = source.strip_prefix("pkcs11:") {
+        Box::new(Pkcs11Resolver::new(spec))
+    } else if let Some(description) = source.strip_prefix("keyring:") {
+        Box::new(KernelKeyringResolver::new(description))
+    } else if let Some(name) = source.strip_prefix("credential:") {
+        Box::new(CredentialResolver::new(name))
     } else {
         // Default to literal if no prefix or explicit "literal:" prefix
         Box::new(LiteralResolver::new(source))
@@ -197,6 +670,103 @@ mod tests {
         assert_eq!(resolver.resolve().unwrap(), "file_secret_trimmed");
     }
 
+    /// Resolves `spec` with the passphrase supplied through a descriptor.
+    fn resolve_passphrase(spec: &str, passphrase: &str) -> Result<Secret> {
+        use std::os::fd::AsRawFd;
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "{}", passphrase).unwrap();
+        let reader = fs::File::open(file.path()).unwrap();
+        get_resolver(&format!("passphrase:{},fd={}", spec, reader.as_raw_fd())).resolve()
+    }
+
+    #[test]
+    fn test_passphrase_fd_is_borrowed() {
+        use std::os::fd::AsRawFd;
+        let spec = "salt=000102030405060708090a0b0c0d0e0f,m=64,t=1,p=1";
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "correct horse battery staple").unwrap();
+        let reader = fs::File::open(file.path()).unwrap();
+        let fd = reader.as_raw_fd();
+
+        get_resolver(&format!("passphrase:{},fd={}", spec, fd))
+            .resolve()
+            .unwrap();
+        // Still open: closing it is up to its owner.
+        assert_ne!(unsafe { libc::fcntl(fd, libc::F_GETFD) }, -1);
+
+        for fd in [0, 1, 2] {
+            let error = get_resolver(&format!("passphrase:{},fd={}", spec, fd))
+                .resolve()
+                .unwrap_err();
+            assert!(error.to_string().contains("standard stream"), "{}", error);
+        }
+        let error = get_resolver(&format!("passphrase:{},fd=999999", spec))
+            .resolve()
+            .unwrap_err();
+        assert!(
+            error.to_string().contains("not an open file descriptor"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_passphrase_resolver() {
+        // Cheap parameters; real configs use the defaults.
+        let spec = "salt=000102030405060708090a0b0c0d0e0f,m=64,t=1,p=1";
+        let key = resolve_passphrase(spec, "correct horse battery staple").unwrap();
+        assert_eq!(key.expose().len(), 64);
+        assert!(key.expose().chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(
+            resolve_passphrase(spec, "correct horse battery staple").unwrap(),
+            key
+        );
+        assert_ne!(resolve_passphrase(spec, "wrong horse").unwrap(), key);
+
+        let other_salt = "salt=0f0e0d0c0b0a09080706050403020100,m=64,t=1,p=1";
+        assert_ne!(
+            resolve_passphrase(other_salt, "correct horse battery staple").unwrap(),
+            key
+        );
+    }
+
+    #[test]
+    fn test_passphrase_resolver_rejects_bad_specs() {
+        assert!(resolve_passphrase("m=64,t=1,p=1", "pass").is_err());
+        assert!(resolve_passphrase("salt=zz,m=64", "pass").is_err());
+        assert!(resolve_passphrase("salt=00112233445566778899aabbccddeeff,m=1", "pass").is_err());
+        assert!(resolve_passphrase("salt=00112233445566778899aabbccddeeff,x=1", "pass").is_err());
+        assert!(resolve_passphrase("salt=00112233445566778899aabbccddeeff,m=64,t=1", "").is_err());
+
+        let source = PassphraseResolver::new_source();
+        let spec = source.strip_prefix("passphrase:").unwrap();
+        let (params, salt, fd) = PassphraseResolver::new(spec).parse().unwrap();
+        assert_eq!(salt.len(), KDF_SALT_LEN);
+        assert_eq!(params.m_cost(), DEFAULT_KDF_MEMORY_KIB);
+        assert_eq!(fd, None);
+    }
+
+    #[test]
+    fn test_private_key_resolver() {
+        let identity = PrivateKeyResolver::generate();
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "{}", identity.to_uppercase()).unwrap();
+        let source = format!("file:{}", file.path().display());
+        assert_eq!(PrivateKeyResolver::new(source).resolve().unwrap(), identity);
+
+        let recipient = PrivateKeyResolver::recipient(&identity).unwrap();
+        assert_eq!(recipient.len(), 64);
+        assert_ne!(recipient, identity);
+        assert_ne!(identity, PrivateKeyResolver::generate());
+
+        assert!(PrivateKeyResolver::new("00112233").resolve().is_err());
+        assert!(
+            PrivateKeyResolver::new("env:PRIVATE_KEY_TEST_UNSET")
+                .resolve()
+                .is_err()
+        );
+    }
+
     #[test]
     fn test_get_resolver_factory() {
         let env_resolver = get_resolver("env:MY_VAR");
@@ -223,5 +793,95 @@ mod tests {
 
         let vault_resolver = get_resolver("vault:secret/data/myapp:api_key");
         assert!(vault_resolver.resolve().is_err());
+
+        let pkcs11_resolver = get_resolver("pkcs11:module=/nonexistent.so,slot=0,key=k,pin=1");
+        assert!(pkcs11_resolver.resolve().is_err());
+
+        let keyring_resolver = get_resolver("keyring:@x:eightyeightyeight-test");
+        assert!(keyring_resolver.resolve().is_err());
+    }
+
+    #[test]
+    fn test_credential_resolver() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+        fs::write(dir.path().join("video-key"), format!("{}\n", key)).unwrap();
+        fs::write(dir.path().join("raw-key"), [0xab; 32]).unwrap();
+        fs::write(dir.path().join("garbage"), [0x00, 0xff, 0x01]).unwrap();
+
+        let resolve = |name: &str| get_resolver(&format!("credential:{}", name)).resolve();
+        unsafe {
+            std::env::remove_var("CREDENTIALS_DIRECTORY");
+        }
+        let err = resolve("video-key").unwrap_err();
+        assert!(format!("{:#}", err).contains("LoadCredentialEncrypted=video-key"));
+
+        unsafe {
+            std::env::set_var("CREDENTIALS_DIRECTORY", dir.path());
+        }
+        assert_eq!(resolve("video-key").unwrap(), key);
+        assert_eq!(resolve("raw-key").unwrap(), "ab".repeat(32));
+        assert!(resolve("garbage").is_err());
+        assert!(resolve("missing").is_err());
+        assert!(resolve("../video-key").is_err());
+        assert!(resolve("").is_err());
+        unsafe {
+            std::env::remove_var("CREDENTIALS_DIRECTORY");
+        }
+    }
+
+    #[test]
+    fn test_secret_debug_is_redacted() {
+        let secret = Secret::from("00112233445566778899aabbccddeeff");
+        assert_eq!(format!("{:?}", secret), "[REDACTED]");
+        assert_eq!(secret, "00112233445566778899aabbccddeeff");
+    }
+
+    #[test]
+    fn test_kernel_keyring_resolver() {
+        let add = |description: &str, payload: &[u8]| {
+            let description = std::ffi::CString::new(description).unwrap();
+            let id = unsafe {
+                libc::syscall(
+                    libc::SYS_add_key,
+                    c"user".as_ptr(),
+                    description.as_ptr(),
+                    payload.as_ptr(),
+                    payload.len(),
+                    libc::KEY_SPEC_PROCESS_KEYRING,
+                )
+            };
+            (id >= 0)
+                .then_some(())
+                .ok_or_else(std::io::Error::last_os_error)
+        };
+
+        let hex = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+        let description = format!("eightyeightyeight-test-{}", std::process::id());
+        let resolve = |source: &str| get_resolver(&format!("keyring:{}", source)).resolve();
+        match add(&description, hex.as_bytes()) {
+            Ok(()) => {}
+            // Containers often block the keyring; lookups must say so.
+            Err(e) if matches!(e.raw_os_error(), Some(libc::ENOSYS) | Some(libc::EPERM)) => {
+                let err = resolve(&format!("@p:{}", description)).unwrap_err();
+                assert!(
+                    format!("{:#}", err).contains("the keyring is not available here"),
+                    "{:#}",
+                    err
+                );
+                return;
+            }
+            Err(e) => panic!("add_key failed: {}", e),
+        }
+        let raw = format!("{}-raw", description);
+        add(&raw, &[0xab; 16]).unwrap();
+
+        assert_eq!(resolve(&format!("@p:{}", description)).unwrap(), hex);
+        assert_eq!(resolve(&description).unwrap(), hex);
+        assert_eq!(resolve(&raw).unwrap(), "ab".repeat(16));
+
+        let err = resolve("@p:eightyeightyeight-missing").unwrap_err();
+        assert!(format!("{:#}", err).contains("keyctl padd"));
+        assert!(resolve("@p:").is_err());
     }
 }
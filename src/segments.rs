@@ -0,0 +1,221 @@
+//! Time-based segment naming and retention for `record`.
+//!
+//! With `segment_duration_secs` set, `splitmuxsink` asks for every file name
+//! through its `format-location-full` signal. Segments are named after the
+//! UTC capture time of their first frame, e.g. `cam0_20261018T120000Z.ts.enc` for
+//! `output_path = "cam0.ts.enc"`, so they sort chronologically by name.
+//! splitmuxsink's own `max-files` only wraps the `%05d` counter and does
+//! nothing for these names, so retention is handled here as well.
+
+use anyhow::Result;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+pub struct SegmentNamer {
+    dir: PathBuf,
+    /// File name up to the first '.', e.g. "cam0".
+    prefix: String,
+    /// Remaining extensions including the leading '.', e.g. ".ts.enc".
+    suffix: String,
+    max_files: Option<u32>,
+}
+
+impl SegmentNamer {
+    pub fn new(output_path: &Path, max_files: Option<u32>) -> Self {
+        let dir = output_path
+            .parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."))
+            .to_path_buf();
+        let name = output_path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let (prefix, suffix) = match name.find('.') {
+            Some(i) => (name[..i].to_string(), name[i..].to_string()),
+            None => (name, String::new()),
+        };
+        Self {
+            dir,
+            prefix,
+            suffix,
+            max_files,
+        }
+    }
+
+    pub fn path_for(&self, start: DateTime<Utc>) -> PathBuf {
+        self.dir.join(format!(
+            "{}_{}{}",
+            self.prefix,
+            start.format(TIMESTAMP_FORMAT),
+            self.suffix
+        ))
+    }
+
+    /// Path for a segment whose first frame was captured at `start`. Never
+    /// returns an existing file, so a segment starting within the same second
+    /// as an earlier one (after a restart, say) cannot overwrite it.
+    pub fn path_at(&self, mut start: DateTime<Utc>) -> PathBuf {
+        loop {
+            let path = self.path_for(start);
+            if !path.exists() {
+                return path;
+            }
+            start += chrono::Duration::seconds(1);
+        }
+    }
+
+    /// Start time of a segment written by this namer, or `None` for any
+    /// other file.
+    pub fn parse(&self, path: &Path) -> Option<DateTime<Utc>> {
+        let name = path.file_name()?.to_str()?;
+        let timestamp = name
+            .strip_prefix(&self.prefix)?
+            .strip_prefix('_')?
+            .strip_suffix(&self.suffix)?;
+        NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT)
+            .ok()
+            .map(|t| t.and_utc())
+    }
+
+    /// Existing segments in the output directory, oldest first.
+    pub fn segments(&self) -> Result<Vec<PathBuf>> {
+        let mut segments: Vec<(DateTime<Utc>, PathBuf)> = std::fs::read_dir(&self.dir)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter_map(|path| self.parse(&path).map(|start| (start, path)))
+            .collect();
+        segments.sort();
+        Ok(segments.into_iter().map(|(_, path)| path).collect())
+    }
+
+    /// Deletes the oldest segments so that at most `max_files` remain once
+    /// the segment about to be opened is created.
+    pub fn enforce_retention(&self) -> Result<()> {
+        let Some(max_files) = self.max_files.filter(|&n| n > 0) else {
+            return Ok(());
+        };
+        let segments = self.segments()?;
+        let excess = (segments.len() + 1).saturating_sub(max_files as usize);
+        for path in segments.iter().take(excess) {
+            std::fs::remove_file(path)?;
+            tracing::info!("Deleted old segment: {}", path.display());
+        }
+        Ok(())
+    }
+}
+
+/// Start time in the name of a time-rotated segment, whatever its prefix,
+/// e.g. 12:00:00 for `cam0_20261018T120000Z.ts.enc`.
+pub fn parse_start_time(path: &Path) -> Option<DateTime<Utc>> {
+    let name = path.file_name()?.to_str()?;
+    let (_, timestamp) = name.split('.').next()?.rsplit_once('_')?;
+    NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT)
+        .ok()
+        .map(|t| t.and_utc())
+}
+
+/// Time from `now` until the next multiple of `period` since the Unix epoch,
+/// e.g. the top of the next minute for a 60 second period.
+pub fn until_next_boundary(now: DateTime<Utc>, period: Duration) -> Duration {
+    let period_ns = period.as_nanos().max(1);
+    let now_ns = now.timestamp_nanos_opt().unwrap_or_default() as u128;
+    Duration::from_nanos((period_ns - now_ns % period_ns) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_path_for() {
+        let namer = SegmentNamer::new(Path::new("/var/rec/cam0.ts.enc"), None);
+        let start = Utc.with_ymd_and_hms(2026, 10, 18, 12, 0, 0).unwrap();
+        assert_eq!(
+            namer.path_for(start),
+            PathBuf::from("/var/rec/cam0_20261018T120000Z.ts.enc")
+        );
+
+        let namer = SegmentNamer::new(Path::new("cam0.ts.enc"), None);
+        assert_eq!(
+            namer.path_for(start),
+            PathBuf::from("./cam0_20261018T120000Z.ts.enc")
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        let namer = SegmentNamer::new(Path::new("/var/rec/cam0.ts.enc"), None);
+        let start = Utc.with_ymd_and_hms(2026, 10, 18, 12, 0, 0).unwrap();
+        assert_eq!(namer.parse(&namer.path_for(start)), Some(start));
+        assert_eq!(namer.parse(Path::new("cam0_00001.ts.enc")), None);
+        assert_eq!(namer.parse(Path::new("cam1_20261018T120000Z.ts.enc")), None);
+        assert_eq!(namer.parse(Path::new("cam0_20261018T120000Z.ts")), None);
+
+        assert_eq!(
+            parse_start_time(Path::new("/mnt/usb/front_door_20261018T120000Z.ts.enc")),
+            Some(start)
+        );
+        assert_eq!(parse_start_time(Path::new("cam0_00001.ts.enc")), None);
+    }
+
+    #[test]
+    fn test_until_next_boundary() {
+        let now = Utc
+            .with_ymd_and_hms(2026, 10, 18, 11, 59, 37)
+            .unwrap()
+            .checked_add_signed(chrono::Duration::milliseconds(250))
+            .unwrap();
+        assert_eq!(
+            until_next_boundary(now, Duration::from_secs(60)),
+            Duration::from_millis(22_750)
+        );
+        assert_eq!(
+            until_next_boundary(now, Duration::from_secs(3600)),
+            Duration::from_millis(22_750)
+        );
+
+        let on_boundary = Utc.with_ymd_and_hms(2026, 10, 18, 12, 0, 0).unwrap();
+        assert_eq!(
+            until_next_boundary(on_boundary, Duration::from_secs(60)),
+            Duration::from_secs(60)
+        );
+    }
+
+    #[test]
+    fn test_enforce_retention() {
+        let dir = tempfile::tempdir().unwrap();
+        let namer = SegmentNamer::new(&dir.path().join("cam0.ts.enc"), Some(3));
+        let start = Utc.with_ymd_and_hms(2026, 10, 18, 12, 0, 0).unwrap();
+        let paths: Vec<PathBuf> = (0..4)
+            .map(|i| namer.path_for(start + chrono::Duration::minutes(i)))
+            .collect();
+        for path in &paths {
+            std::fs::write(path, b"segment").unwrap();
+        }
+        let unrelated = dir.path().join("notes.txt");
+        std::fs::write(&unrelated, b"keep").unwrap();
+
+        namer.enforce_retention().unwrap();
+
+        // Room is left for the segment about to be opened.
+        assert_eq!(namer.segments().unwrap(), paths[2..]);
+        assert!(unrelated.exists());
+    }
+
+    #[test]
+    fn test_path_at_does_not_overwrite() {
+        let dir = tempfile::tempdir().unwrap();
+        let namer = SegmentNamer::new(&dir.path().join("cam0.ts.enc"), None);
+        let start = Utc.with_ymd_and_hms(2026, 10, 18, 12, 0, 0).unwrap();
+        let first = namer.path_at(start);
+        assert_eq!(first, namer.path_for(start));
+        std::fs::write(&first, b"segment").unwrap();
+        let second = namer.path_at(start + chrono::Duration::milliseconds(400));
+        assert_ne!(first, second);
+        assert!(namer.parse(&second).unwrap() > namer.parse(&first).unwrap());
+    }
+}
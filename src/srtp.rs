@@ -0,0 +1,402 @@
+//! SRTP parameters and keying for the standard-RTP streaming transport.
+//!
+//! Every run of the sender generates a random 30-byte master key and salt for
+//! `AES_CM_128_HMAC_SHA1_80` and a random SSRC, so no two sessions share a
+//! keystream. The profile is the one every SRTP stack supports, which keeps
+//! third-party receivers working; they get the key out of band from the SDP
+//! `a=crypto` line. Our own receiver learns it from key announcements
+//! instead: the sender seals the SSRC, the rollover counter and the master
+//! key with the configured key in the `packet` format and sends them to the
+//! stream's port + 1 every `ANNOUNCE_INTERVAL`, so both ends still only need
+//! the usual `key` from the config.
+
+use crate::container;
+use crate::packet::{Opener, Sealer};
+use anyhow::{Context, Result};
+use base64::Engine;
+use hkdf::Hkdf;
+use rand::RngCore;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+use zeroize::{Zeroize, Zeroizing};
+
+pub const PAYLOAD_TYPE: u8 = 96;
+pub const CIPHER: &str = "aes-128-icm";
+pub const AUTH: &str = "hmac-sha1-80";
+/// How often the sender repeats its key announcement.
+pub const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(1);
+
+const MASTER_LEN: usize = 30;
+/// `ssrc u32 | roc u32 | master key and salt`
+const ANNOUNCEMENT_LEN: usize = 4 + 4 + MASTER_LEN;
+const HKDF_INFO: &[u8] = b"eightyeightyeight srtp key announcement";
+/// How long a receiver keeps the key of an SSRC that is no longer announced.
+const KEY_LIFETIME: Duration = Duration::from_secs(30);
+
+#[derive(Clone)]
+pub struct MasterKey([u8; MASTER_LEN]);
+
+impl MasterKey {
+    pub fn generate() -> Self {
+        let mut key = [0u8; MASTER_LEN];
+        rand::rng().fill_bytes(&mut key);
+        Self(key)
+    }
+
+    /// Base64 form used by SDP `a=crypto` lines (RFC 4568).
+    pub fn to_base64(&self) -> String {
+        base64::engine::general_purpose::STANDARD.encode(self.0)
+    }
+}
+
+/// Raw form for the `srtpenc` key property and `srtp-key` caps field. A
+/// `gst::Buffer` made from the key itself wipes it once GStreamer frees it.
+impl AsRef<[u8]> for MasterKey {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Drop for MasterKey {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+/// Keying of one sending session.
+pub struct Session {
+    pub ssrc: u32,
+    pub master: MasterKey,
+}
+
+impl Session {
+    pub fn generate() -> Self {
+        Self {
+            ssrc: rand::rng().next_u32(),
+            master: MasterKey::generate(),
+        }
+    }
+
+    /// SDP description of the stream for standard receivers such as ffplay.
+    pub fn sdp(&self, dest: &str, port: u16) -> String {
+        let family = if dest.contains(':') { "IP6" } else { "IP4" };
+        format!(
+            "v=0\r\n\
+             o=- 0 0 IN {family} {dest}\r\n\
+             s=eightyeightyeight\r\n\
+             c=IN {family} {dest}\r\n\
+             t=0 0\r\n\
+             m=video {port} RTP/SAVP {pt}\r\n\
+             a=rtpmap:{pt} H264/90000\r\n\
+             a=fmtp:{pt} packetization-mode=1\r\n\
+             a=crypto:1 AES_CM_128_HMAC_SHA1_80 inline:{key}\r\n\
+             a=ssrc:{ssrc} cname:eightyeightyeight\r\n",
+            pt = PAYLOAD_TYPE,
+            key = self.master.to_base64(),
+            ssrc = self.ssrc,
+        )
+    }
+
+    /// Writes the SDP of the session. It contains the master key, so the
+    /// file is only readable by the owner.
+    pub fn write_sdp(&self, dest: &str, port: u16, path: &Path) -> Result<()> {
+        use std::io::Write;
+        use std::os::unix::fs::OpenOptionsExt;
+
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)
+            .with_context(|| format!("Failed to create {}", path.display()))?
+            .write_all(self.sdp(dest, port).as_bytes())?;
+        info!("Wrote SDP to {}", path.display());
+        Ok(())
+    }
+}
+
+/// Port the key announcements of a stream on `port` are sent to.
+fn announce_port(port: u16) -> Result<u16> {
+    port.checked_add(1)
+        .context("SRTP streams need the port after the stream port for their keys")
+}
+
+/// Key the announcements are sealed with, kept apart from the key of the
+/// AEAD transport although both come from the configured key.
+fn announcement_key(key: &str) -> Result<Zeroizing<Vec<u8>>> {
+    let ikm = container::parse_key(key)?;
+    let mut okm = Zeroizing::new(vec![0u8; ikm.len()]);
+    Hkdf::<Sha256>::new(None, &ikm)
+        .expand(HKDF_INFO, &mut okm)
+        .expect("16 or 32 bytes is a valid HKDF-SHA256 output length");
+    Ok(okm)
+}
+
+fn resolve(host: &str, port: u16) -> Result<SocketAddr> {
+    (host, port)
+        .to_socket_addrs()
+        .with_context(|| format!("Failed to resolve {}", host))?
+        .next()
+        .with_context(|| format!("No address for {}", host))
+}
+
+/// Sends the key announcements of a session.
+pub struct Announcer {
+    socket: UdpSocket,
+    dest: SocketAddr,
+    sealer: Sealer,
+}
+
+impl Announcer {
+    /// Announces to the receiver of a stream sent to `dest`:`port`.
+    pub fn new(key: &str, dest: &str, port: u16) -> Result<Self> {
+        let dest = resolve(dest, announce_port(port)?)?;
+        let any = match dest {
+            SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        Ok(Self {
+            socket: UdpSocket::bind((any, 0)).context("Failed to open announcement socket")?,
+            dest,
+            sealer: Sealer::new(&announcement_key(key)?)?,
+        })
+    }
+
+    /// Sends the session's key with `roc`, the sender's current rollover
+    /// counter, so receivers joining after the sequence number wrapped can
+    /// still decrypt.
+    pub fn announce(&mut self, session: &Session, roc: u32) -> Result<()> {
+        let mut announcement = Zeroizing::new(Vec::with_capacity(ANNOUNCEMENT_LEN));
+        announcement.extend_from_slice(&session.ssrc.to_be_bytes());
+        announcement.extend_from_slice(&roc.to_be_bytes());
+        announcement.extend_from_slice(session.master.as_ref());
+        self.socket
+            .send_to(&self.sealer.seal(&announcement), self.dest)
+            .context("Failed to send key announcement")?;
+        Ok(())
+    }
+}
+
+/// Announced keys by SSRC, with their rollover counter and when they were
+/// last announced.
+type Keys = (Mutex<HashMap<u32, (u32, MasterKey, Instant)>>, Condvar);
+
+/// Collects the keys announced to a receiver, by SSRC. Keys that have not
+/// been announced for `KEY_LIFETIME` are forgotten.
+pub struct KeyListener {
+    keys: Arc<Keys>,
+}
+
+impl KeyListener {
+    /// Listens for the announcements of a stream received on `listen`:`port`.
+    pub fn bind(key: &str, listen: &str, port: u16) -> Result<Self> {
+        let addr = resolve(listen, announce_port(port)?)?;
+        let socket = match addr.ip() {
+            IpAddr::V4(ip) if ip.is_multicast() => {
+                let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, addr.port()))?;
+                socket.join_multicast_v4(&ip, &Ipv4Addr::UNSPECIFIED)?;
+                socket
+            }
+            IpAddr::V6(ip) if ip.is_multicast() => {
+                let socket = UdpSocket::bind((Ipv6Addr::UNSPECIFIED, addr.port()))?;
+                socket.join_multicast_v6(&ip, 0)?;
+                socket
+            }
+            _ => UdpSocket::bind(addr)
+                .with_context(|| format!("Failed to listen for SRTP keys on {}", addr))?,
+        };
+        let mut opener = Opener::new(&announcement_key(key)?)?;
+
+        let keys = Arc::new(Keys::default());
+        let shared = Arc::clone(&keys);
+        thread::spawn(move || {
+            let mut buf = [0u8; 1500];
+            loop {
+                let (len, from) = match socket.recv_from(&mut buf) {
+                    Ok(received) => received,
+                    Err(e) => {
+                        warn!("Key announcement socket failed: {}", e);
+                        return;
+                    }
+                };
+                let announcement = match opener.open(&buf[..len]) {
+                    Ok(announcement) if announcement.len() == ANNOUNCEMENT_LEN => {
+                        Zeroizing::new(announcement)
+                    }
+                    Ok(_) => {
+                        warn!("Dropping malformed key announcement from {}", from);
+                        continue;
+                    }
+                    Err(e) => {
+                        warn!("Dropping key announcement from {}: {}", from, e);
+                        continue;
+                    }
+                };
+                let ssrc = u32::from_be_bytes(announcement[..4].try_into().unwrap());
+                let roc = u32::from_be_bytes(announcement[4..8].try_into().unwrap());
+                let master = MasterKey(announcement[8..].try_into().unwrap());
+
+                let (keys, received) = &*shared;
+                let mut keys = keys.lock().unwrap();
+                keys.retain(|_, (_, _, announced)| announced.elapsed() < KEY_LIFETIME);
+                if keys.insert(ssrc, (roc, master, Instant::now())).is_none() {
+                    info!("Received SRTP key for SSRC {:#010x}", ssrc);
+                }
+                received.notify_all();
+            }
+        });
+        Ok(Self { keys })
+    }
+
+    /// Waits up to `timeout` for the key of `ssrc` and returns it with the
+    /// last announced rollover counter.
+    pub fn wait_for(&self, ssrc: u32, timeout: Duration) -> Option<(u32, MasterKey)> {
+        let (keys, received) = &*self.keys;
+        let (keys, _) = received
+            .wait_timeout_while(keys.lock().unwrap(), timeout, |keys| {
+                !keys.contains_key(&ssrc)
+            })
+            .unwrap();
+        keys.get(&ssrc)
+            .filter(|(_, _, announced)| announced.elapsed() < KEY_LIFETIME)
+            .map(|(roc, master, _)| (*roc, master.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+
+    #[test]
+    fn test_sessions_are_keyed_independently() {
+        let a = Session::generate();
+        let b = Session::generate();
+        assert_ne!(a.master.as_ref(), b.master.as_ref());
+        assert_ne!(a.ssrc, b.ssrc);
+        assert_eq!(a.master.as_ref().len(), MASTER_LEN);
+        assert_ne!(a.sdp("127.0.0.1", 9000), b.sdp("127.0.0.1", 9000));
+    }
+
+    #[test]
+    fn test_sdp() {
+        let session = Session::generate();
+        let sdp = session.sdp("192.168.1.10", 9000);
+        assert!(sdp.contains("c=IN IP4 192.168.1.10\r\n"));
+        assert!(sdp.contains("m=video 9000 RTP/SAVP 96\r\n"));
+        assert!(sdp.contains(&format!(
+            "a=crypto:1 AES_CM_128_HMAC_SHA1_80 inline:{}\r\n",
+            session.master.to_base64()
+        )));
+        assert!(sdp.contains(&format!("a=ssrc:{} ", session.ssrc)));
+        assert!(session.sdp("::1", 9000).contains("c=IN IP6 ::1\r\n"));
+    }
+
+    #[test]
+    fn test_key_announcement() {
+        // The listener takes the port after the stream port.
+        let port = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+            - 1;
+        let listener = KeyListener::bind(KEY, "127.0.0.1", port).unwrap();
+        let session = Session::generate();
+
+        // Announcements sealed with another key are ignored.
+        let mut stranger = Announcer::new(&"ff".repeat(32), "127.0.0.1", port).unwrap();
+        stranger.announce(&session, 0).unwrap();
+        assert!(
+            listener
+                .wait_for(session.ssrc, Duration::from_millis(200))
+                .is_none()
+        );
+
+        let mut announcer = Announcer::new(KEY, "127.0.0.1", port).unwrap();
+        announcer.announce(&session, 3).unwrap();
+        let (roc, master) = listener
+            .wait_for(session.ssrc, Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(roc, 3);
+        assert_eq!(master.as_ref(), session.master.as_ref());
+    }
+
+    #[test]
+    fn test_replayed_announcement_does_not_block_keys() {
+        // Capture an announcement of an earlier run.
+        let capture = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let capture_port = capture.local_addr().unwrap().port() - 1;
+        let old = Session::generate();
+        Announcer::new(KEY, "127.0.0.1", capture_port)
+            .unwrap()
+            .announce(&old, 0)
+            .unwrap();
+        let mut replay = [0u8; 1500];
+        let len = capture.recv(&mut replay).unwrap();
+
+        let port = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+            - 1;
+        let listener = KeyListener::bind(KEY, "127.0.0.1", port).unwrap();
+        let mut live = Announcer::new(KEY, "127.0.0.1", port).unwrap();
+        let session = Session::generate();
+        live.announce(&session, 0).unwrap();
+        assert!(
+            listener
+                .wait_for(session.ssrc, Duration::from_secs(5))
+                .is_some()
+        );
+
+        capture
+            .send_to(&replay[..len], ("127.0.0.1", port + 1))
+            .unwrap();
+        let rekeyed = Session::generate();
+        live.announce(&rekeyed, 0).unwrap();
+        assert!(
+            listener
+                .wait_for(rekeyed.ssrc, Duration::from_secs(5))
+                .is_some()
+        );
+        assert!(listener.wait_for(old.ssrc, Duration::ZERO).is_none());
+
+        // Keys no longer announced are dropped.
+        listener
+            .keys
+            .0
+            .lock()
+            .unwrap()
+            .get_mut(&session.ssrc)
+            .unwrap()
+            .2 -= KEY_LIFETIME;
+        assert!(listener.wait_for(session.ssrc, Duration::ZERO).is_none());
+        live.announce(&rekeyed, 0).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while listener.keys.0.lock().unwrap().contains_key(&session.ssrc) {
+            assert!(Instant::now() < deadline, "stale key was not evicted");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_announcement_key_is_not_the_key() {
+        let key = announcement_key(KEY).unwrap();
+        assert_eq!(key.len(), 32);
+        assert_ne!(
+            key.as_slice(),
+            container::parse_key(KEY).unwrap().as_slice()
+        );
+        assert!(announcement_key("not hex").is_err());
+    }
+}
@@ -0,0 +1,530 @@
+//! Minimal HashiCorp Vault client for the `vault:` key source.
+//!
+//! Only what key resolution needs: token, token file and AppRole logins, KV
+//! version 2 reads and Transit decryption. Connection settings come from the
+//! same environment variables as the `vault` CLI.
+
+use anyhow::{Context, Result};
+use base64::Engine;
+use serde_json::{Value, json};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_TRANSIT_MOUNT: &str = "transit";
+
+/// Where and how to connect, normally from the environment.
+#[derive(Debug, Default, Clone)]
+pub struct Settings {
+    /// `VAULT_ADDR`, e.g. "https://vault.example.com:8200".
+    pub addr: Option<String>,
+    /// `VAULT_CACERT`: PEM file with the CA certificates to trust instead
+    /// of the built-in roots.
+    pub ca_cert: Option<PathBuf>,
+    /// `VAULT_NAMESPACE` (Vault Enterprise).
+    pub namespace: Option<String>,
+    /// `VAULT_TOKEN`.
+    pub token: Option<String>,
+    /// `VAULT_TOKEN_FILE`, e.g. a Vault Agent sink, or `~/.vault-token`.
+    pub token_file: Option<PathBuf>,
+    /// `VAULT_ROLE_ID` and `VAULT_SECRET_ID` (or `VAULT_SECRET_ID_FILE`)
+    /// for an AppRole login.
+    pub role_id: Option<String>,
+    pub secret_id: Option<String>,
+    pub secret_id_file: Option<PathBuf>,
+}
+
+impl Settings {
+    pub fn from_env() -> Self {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+        let role_id = var("VAULT_ROLE_ID");
+        // Like the CLI, fall back to the token `vault login` leaves behind,
+        // unless an AppRole is configured.
+        let token_file = var("VAULT_TOKEN_FILE").map(PathBuf::from).or_else(|| {
+            let file = PathBuf::from(var("HOME")?).join(".vault-token");
+            (role_id.is_none() && file.exists()).then_some(file)
+        });
+        Self {
+            addr: var("VAULT_ADDR"),
+            ca_cert: var("VAULT_CACERT").map(PathBuf::from),
+            namespace: var("VAULT_NAMESPACE"),
+            token: var("VAULT_TOKEN"),
+            token_file,
+            role_id,
+            secret_id: var("VAULT_SECRET_ID"),
+            secret_id_file: var("VAULT_SECRET_ID_FILE").map(PathBuf::from),
+        }
+    }
+}
+
+/// A `vault:` key source: "PATH:FIELD[,mount=MOUNT][,transit=KEY][,transit_mount=MOUNT]".
+///
+/// `PATH` is the KV v2 secret as given to `vault kv get`; its first segment
+/// is the mount unless `mount` says otherwise. With `transit`, the field
+/// holds a Transit ciphertext (for example from `transit/datakey/wrapped`)
+/// that Vault decrypts with that key, and the plaintext bytes become the
+/// hex key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Source {
+    mount: String,
+    path: String,
+    field: String,
+    transit: Option<(String, String)>,
+}
+
+impl Source {
+    pub fn parse(source: &str) -> Result<Self> {
+        let (path, rest) = source
+            .split_once(':')
+            .context("Vault sources look like vault:PATH:FIELD")?;
+        let mut options = rest.split(',');
+        let field = options.next().unwrap_or_default().to_string();
+        let path = path.trim_matches('/');
+        if path.is_empty() || field.is_empty() {
+            anyhow::bail!("Vault sources look like vault:PATH:FIELD");
+        }
+
+        let mut mount = None;
+        let mut transit_key = None;
+        let mut transit_mount = DEFAULT_TRANSIT_MOUNT.to_string();
+        for option in options {
+            match option.split_once('=') {
+                Some(("mount", value)) => mount = Some(value.trim_matches('/').to_string()),
+                Some(("transit", value)) => transit_key = Some(value.to_string()),
+                Some(("transit_mount", value)) => {
+                    transit_mount = value.trim_matches('/').to_string()
+                }
+                _ => anyhow::bail!("Unknown Vault source option: {}", option),
+            }
+        }
+
+        let (mount, path) = match mount {
+            Some(mount) => {
+                let path = path.strip_prefix(&format!("{}/", mount)).unwrap_or(path);
+                (mount, path.to_string())
+            }
+            None => {
+                let (mount, path) = path
+                    .split_once('/')
+                    .context("Vault path needs a mount and a secret, e.g. secret/cam0")?;
+                (mount.to_string(), path.to_string())
+            }
+        };
+        // Also accept the API path, as printed by `vault kv get`.
+        let path = path.strip_prefix("data/").unwrap_or(&path).to_string();
+        Ok(Self {
+            mount,
+            path,
+            field,
+            transit: transit_key.map(|key| (transit_mount, key)),
+        })
+    }
+}
+
+pub struct Client {
+    agent: ureq::Agent,
+    addr: String,
+    namespace: Option<String>,
+    token: String,
+}
+
+impl Client {
+    /// Connects and logs in with, in order of preference, the token, the
+    /// token file or the AppRole.
+    pub fn connect(settings: &Settings) -> Result<Self> {
+        let addr = settings
+            .addr
+            .as_deref()
+            .context("VAULT_ADDR is not set")?
+            .trim_end_matches('/')
+            .to_string();
+        let mut client = Self {
+            agent: agent(settings.ca_cert.as_deref())?,
+            addr,
+            namespace: settings.namespace.clone(),
+            token: String::new(),
+        };
+
+        client.token = if let Some(token) = &settings.token {
+            token.clone()
+        } else if let Some(file) = &settings.token_file {
+            read_trimmed(file, "Vault token file")?
+        } else if let Some(role_id) = &settings.role_id {
+            let secret_id = match (&settings.secret_id, &settings.secret_id_file) {
+                (Some(secret_id), _) => secret_id.clone(),
+                (None, Some(file)) => read_trimmed(file, "Vault secret ID file")?,
+                (None, None) => anyhow::bail!(
+                    "VAULT_ROLE_ID is set but neither VAULT_SECRET_ID nor VAULT_SECRET_ID_FILE"
+                ),
+            };
+            client.approle_login(role_id, &secret_id)?
+        } else {
+            anyhow::bail!(
+                "No Vault credentials: set VAULT_TOKEN, VAULT_TOKEN_FILE, or VAULT_ROLE_ID and VAULT_SECRET_ID"
+            );
+        };
+        Ok(client)
+    }
+
+    fn approle_login(&self, role_id: &str, secret_id: &str) -> Result<String> {
+        let response = self.post(
+            "auth/approle/login",
+            &json!({ "role_id": role_id, "secret_id": secret_id }),
+        )?;
+        response["auth"]["client_token"]
+            .as_str()
+            .map(str::to_string)
+            .context("Vault AppRole login returned no client token")
+    }
+
+    /// Reads the key described by `source`.
+    pub fn fetch(&self, source: &Source) -> Result<String> {
+        let secret = self.get(&format!("{}/data/{}", source.mount, source.path))?;
+        let value = secret["data"]["data"][&source.field]
+            .as_str()
+            .with_context(|| {
+                format!(
+                    "Vault secret {}/{} has no string field {}",
+                    source.mount, source.path, source.field
+                )
+            })?;
+        match &source.transit {
+            Some((mount, key)) => self.transit_decrypt(mount, key, value),
+            None => Ok(value.trim().to_string()),
+        }
+    }
+
+    /// Decrypts a Transit ciphertext and returns the plaintext as hex.
+    fn transit_decrypt(&self, mount: &str, key: &str, ciphertext: &str) -> Result<String> {
+        let response = self.post(
+            &format!("{}/decrypt/{}", mount, key),
+            &json!({ "ciphertext": ciphertext.trim() }),
+        )?;
+        let plaintext = response["data"]["plaintext"]
+            .as_str()
+            .context("Vault Transit returned no plaintext")?;
+        let key = base64::engine::general_purpose::STANDARD
+            .decode(plaintext)
+            .context("Vault Transit plaintext is not base64")?;
+        Ok(hex::encode(key))
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/v1/{}", self.addr, path)
+    }
+
+    fn headers<B>(&self, mut request: ureq::RequestBuilder<B>) -> ureq::RequestBuilder<B> {
+        if !self.token.is_empty() {
+            request = request.header("X-Vault-Token", &self.token);
+        }
+        if let Some(namespace) = &self.namespace {
+            request = request.header("X-Vault-Namespace", namespace);
+        }
+        request
+    }
+
+    fn get(&self, path: &str) -> Result<Value> {
+        let response = self.headers(self.agent.get(&self.url(path))).call();
+        self.read(path, response)
+    }
+
+    fn post(&self, path: &str, body: &Value) -> Result<Value> {
+        let response = self
+            .headers(self.agent.post(&self.url(path)))
+            .send_json(body);
+        self.read(path, response)
+    }
+
+    fn read(
+        &self,
+        path: &str,
+        response: Result<ureq::http::Response<ureq::Body>, ureq::Error>,
+    ) -> Result<Value> {
+        let mut response = response.map_err(|e| {
+            let hint = match e {
+                ureq::Error::Tls(_) | ureq::Error::Rustls(_) => {
+                    " (for a private CA, set VAULT_CACERT)"
+                }
+                _ => "",
+            };
+            anyhow::anyhow!("Could not reach Vault at {}: {}{}", self.addr, e, hint)
+        })?;
+        let status = response.status().as_u16();
+        let body: Value = response.body_mut().read_json().unwrap_or(Value::Null);
+        if (200..300).contains(&status) {
+            return Ok(body);
+        }
+
+        let hint = match status {
+            400 => "bad request",
+            403 => "permission denied; check the token and its policy",
+            404 => "not found; check the mount and path",
+            503 => "Vault is sealed or unavailable",
+            _ => "unexpected response",
+        };
+        let errors: Vec<&str> = body["errors"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .filter(|e| !e.is_empty())
+            .collect();
+        let mut message = format!("Vault {} failed with HTTP {}: {}", path, status, hint);
+        if !errors.is_empty() {
+            message.push_str(&format!(" ({})", errors.join("; ")));
+        }
+        Err(anyhow::anyhow!(message))
+    }
+}
+
+fn agent(ca_cert: Option<&Path>) -> Result<ureq::Agent> {
+    let mut config = ureq::Agent::config_builder()
+        .http_status_as_error(false)
+        .timeout_global(Some(TIMEOUT));
+    if let Some(path) = ca_cert {
+        let pem = std::fs::read(path)
+            .with_context(|| format!("Failed to read VAULT_CACERT {}", path.display()))?;
+        let certs: Vec<_> = ureq::tls::parse_pem(&pem)
+            .filter_map(|item| match item {
+                Ok(ureq::tls::PemItem::Certificate(cert)) => Some(cert),
+                _ => None,
+            })
+            .collect();
+        if certs.is_empty() {
+            anyhow::bail!("VAULT_CACERT {} contains no certificates", path.display());
+        }
+        config = config.tls_config(
+            ureq::tls::TlsConfig::builder()
+                .root_certs(ureq::tls::RootCerts::new_with_certs(&certs))
+                .build(),
+        );
+    }
+    Ok(config.build().into())
+}
+
+fn read_trimmed(path: &Path, what: &str) -> Result<String> {
+    let value = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {} {}", what, path.display()))?;
+    Ok(value.trim().to_string())
+}
+
+/// Resolves a `vault:` source with settings from the environment.
+pub fn resolve(source: &str) -> Result<String> {
+    let source = Source::parse(source)?;
+    Client::connect(&Settings::from_env())?.fetch(&source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_http::mock_http;
+
+    const KEY: &str = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+
+    fn settings(addr: &str) -> Settings {
+        Settings {
+            addr: Some(addr.to_string()),
+            token: Some("s.token".to_string()),
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_source() {
+        let source = Source::parse("secret/cameras/cam0:key").unwrap();
+        assert_eq!(source.mount, "secret");
+        assert_eq!(source.path, "cameras/cam0");
+        assert_eq!(source.field, "key");
+        assert_eq!(source.transit, None);
+        assert_eq!(
+            Source::parse("secret/data/cameras/cam0:key").unwrap(),
+            source
+        );
+
+        let source =
+            Source::parse("kv/team/cam0:wrapped,mount=kv/team,transit=rec,transit_mount=tr")
+                .unwrap();
+        assert_eq!(source.mount, "kv/team");
+        assert_eq!(source.path, "cam0");
+        assert_eq!(source.transit, Some(("tr".to_string(), "rec".to_string())));
+
+        for bad in [
+            "secret/cam0",
+            "secret/cam0:",
+            "cam0:key",
+            "secret/cam0:key,ttl=1",
+        ] {
+            assert!(Source::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_fetch_kv_with_token() {
+        let (addr, requests) = mock_http(&[(
+            "GET /v1/secret/data/cameras/cam0",
+            200,
+            json!({ "data": { "data": { "key": KEY }, "metadata": { "version": 3 } } }),
+        )]);
+        let settings = Settings {
+            namespace: Some("ops".to_string()),
+            ..settings(&addr)
+        };
+        let client = Client::connect(&settings).unwrap();
+        let source = Source::parse("secret/cameras/cam0:key").unwrap();
+        assert_eq!(client.fetch(&source).unwrap(), KEY);
+        let err = client
+            .fetch(&Source::parse("secret/cameras/cam0:other").unwrap())
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("has no string field other"),
+            "{}",
+            err
+        );
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests[0].headers["x-vault-token"], "s.token");
+        assert_eq!(requests[0].headers["x-vault-namespace"], "ops");
+    }
+
+    #[test]
+    fn test_approle_login_and_transit() {
+        let data_key = [7u8; 32];
+        let (addr, requests) = mock_http(&[
+            (
+                "POST /v1/auth/approle/login",
+                200,
+                json!({ "auth": { "client_token": "s.approle" } }),
+            ),
+            (
+                "GET /v1/secret/data/cam0",
+                200,
+                json!({ "data": { "data": { "wrapped": "vault:v1:c2VhbGVk" } } }),
+            ),
+            (
+                "POST /v1/transit/decrypt/recorder",
+                200,
+                json!({ "data": {
+                    "plaintext": base64::engine::general_purpose::STANDARD.encode(data_key)
+                } }),
+            ),
+        ]);
+        let dir = tempfile::tempdir().unwrap();
+        let secret_id_file = dir.path().join("secret-id");
+        std::fs::write(&secret_id_file, "secret-123\n").unwrap();
+        let settings = Settings {
+            addr: Some(format!("{}/", addr)),
+            role_id: Some("role-abc".to_string()),
+            secret_id_file: Some(secret_id_file),
+            ..Settings::default()
+        };
+
+        let client = Client::connect(&settings).unwrap();
+        let source = Source::parse("secret/cam0:wrapped,transit=recorder").unwrap();
+        assert_eq!(client.fetch(&source).unwrap(), hex::encode(data_key));
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(
+            requests[0].body,
+            json!({ "role_id": "role-abc", "secret_id": "secret-123" })
+        );
+        assert!(!requests[0].headers.contains_key("x-vault-token"));
+        assert_eq!(requests[2].line, "POST /v1/transit/decrypt/recorder");
+        assert_eq!(requests[2].headers["x-vault-token"], "s.approle");
+        assert_eq!(
+            requests[2].body,
+            json!({ "ciphertext": "vault:v1:c2VhbGVk" })
+        );
+    }
+
+    #[test]
+    fn test_token_file() {
+        let (addr, requests) = mock_http(&[(
+            "GET /v1/secret/data/cam0",
+            200,
+            json!({ "data": { "data": { "key": KEY } } }),
+        )]);
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), "s.from-file\n").unwrap();
+        let settings = Settings {
+            addr: Some(addr),
+            token_file: Some(file.path().to_path_buf()),
+            role_id: Some("ignored".to_string()),
+            ..Settings::default()
+        };
+        let client = Client::connect(&settings).unwrap();
+        client
+            .fetch(&Source::parse("secret/cam0:key").unwrap())
+            .unwrap();
+        assert_eq!(
+            requests.lock().unwrap()[0].headers["x-vault-token"],
+            "s.from-file"
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let (addr, _) = mock_http(&[
+            (
+                "GET /v1/secret/data/denied",
+                403,
+                json!({ "errors": ["1 error occurred:\n\t* permission denied\n\n"] }),
+            ),
+            (
+                "GET /v1/secret/data/sealed",
+                503,
+                json!({ "errors": ["Vault is sealed"] }),
+            ),
+        ]);
+        let client = Client::connect(&settings(&addr)).unwrap();
+        let fetch = |path: &str| {
+            client
+                .fetch(&Source::parse(&format!("secret/{}:key", path)).unwrap())
+                .unwrap_err()
+                .to_string()
+        };
+        let err = fetch("denied");
+        assert!(err.contains("HTTP 403: permission denied"), "{}", err);
+        assert!(fetch("missing").contains("HTTP 404: not found"));
+        assert!(fetch("sealed").contains("(Vault is sealed)"));
+
+        let err = Client::connect(&Settings::default()).err().unwrap();
+        assert_eq!(err.to_string(), "VAULT_ADDR is not set");
+        let no_credentials = Settings {
+            addr: Some(addr.clone()),
+            ..Settings::default()
+        };
+        let err = Client::connect(&no_credentials).err().unwrap();
+        assert!(err.to_string().starts_with("No Vault credentials"));
+        let no_secret_id = Settings {
+            role_id: Some("role".to_string()),
+            ..no_credentials
+        };
+        assert!(Client::connect(&no_secret_id).is_err());
+
+        // Nothing listens on port 9 (discard) locally.
+        let client = Client::connect(&settings("http://127.0.0.1:9")).unwrap();
+        let err = client
+            .fetch(&Source::parse("secret/cam0:key").unwrap())
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("Could not reach Vault at http://127.0.0.1:9")
+        );
+    }
+
+    #[test]
+    fn test_ca_cert_must_hold_certificates() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), "not a certificate").unwrap();
+        let settings = Settings {
+            ca_cert: Some(file.path().to_path_buf()),
+            ..settings("https://vault.example.com:8200")
+        };
+        let err = Client::connect(&settings).err().unwrap();
+        assert!(
+            err.to_string().contains("contains no certificates"),
+            "{}",
+            err
+        );
+    }
+}
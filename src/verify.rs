@@ -0,0 +1,517 @@
+//! `verify`: checks recordings without displaying them.
+//!
+//! Every file gets two passes. The container pass reads each chunk with
+//! `ChunkReader` to confirm the key, find damaged chunks and where a
+//! truncated tail begins, and checks MPEG-TS sync and continuity counters in
+//! the decrypted bytes (`tsdemux` skips over those without saying so). The
+//! stream pass runs the playback chain into `fakesink` to count frames and
+//! keyframes. Any problem makes the command fail, so it can run from cron.
+
+use crate::config::Config;
+use crate::container::{self, ChunkReader, ContainerError, Format, Record};
+use crate::pipeline;
+use crate::playlist;
+use anyhow::Result;
+use gstreamer as gst;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+pub(crate) const TS_PACKET_LEN: usize = 188;
+pub(crate) const TS_SYNC_BYTE: u8 = 0x47;
+const TS_NULL_PID: u16 = 0x1fff;
+
+/// Errors listed per kind before the rest is summarized.
+const MAX_LISTED: usize = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContinuityError {
+    /// Offset of the packet in the decrypted stream.
+    pub offset: u64,
+    pub pid: u16,
+    pub expected: u8,
+    pub found: u8,
+}
+
+/// Checks MPEG-TS packet sync and per-PID continuity counters.
+#[derive(Default)]
+pub struct ContinuityChecker {
+    counters: HashMap<u16, u8>,
+    pending: Vec<u8>,
+    /// Stream offset of the first byte in `pending`.
+    offset: u64,
+    in_sync: bool,
+    pub packets: u64,
+    /// Offsets where packet sync was lost.
+    pub sync_losses: Vec<u64>,
+    pub errors: Vec<ContinuityError>,
+}
+
+impl ContinuityChecker {
+    pub fn feed(&mut self, data: &[u8]) {
+        self.pending.extend_from_slice(data);
+        let mut pos = 0;
+        while pos + TS_PACKET_LEN <= self.pending.len() {
+            // When searching for sync, a lone 0x47 in a payload must not
+            // count, so the next packet has to start with one as well.
+            let next_in_sync = self
+                .pending
+                .get(pos + TS_PACKET_LEN)
+                .is_none_or(|&b| b == TS_SYNC_BYTE);
+            if self.pending[pos] != TS_SYNC_BYTE || !self.in_sync && !next_in_sync {
+                if self.in_sync || self.packets == 0 && self.sync_losses.is_empty() {
+                    self.sync_losses.push(self.offset + pos as u64);
+                }
+                self.in_sync = false;
+                pos += 1;
+                continue;
+            }
+            self.in_sync = true;
+            let offset = self.offset + pos as u64;
+            let packet: [u8; 6] = self.pending[pos..pos + 6].try_into().unwrap();
+            self.check(&packet, offset);
+            pos += TS_PACKET_LEN;
+        }
+        self.pending.drain(..pos);
+        self.offset += pos as u64;
+    }
+
+    /// Skips `len` bytes that could not be read, e.g. a damaged chunk.
+    /// Counters restart after the gap, since packets are missing anyway.
+    pub fn skip(&mut self, len: u64) {
+        self.offset += self.pending.len() as u64 + len;
+        self.pending.clear();
+        self.counters.clear();
+        self.in_sync = false;
+    }
+
+    /// Checks the first bytes of a packet: header and adaptation flags.
+    fn check(&mut self, packet: &[u8; 6], offset: u64) {
+        self.packets += 1;
+        let pid = ((packet[1] & 0x1f) as u16) << 8 | packet[2] as u16;
+        if pid == TS_NULL_PID {
+            return;
+        }
+        let adaptation = packet[3] & 0x20 != 0;
+        let payload = packet[3] & 0x10 != 0;
+        let counter = packet[3] & 0x0f;
+        let discontinuity = adaptation && packet[4] > 0 && packet[5] & 0x80 != 0;
+
+        // The counter only advances with payload.
+        if !payload {
+            return;
+        }
+        if let Some(previous) = self.counters.insert(pid, counter)
+            && !discontinuity
+        {
+            let expected = (previous + 1) & 0x0f;
+            // A single repeated packet is allowed.
+            if counter != expected && counter != previous {
+                self.errors.push(ContinuityError {
+                    offset,
+                    pid,
+                    expected,
+                    found: counter,
+                });
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ContainerReport {
+    /// At least one chunk authenticated, so the key is right.
+    pub key_ok: bool,
+    pub chunks: u32,
+    pub authenticated_bytes: u64,
+    pub corrupt_chunks: Vec<u32>,
+    /// Chunk index and file offset where an unauthenticated tail begins.
+    pub truncated_at: Option<(u32, u64)>,
+    pub ts: ContinuityChecker,
+}
+
+/// Reads every chunk of an AEAD recording.
+pub fn check_container(path: &Path, key: &str) -> Result<ContainerReport> {
+    let key = container::parse_key(key)?;
+    let reader = match ChunkReader::new(BufReader::new(File::open(path)?), &key) {
+        Ok(reader) => reader,
+        // Nothing authenticates without the data key.
+        Err(e) if e.downcast_ref() == Some(&ContainerError::KeyUnwrap) => {
+            return Ok(ContainerReport::default());
+        }
+        Err(e) => return Err(e),
+    };
+    let header_len = reader.header().encoded_len();
+    let chunk_size = reader.header().chunk_size as u64;
+    let record_size = reader.header().record_size();
+
+    let mut report = ContainerReport::default();
+    for record in reader {
+        match record? {
+            Record::Chunk { plaintext, .. } => {
+                report.key_ok = true;
+                report.chunks += 1;
+                report.authenticated_bytes += plaintext.len() as u64;
+                report.ts.feed(&plaintext);
+            }
+            Record::Corrupt { index } => {
+                report.chunks += 1;
+                report.corrupt_chunks.push(index);
+                report.ts.skip(chunk_size);
+            }
+            Record::Truncated { index, .. } => {
+                report.truncated_at = Some((index, header_len + index as u64 * record_size));
+            }
+        }
+    }
+    Ok(report)
+}
+
+impl ContainerReport {
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if !self.key_ok {
+            problems.push("no chunk authenticates: wrong key or the file is destroyed".to_string());
+            return problems;
+        }
+        if !self.corrupt_chunks.is_empty() {
+            problems.push(format!(
+                "{} chunk(s) failed authentication (tampered or damaged): {}",
+                self.corrupt_chunks.len(),
+                list(&self.corrupt_chunks)
+            ));
+        }
+        if let Some((index, offset)) = self.truncated_at {
+            problems.push(format!(
+                "truncated: final chunk missing or incomplete, unauthenticated tail begins at chunk {} (file offset {})",
+                index, offset
+            ));
+        }
+        if !self.ts.sync_losses.is_empty() {
+            problems.push(format!(
+                "MPEG-TS sync lost {} time(s) at stream offset {}",
+                self.ts.sync_losses.len(),
+                list(&self.ts.sync_losses)
+            ));
+        }
+        if !self.ts.errors.is_empty() {
+            let errors: Vec<String> = self
+                .ts
+                .errors
+                .iter()
+                .map(|e| {
+                    format!(
+                        "PID {} at offset {} (expected {}, found {})",
+                        e.pid, e.offset, e.expected, e.found
+                    )
+                })
+                .collect();
+            problems.push(format!(
+                "{} MPEG-TS continuity error(s): {}",
+                errors.len(),
+                list(&errors)
+            ));
+        }
+        problems
+    }
+}
+
+/// What the stream pass saw at the parser output.
+#[derive(Default, Debug)]
+pub struct StreamStats {
+    pub frames: u64,
+    pub first_pts: Option<gst::ClockTime>,
+    /// End of the latest frame.
+    pub end_pts: Option<gst::ClockTime>,
+    pub keyframes: Vec<gst::ClockTime>,
+    /// Error that stopped the pipeline early.
+    pub error: Option<String>,
+}
+
+impl StreamStats {
+    pub fn duration(&self) -> Option<gst::ClockTime> {
+        self.end_pts?.checked_sub(self.first_pts?)
+    }
+
+    /// Shortest, longest and mean time between keyframes.
+    pub fn keyframe_intervals(&self) -> Option<(gst::ClockTime, gst::ClockTime, gst::ClockTime)> {
+        let intervals: Vec<gst::ClockTime> = self
+            .keyframes
+            .windows(2)
+            .filter_map(|pair| pair[1].checked_sub(pair[0]))
+            .collect();
+        let min = *intervals.iter().min()?;
+        let max = *intervals.iter().max()?;
+        let total: gst::ClockTime = intervals.iter().copied().sum();
+        Some((min, max, total / intervals.len() as u64))
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if let Some(error) = &self.error {
+            problems.push(format!("playback chain failed: {}", error));
+        }
+        if self.frames == 0 {
+            problems.push("no video frames".to_string());
+        } else if self.keyframes.is_empty() {
+            problems.push("no keyframes, the video cannot be decoded".to_string());
+        }
+        problems
+    }
+}
+
+fn list<T: std::fmt::Display>(items: &[T]) -> String {
+    let mut listed: Vec<String> = items
+        .iter()
+        .take(MAX_LISTED)
+        .map(|i| i.to_string())
+        .collect();
+    if items.len() > MAX_LISTED {
+        listed.push(format!("and {} more", items.len() - MAX_LISTED));
+    }
+    listed.join(", ")
+}
+
+struct FileReport {
+    path: PathBuf,
+    format: Format,
+    container: Option<ContainerReport>,
+    stream: Option<StreamStats>,
+    problems: Vec<String>,
+}
+
+fn verify_file(config: &Config, path: &Path) -> FileReport {
+    let mut report = FileReport {
+        path: path.to_path_buf(),
+        format: Format::Aead,
+        container: None,
+        stream: None,
+        problems: Vec::new(),
+    };
+    report.format = match container::detect_format(path) {
+        Ok(format) => format,
+        Err(e) => {
+            report.problems.push(e.to_string());
+            return report;
+        }
+    };
+
+    if report.format == Format::Aead {
+        let keys = config.keys();
+        let key = match container::select_key(path, &keys) {
+            Ok(key) => key,
+            Err(e) => {
+                report.problems.push(format!("{:#}", e));
+                return report;
+            }
+        };
+        match check_container(path, key) {
+            Ok(container) => {
+                report.problems.extend(container.problems());
+                let key_ok = container.key_ok;
+                report.container = Some(container);
+                if !key_ok {
+                    return report;
+                }
+            }
+            Err(e) => {
+                report.problems.push(format!("{:#}", e));
+                return report;
+            }
+        }
+    }
+
+    match pipeline::run_verify_pipeline(config, path, report.format) {
+        Ok(stream) => {
+            report.problems.extend(stream.problems());
+            report.stream = Some(stream);
+        }
+        Err(e) => report.problems.push(format!("{:#}", e)),
+    }
+    report
+}
+
+fn print_report(report: &FileReport) {
+    let status = if report.problems.is_empty() {
+        "OK"
+    } else {
+        "PROBLEMS"
+    };
+    println!("{}: {}", report.path.display(), status);
+
+    if report.format == Format::LegacyCbc {
+        println!("  legacy CBC recording: key and integrity cannot be checked");
+    }
+    if let Some(container) = &report.container
+        && container.key_ok
+    {
+        println!(
+            "  key ok, {} chunks, {} bytes authenticated",
+            container.chunks, container.authenticated_bytes
+        );
+        println!(
+            "  {} TS packets, {} continuity errors",
+            container.ts.packets,
+            container.ts.errors.len()
+        );
+    }
+    if let Some(stream) = &report.stream {
+        let duration = stream
+            .duration()
+            .map(|d| format!("{:.3}", d))
+            .unwrap_or_else(|| "unknown".to_string());
+        println!(
+            "  duration {}, {} frames, {} keyframes",
+            duration,
+            stream.frames,
+            stream.keyframes.len()
+        );
+        if let Some((min, max, mean)) = stream.keyframe_intervals() {
+            println!(
+                "  keyframe interval {:.3} (min {:.3}, max {:.3})",
+                mean, min, max
+            );
+        }
+    }
+    for problem in &report.problems {
+        println!("  ! {}", problem);
+    }
+}
+
+/// Verifies a file, a directory or a glob of recordings and fails if any of
+/// them has a problem.
+pub fn run(config: &Config, input: &str) -> Result<()> {
+    let files = playlist::expand(input)?;
+    if files.is_empty() {
+        anyhow::bail!("No recordings found for {}", input);
+    }
+
+    let mut failed = 0;
+    for path in &files {
+        let report = verify_file(config, path);
+        print_report(&report);
+        if !report.problems.is_empty() {
+            failed += 1;
+        }
+    }
+
+    if failed > 0 {
+        anyhow::bail!("{} of {} recording(s) have problems", failed, files.len());
+    }
+    println!("{} recording(s) verified", files.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const KEY: &str = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+
+    /// Payload-only packets on `pid` with counters starting at `first`.
+    fn packets(pid: u16, first: u8, count: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+        for i in 0..count {
+            let mut packet = [0xffu8; TS_PACKET_LEN];
+            packet[0] = TS_SYNC_BYTE;
+            packet[1] = (pid >> 8) as u8;
+            packet[2] = pid as u8;
+            packet[3] = 0x10 | ((first as usize + i) & 0x0f) as u8;
+            data.extend_from_slice(&packet);
+        }
+        data
+    }
+
+    #[test]
+    fn test_continuity_checker() {
+        let mut checker = ContinuityChecker::default();
+        let data = [packets(0x100, 0, 20), packets(0x101, 7, 20)].concat();
+        // Fed in odd pieces, as chunks do not end on packet boundaries.
+        for piece in data.chunks(1000) {
+            checker.feed(piece);
+        }
+        assert_eq!(checker.packets, 40);
+        assert!(checker.errors.is_empty());
+        assert!(checker.sync_losses.is_empty());
+
+        // A lost packet.
+        checker.feed(&packets(0x100, 5, 1));
+        assert_eq!(
+            checker.errors,
+            [ContinuityError {
+                offset: 40 * TS_PACKET_LEN as u64,
+                pid: 0x100,
+                expected: 4,
+                found: 5,
+            }]
+        );
+
+        // A discontinuity flagged in the adaptation field is fine.
+        let mut flagged = packets(0x100, 9, 1);
+        flagged[3] |= 0x20;
+        flagged[4] = 1;
+        flagged[5] = 0x80;
+        checker.feed(&flagged);
+        assert_eq!(checker.errors.len(), 1);
+
+        // Garbage between packets loses sync once, then recovers.
+        checker.feed(&[0u8; 50]);
+        checker.feed(&packets(0x100, 10, 2));
+        assert_eq!(checker.sync_losses, [42 * TS_PACKET_LEN as u64]);
+        assert_eq!(checker.errors.len(), 1);
+    }
+
+    fn write_recording(path: &Path, data: &[u8]) {
+        let key = container::parse_key(KEY).unwrap();
+        let mut writer =
+            container::Writer::with_chunk_size(File::create(path).unwrap(), &key, 4096).unwrap();
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn test_check_container() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cam0.ts.enc");
+        let data = packets(0x100, 0, 200);
+        write_recording(&path, &data);
+
+        let report = check_container(&path, KEY).unwrap();
+        assert!(report.key_ok);
+        assert_eq!(report.authenticated_bytes, data.len() as u64);
+        assert_eq!(report.ts.packets, 200);
+        assert!(report.problems().is_empty());
+
+        let report = check_container(&path, &"ff".repeat(32)).unwrap();
+        assert!(!report.key_ok);
+        assert!(report.problems()[0].contains("wrong key"));
+    }
+
+    #[test]
+    fn test_check_container_finds_damage_and_truncation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cam0.ts.enc");
+        write_recording(&path, &packets(0x100, 0, 200));
+
+        let mut bytes = std::fs::read(&path).unwrap();
+        let header_len = container::Header::read_from(&mut &bytes[..])
+            .unwrap()
+            .encoded_len() as usize;
+        bytes[header_len + 4112 + 100] ^= 0x01;
+        bytes.truncate(bytes.len() - 20);
+        std::fs::write(&path, &bytes).unwrap();
+
+        let report = check_container(&path, KEY).unwrap();
+        assert_eq!(report.corrupt_chunks, [1]);
+        assert_eq!(
+            report.truncated_at,
+            Some((9, (header_len + 9 * 4112) as u64))
+        );
+        // Counters restart after the damaged chunk, so it does not count
+        // as a continuity error on top.
+        assert!(report.ts.errors.is_empty());
+        assert_eq!(report.problems().len(), 2);
+    }
+}
@@ -1,10 +1,13 @@
-use crate::config::Config;
+use crate::config::{Config, Device, Fraction};
+use crate::secrets::PassphraseResolver;
 use anyhow::{Context, Result};
 use gstreamer as gst;
 use gstreamer::prelude::*;
-use inquire::{Confirm, CustomType, Select, Text};
+use inquire::validator::Validation;
+use inquire::{Confirm, CustomType, CustomUserError, Select, Text};
 use rand::RngCore;
 use std::collections::HashSet;
+use std::num::NonZeroU32;
 use std::path::PathBuf;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -34,28 +37,49 @@ pub fn run(output_path: String) -> Result<()> {
     let (device, width, height, framerate) = select_device_and_mode()?;
 
     // 3. Bitrate
-    let bitrate = CustomType::<u32>::new("Bitrate (kbps):")
-        .with_default(1000)
-        .with_error_message("Please enter a valid number")
+    let bitrate = CustomType::<NonZeroU32>::new("Bitrate (kbps):")
+        .with_default(NonZeroU32::new(1000).unwrap())
+        .with_error_message("Please enter a number greater than 0")
         .prompt()?;
 
     // 4. Key Generation
-    let key = if Confirm::new("Generate a new secure key?")
-        .with_default(true)
+    const GENERATE: &str = "Generate a new secure key";
+    const PASSPHRASE: &str = "Derive the key from a passphrase (asked for on every start)";
+    const EXISTING: &str = "Enter an existing key";
+    let key_val = match Select::new("Encryption key:", vec![GENERATE, PASSPHRASE, EXISTING])
         .prompt()?
     {
-        generate_key()
-    } else {
-        Text::new("Enter existing key (hex):").prompt()?
+        GENERATE => format!("literal:{}", generate_key()),
+        PASSPHRASE => {
+            // Only the salt and parameters are stored. Asking twice catches
+            // typos that would otherwise lock the footage away.
+            let source = PassphraseResolver::new_source();
+            let passphrase = inquire::Password::new("Passphrase:")
+                .with_custom_confirmation_message("Passphrase (again):")
+                .prompt()?;
+            let spec = source.strip_prefix("passphrase:").unwrap_or_default();
+            let key = crate::container::parse_key(
+                PassphraseResolver::new(spec).derive(&passphrase)?.expose(),
+            )?;
+            println!(
+                "Key ID {}. The passphrase is not saved; without it the recordings cannot be decrypted.",
+                crate::container::KeyId::of(&key)
+            );
+            source
+        }
+        _ => {
+            let key = Text::new("Enter existing key (hex):").prompt()?;
+            if ["literal:", "env:", "file:", "passphrase:"]
+                .iter()
+                .any(|prefix| key.starts_with(prefix))
+            {
+                key
+            } else {
+                format!("literal:{}", key)
+            }
+        }
     };
 
-    let key_val =
-        if key.starts_with("literal:") || key.starts_with("env:") || key.starts_with("file:") {
-            key
-        } else {
-            format!("literal:{}", key)
-        };
-
     // 5. Output Path
     let output_file = Text::new("Output file path:")
         .with_default("output.ts.enc")
@@ -67,20 +91,36 @@ pub fn run(output_path: String) -> Result<()> {
         .prompt()?;
 
     let config = Config {
-        device,
-        width,
-        height,
-        framerate,
+        device: device.parse().map_err(anyhow::Error::msg)?,
+        width: NonZeroU32::new(width).context("Width must be greater than 0")?,
+        height: NonZeroU32::new(height).context("Height must be greater than 0")?,
+        framerate: framerate.parse().map_err(anyhow::Error::msg)?,
         bitrate,
-        key: key_val,
+        key: Default::default(),
+        keyring: Vec::new(),
+        recipient: None,
+        identity: None,
         output_path: PathBuf::from(output_file),
         cv_enabled,
         min_disk_space_mb: None, // defaults
         max_files: None,
         max_file_size_mb: None,
+        segment_duration_secs: None,
+        metrics_listen: None,
+        rtsp_username: None,
+        rtsp_password: None,
+        stream_dest: None,
+        stream_port: None,
+        stream_transport: None,
+        receive_listen: None,
+        rtsp_listen: None,
+        rtsp_port: None,
     };
 
-    let toml_string = toml::to_string_pretty(&config)?;
+    // Secrets are not serialized, so the key source is added by hand.
+    let mut table = toml::Table::try_from(&config)?;
+    table.insert("key".to_string(), key_val.into());
+    let toml_string = toml::to_string_pretty(&table)?;
     std::fs::write(&output_path, toml_string)?;
 
     println!("Configuration saved to {}", output_path);
@@ -231,27 +271,40 @@ fn select_device_and_mode() -> Result<(String, u32, u32, String)> {
     ))
 }
 
+/// Checks a prompt answer the way the config loader will read it, so a bad
+/// value is caught at its prompt rather than after all of them.
+fn parses_as<T: std::str::FromStr<Err = String>>(
+    input: &str,
+) -> Result<Validation, CustomUserError> {
+    Ok(match input.parse::<T>() {
+        Ok(_) => Validation::Valid,
+        Err(e) => Validation::Invalid(e.into()),
+    })
+}
+
 fn manual_entry() -> Result<(String, u32, u32, String)> {
     let device = Text::new("Device path:")
         .with_default("/dev/video0")
+        .with_validator(parses_as::<Device>)
         .prompt()?;
     manual_mode_entry(device)
 }
 
 fn manual_mode_entry(device: String) -> Result<(String, u32, u32, String)> {
-    let width = CustomType::<u32>::new("Width:")
-        .with_default(640)
-        .with_error_message("Please enter a valid number")
+    let width = CustomType::<NonZeroU32>::new("Width:")
+        .with_default(NonZeroU32::new(640).unwrap())
+        .with_error_message("Please enter a number greater than 0")
         .prompt()?;
-    let height = CustomType::<u32>::new("Height:")
-        .with_default(480)
-        .with_error_message("Please enter a valid number")
+    let height = CustomType::<NonZeroU32>::new("Height:")
+        .with_default(NonZeroU32::new(480).unwrap())
+        .with_error_message("Please enter a number greater than 0")
         .prompt()?;
     let framerate = Text::new("Framerate (e.g. 30/1):")
         .with_default("30/1")
+        .with_validator(parses_as::<Fraction>)
         .prompt()?;
 
-    Ok((device, width, height, framerate))
+    Ok((device, width.get(), height.get(), framerate))
 }
 
 fn generate_key() -> String {